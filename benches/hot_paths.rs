@@ -0,0 +1,57 @@
+use duino_miner::hasher::Sha1Hasher;
+use duino_miner::protocol::Job;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sha1::{Digest, Sha1};
+
+const LAST_BLOCK_HASH: &str = "ba29a15896fd2d792d5c4b60668bf2b9feebc51d";
+
+/// Roughly the difficulties the pool hands out to each kind of device.
+const DIFFICULTIES: [(&str, u64); 3] = [("AVR", 6), ("ESP", 1500), ("PC", 10000)];
+
+fn expected_hash(nonce: u64) -> String {
+    hex::encode(Sha1::digest(
+        format!("{}{}", LAST_BLOCK_HASH, nonce).as_bytes(),
+    ))
+}
+
+fn single_hash(c: &mut Criterion) {
+    let hasher = Sha1Hasher::new();
+    let expected = expected_hash(0);
+
+    c.bench_function("single_hash", |b| {
+        b.iter(|| hasher.get_hash(black_box(LAST_BLOCK_HASH), black_box(&expected), 1))
+    });
+}
+
+/// Searches whole jobs, solved by their last nonce.
+fn search(c: &mut Criterion) {
+    let hasher = Sha1Hasher::new();
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+
+    for (device_type, difficulty) in DIFFICULTIES {
+        let max_nonce = difficulty * 100 + 1;
+        let expected = expected_hash(max_nonce - 1);
+
+        group.throughput(Throughput::Elements(max_nonce));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(device_type),
+            &max_nonce,
+            |b, &max_nonce| b.iter(|| hasher.get_hash(LAST_BLOCK_HASH, &expected, max_nonce)),
+        );
+    }
+    group.finish();
+}
+
+fn parse_job(c: &mut Criterion) {
+    let line = format!("{},{},10000\n", LAST_BLOCK_HASH, expected_hash(4242));
+    let mut group = c.benchmark_group("parse");
+
+    group.throughput(Throughput::Bytes(line.len() as u64));
+    group.bench_function("job", |b| b.iter(|| Job::parse(black_box(&line))));
+    group.finish();
+}
+
+criterion_group!(benches, single_hash, search, parse_job);
+criterion_main!(benches);
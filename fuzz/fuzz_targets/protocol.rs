@@ -0,0 +1,16 @@
+#![no_main]
+
+use duino_miner::protocol::{Job, MessageReader, ShareResponse};
+
+use libfuzzer_sys::fuzz_target;
+
+// Feeds arbitrary pool traffic through message framing and both parsers.
+fuzz_target!(|data: &[u8]| {
+    let mut stream = data;
+    let mut reader = MessageReader::new(4096);
+
+    while let Ok(message) = reader.read(&mut stream) {
+        let _ = Job::parse(message);
+        let _ = ShareResponse::parse(message);
+    }
+});
@@ -0,0 +1,35 @@
+#ifndef DUINO_MINER_H
+#define DUINO_MINER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+enum duino_miner_event_kind {
+    DUINO_MINER_CONNECTED = 0,
+    DUINO_MINER_DISCONNECTED = 1,
+    DUINO_MINER_REJECTED = 2,
+    DUINO_MINER_BLOCK_FOUND = 3,
+    DUINO_MINER_SAFETY_BRAKE = 4,
+    DUINO_MINER_GAVE_UP = 5,
+    DUINO_MINER_DAILY_REPORT = 6,
+    DUINO_MINER_TOO_SLOW = 7,
+};
+
+typedef void (*duino_miner_event_callback)(const char *device, int kind,
+                                           const char *message, void *user_data);
+
+/* Registers a callback for miner events, or unregisters it when NULL. */
+void duino_miner_set_event_callback(duino_miner_event_callback callback, void *user_data);
+
+/* Starts mining with a JSON config. Returns 0 on success, -1 on error. */
+int duino_miner_start(const char *config_json);
+
+/* Stops the miner and waits for it to exit. Returns 0 on success. */
+int duino_miner_stop(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,627 @@
+use crate::error::MinerError;
+use crate::throttle::Throttle;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeSet;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+/// Pool balancer queried for the pool to mine on.
+pub const POOL_URL: &str = "http://51.15.127.80:4242/getPool";
+/// Root of the Duino REST API.
+pub const DEFAULT_BASE_URL: &str = "https://server.duinocoin.com";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_IDLE_CONNECTIONS_PER_HOST: usize = 4;
+
+/// Attempts made for a request before giving up.
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, doubled for each one after.
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Requests per second allowed across every user of a client.
+pub const DEFAULT_REQUESTS_PER_SEC: u64 = 2;
+/// How long a fetched DUCO price is used before it is fetched again.
+pub const DEFAULT_PRICE_REFRESH: Duration = Duration::from_secs(300);
+
+static SHARED: OnceLock<Client> = OnceLock::new();
+static PRICES: OnceLock<PriceOracle> = OnceLock::new();
+
+/// Client for the Duino web APIs. Connections are kept alive between
+/// requests, failed requests are retried with backoff, and every request
+/// counts against one rate limit.
+pub struct Client {
+    agent: ureq::Agent,
+    base_url: String,
+    attempts: u32,
+    retry_delay: Duration,
+    limit: Throttle,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_ATTEMPTS,
+            DEFAULT_RETRY_DELAY,
+            DEFAULT_REQUESTS_PER_SEC,
+        )
+    }
+}
+
+/// Whether a failed request may succeed if sent again.
+fn is_retryable(e: &ureq::Error) -> bool {
+    match e {
+        ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+        ureq::Error::Transport(_) => true,
+    }
+}
+
+impl Client {
+    pub fn new(attempts: u32, retry_delay: Duration, requests_per_sec: u64) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(TIMEOUT)
+                .max_idle_connections_per_host(MAX_IDLE_CONNECTIONS_PER_HOST)
+                .build(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            attempts: attempts.max(1),
+            retry_delay,
+            limit: Throttle::new(requests_per_sec),
+        }
+    }
+
+    /// Sends REST API requests to `base_url` instead of the official server.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The client shared by the whole process.
+    pub fn shared() -> &'static Client {
+        SHARED.get_or_init(Client::default)
+    }
+
+    /// Fetches `url` and parses the body as JSON.
+    pub fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, MinerError> {
+        self.get_json_with(url, &[])
+    }
+
+    /// Fetches `url` with `query` appended. Query values are kept out of
+    /// logs and errors, so they may hold secrets.
+    fn get_json_with<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, MinerError> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+
+        loop {
+            self.limit.acquire(1);
+
+            let request = query
+                .iter()
+                .fold(self.agent.get(url), |request, (param, value)| {
+                    request.query(param, value)
+                });
+
+            match request.call() {
+                Ok(response) => {
+                    return response
+                        .into_json()
+                        .map_err(|e| MinerError::Api(format!("{}: {}", url, e)))
+                }
+                Err(e) if attempt < self.attempts && is_retryable(&e) => {
+                    warn!("{} failed: {}, retrying in {:?}", url, e, delay);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(ureq::Error::Status(status, _)) => {
+                    return Err(MinerError::Api(format!("{} returned {}", url, status)))
+                }
+                Err(e) => return Err(MinerError::Api(format!("{}: {}", url, e.kind()))),
+            }
+        }
+    }
+
+    /// Fetches `path` from the REST API and unwraps its `result`.
+    fn get_result<T: DeserializeOwned>(&self, path: &str) -> Result<T, MinerError> {
+        self.get_result_with(path, &[])
+    }
+
+    fn get_result_with<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, MinerError> {
+        let url = format!("{}{}", self.base_url, path);
+        let envelope: Envelope<T> = self.get_json_with(&url, query)?;
+
+        match envelope {
+            Envelope {
+                success: true,
+                result: Some(result),
+                ..
+            } => Ok(result),
+            Envelope { message, .. } => Err(MinerError::Api(
+                message.unwrap_or_else(|| format!("{}: no result", url)),
+            )),
+        }
+    }
+
+    /// Asks the pool balancer which pool to mine on.
+    pub fn get_pool(&self) -> Result<PoolInfo, MinerError> {
+        self.get_json(POOL_URL)
+    }
+
+    /// Every pool the API lists, healthy or not.
+    pub fn get_pools(&self) -> Result<Vec<PoolInfo>, MinerError> {
+        self.get_result("/all_pools")
+    }
+
+    pub fn get_balance(&self, username: &str) -> Result<Balance, MinerError> {
+        self.get_result(&format!("/balances/{}", username))
+    }
+
+    /// Miners currently submitting shares for `username`.
+    pub fn get_miners(&self, username: &str) -> Result<Vec<MinerInfo>, MinerError> {
+        self.get_result(&format!("/miners/{}", username))
+    }
+
+    /// Checks `credentials` against the pool and returns a session for the
+    /// account's authenticated endpoints.
+    pub fn login(&self, credentials: Credentials) -> Result<Session<'_>, MinerError> {
+        let _: serde_json::Value = self.get_result_with(
+            &format!("/v2/auth/{}", credentials.username),
+            &[credentials.query()],
+        )?;
+
+        Ok(Session {
+            client: self,
+            credentials,
+        })
+    }
+
+    /// Transactions sent or received by `username`, newest last.
+    pub fn get_transactions(&self, username: &str) -> Result<Vec<Transaction>, MinerError> {
+        self.get_result(&format!("/user_transactions/{}", username))
+    }
+}
+
+/// A document the DUCO price in USD is read from, at `pointer`, such as
+/// `/Duco price`. The price may be a number or a string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceSource {
+    pub url: String,
+    pub pointer: String,
+}
+
+impl PriceSource {
+    /// The price the Duino API publishes.
+    pub fn duino() -> Self {
+        Self {
+            url: format!("{}/api.json", DEFAULT_BASE_URL),
+            pointer: "/Duco price".to_string(),
+        }
+    }
+
+    fn read(&self, document: &serde_json::Value) -> Option<f64> {
+        let price = document.pointer(&self.pointer)?;
+        price
+            .as_f64()
+            .or_else(|| price.as_str().and_then(|s| s.trim().parse().ok()))
+    }
+}
+
+/// The DUCO price in USD, taken from the first of its sources that answers
+/// and cached for a while, so every consumer shares one price.
+pub struct PriceOracle {
+    sources: Vec<PriceSource>,
+    refresh: Duration,
+    cached: Mutex<Option<(Instant, f64)>>,
+}
+
+impl Default for PriceOracle {
+    fn default() -> Self {
+        Self::new(vec![PriceSource::duino()], DEFAULT_PRICE_REFRESH)
+    }
+}
+
+impl PriceOracle {
+    pub fn new(sources: Vec<PriceSource>, refresh: Duration) -> Self {
+        Self {
+            sources,
+            refresh,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Makes `oracle` the one shared by the whole process. Returns false if
+    /// the shared oracle was already set or used.
+    pub fn set_shared(oracle: PriceOracle) -> bool {
+        PRICES.set(oracle).is_ok()
+    }
+
+    /// The oracle shared by the whole process.
+    pub fn shared() -> &'static PriceOracle {
+        PRICES.get_or_init(PriceOracle::default)
+    }
+
+    fn fetch(&self, client: &Client) -> Result<f64, MinerError> {
+        let mut errors = Vec::new();
+        for source in self.sources.iter() {
+            match client.get_json::<serde_json::Value>(&source.url) {
+                Ok(document) => match source.read(&document) {
+                    Some(price) => return Ok(price),
+                    None => errors.push(format!("{}: no price at {}", source.url, source.pointer)),
+                },
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        Err(MinerError::Api(errors.join(", ")))
+    }
+
+    /// The price, fetched with `client` once the cached one is older than
+    /// the refresh interval. While no source answers, the last price fetched
+    /// is used however old it is.
+    pub fn price(&self, client: &Client) -> Option<f64> {
+        if let Some((at, price)) = *self.cached.lock().unwrap() {
+            if at.elapsed() < self.refresh {
+                return Some(price);
+            }
+        }
+
+        match self.fetch(client) {
+            Ok(price) => {
+                *self.cached.lock().unwrap() = Some((Instant::now(), price));
+                Some(price)
+            }
+            Err(e) => {
+                warn!("could not fetch the DUCO price: {}", e);
+                self.cached()
+            }
+        }
+    }
+
+    /// The last price fetched, without fetching.
+    pub fn cached(&self) -> Option<f64> {
+        self.cached.lock().unwrap().map(|(_, price)| price)
+    }
+}
+
+/// Environment variable holding the password of every account, unless
+/// overridden for an account by `DUINO_PASSWORD_<USERNAME>`.
+pub const PASSWORD_ENV: &str = "DUINO_PASSWORD";
+
+/// An account's password or mining key.
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub secret: Secret,
+}
+
+#[derive(Clone)]
+pub enum Secret {
+    Password(String),
+    MiningKey(String),
+}
+
+impl Credentials {
+    /// Reads the password of `username` from the environment.
+    pub fn from_env(username: &str) -> Option<Self> {
+        let account_env = format!("{}_{}", PASSWORD_ENV, username.to_uppercase());
+        let password = std::env::var(account_env)
+            .or_else(|_| std::env::var(PASSWORD_ENV))
+            .ok()?;
+
+        Some(Self {
+            username: username.to_string(),
+            secret: Secret::Password(password),
+        })
+    }
+
+    fn query(&self) -> (&str, &str) {
+        match &self.secret {
+            Secret::Password(password) => ("password", password),
+            Secret::MiningKey(key) => ("k", key),
+        }
+    }
+}
+
+/// A logged in account.
+pub struct Session<'a> {
+    client: &'a Client,
+    credentials: Credentials,
+}
+
+impl Session<'_> {
+    pub fn username(&self) -> &str {
+        &self.credentials.username
+    }
+
+    /// Miners the pool sees for this account, including ones the public
+    /// endpoint hides.
+    pub fn get_miners(&self) -> Result<Vec<MinerInfo>, MinerError> {
+        self.client.get_result_with(
+            &format!("/miners/{}", self.credentials.username),
+            &[self.credentials.query()],
+        )
+    }
+}
+
+/// Devices of an account compared with what the pool sees.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Visibility {
+    /// Local devices the pool reports.
+    pub visible: Vec<String>,
+    /// Local devices the pool does not report.
+    pub missing: Vec<String>,
+    /// Miners the pool reports that are not local devices.
+    pub unknown: Vec<String>,
+}
+
+impl Visibility {
+    /// Matches `local` device names against the identifiers of `pool`.
+    pub fn compare<'a>(local: impl IntoIterator<Item = &'a str>, pool: &[MinerInfo]) -> Self {
+        let seen: BTreeSet<&str> = pool.iter().map(|m| m.identifier.as_str()).collect();
+        let local: BTreeSet<&str> = local.into_iter().collect();
+
+        let (visible, missing) = local.iter().partition(|name| seen.contains(*name));
+
+        Self {
+            visible: to_strings(visible),
+            missing: to_strings(missing),
+            unknown: to_strings(seen.difference(&local).copied().collect()),
+        }
+    }
+}
+
+fn to_strings(names: Vec<&str>) -> Vec<String> {
+    names.into_iter().map(str::to_string).collect()
+}
+
+/// Wrapper around every REST API response.
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    result: Option<T>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// A pool handed out by the balancer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolInfo {
+    pub name: String,
+    pub ip: String,
+    pub port: u16,
+    #[serde(default)]
+    pub connections: u32,
+}
+
+impl PoolInfo {
+    /// Address to connect to, as `host:port`.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Balance {
+    pub username: String,
+    pub balance: f64,
+    #[serde(default)]
+    pub stake_amount: f64,
+}
+
+/// A miner as seen by the pool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinerInfo {
+    pub username: String,
+    pub identifier: String,
+    pub software: String,
+    pub hashrate: f64,
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Seconds the last share took.
+    pub sharetime: f64,
+    pub diff: u64,
+    #[serde(default)]
+    pub pool: String,
+    #[serde(default)]
+    pub algorithm: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub id: u64,
+    pub hash: String,
+    pub sender: String,
+    pub recipient: String,
+    pub amount: f64,
+    pub datetime: String,
+    #[serde(default)]
+    pub memo: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serves one canned response per connection, in order.
+    fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        format!("http://{}/", addr)
+    }
+
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n42";
+
+    fn client(attempts: u32) -> Client {
+        Client::new(attempts, Duration::from_millis(1), 1000)
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let url = serve(vec![UNAVAILABLE, OK]);
+
+        assert_eq!(client(2).get_json::<u32>(&url).unwrap(), 42);
+    }
+
+    #[test]
+    fn gives_up_after_attempts() {
+        let url = serve(vec![UNAVAILABLE, UNAVAILABLE]);
+
+        assert!(client(2).get_json::<u32>(&url).is_err());
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let url = serve(vec![NOT_FOUND, OK]);
+
+        assert!(client(2).get_json::<u32>(&url).is_err());
+    }
+
+    fn respond(body: &str) -> &'static str {
+        Box::leak(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .into_boxed_str(),
+        )
+    }
+
+    #[test]
+    fn unwraps_result() {
+        let url = serve(vec![respond(
+            r#"{"success":true,"result":{"username":"alice","balance":12.5}}"#,
+        )]);
+        let balance = client(1).with_base_url(&url).get_balance("alice").unwrap();
+
+        assert_eq!(balance.username, "alice");
+        assert_eq!(balance.balance, 12.5);
+    }
+
+    #[test]
+    fn maps_failures_to_api_errors() {
+        let url = serve(vec![respond(
+            r#"{"success":false,"message":"This user doesn't exist"}"#,
+        )]);
+
+        match client(1).with_base_url(&url).get_miners("nobody") {
+            Err(MinerError::Api(message)) => assert_eq!(message, "This user doesn't exist"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn miner(identifier: &str) -> MinerInfo {
+        MinerInfo {
+            username: "alice".to_string(),
+            identifier: identifier.to_string(),
+            software: "Official AVR Miner v2.6".to_string(),
+            hashrate: 190.0,
+            accepted: 10,
+            rejected: 0,
+            sharetime: 5.0,
+            diff: 6,
+            pool: String::new(),
+            algorithm: String::new(),
+        }
+    }
+
+    #[test]
+    fn compares_local_devices_with_pool() {
+        let pool = vec![miner("avr-1"), miner("avr-1"), miner("rig")];
+        let visibility = Visibility::compare(vec!["avr-2", "avr-1"], &pool);
+
+        assert_eq!(visibility.visible, vec!["avr-1"]);
+        assert_eq!(visibility.missing, vec!["avr-2"]);
+        assert_eq!(visibility.unknown, vec!["rig"]);
+    }
+
+    fn oracle(urls: &[String], refresh: Duration) -> PriceOracle {
+        let sources = urls
+            .iter()
+            .map(|url| PriceSource {
+                url: url.clone(),
+                pointer: "/Duco price".to_string(),
+            })
+            .collect();
+
+        PriceOracle::new(sources, refresh)
+    }
+
+    #[test]
+    fn falls_back_to_next_price_source() {
+        let down = serve(vec![UNAVAILABLE]);
+        let up = serve(vec![respond(r#"{"Duco price":"0.0012"}"#)]);
+        let oracle = oracle(&[down, up], Duration::from_secs(60));
+
+        assert_eq!(oracle.price(&client(1)), Some(0.0012));
+        // Cached, so neither source is asked again.
+        assert_eq!(oracle.price(&client(1)), Some(0.0012));
+    }
+
+    #[test]
+    fn keeps_stale_price_while_sources_are_down() {
+        let url = serve(vec![respond(r#"{"Duco price":0.002}"#), UNAVAILABLE]);
+        let oracle = oracle(&[url], Duration::ZERO);
+
+        assert_eq!(oracle.price(&client(1)), Some(0.002));
+        assert_eq!(oracle.price(&client(1)), Some(0.002));
+        assert_eq!(oracle.cached(), Some(0.002));
+    }
+
+    #[test]
+    fn login_sends_secret_as_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).unwrap();
+            stream
+                .write_all(respond(r#"{"success":true,"result":{}}"#).as_bytes())
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+
+        let credentials = Credentials {
+            username: "alice".to_string(),
+            secret: Secret::Password("p&ss".to_string()),
+        };
+        let client = client(1).with_base_url(&url);
+        let session = client.login(credentials).unwrap();
+
+        assert_eq!(session.username(), "alice");
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /v2/auth/alice?password=p%26ss "));
+    }
+}
@@ -0,0 +1,54 @@
+use duino_miner::config::ServerConfig;
+
+use data_encoding::BASE64;
+
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    bearer: Option<String>,
+    basic: Option<String>,
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Auth {
+    pub fn new(config: &ServerConfig) -> Self {
+        let basic = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => Some(format!(
+                "Basic {}",
+                BASE64.encode(format!("{}:{}", username, password).as_bytes())
+            )),
+            _ => None,
+        };
+
+        Self {
+            bearer: config.token.as_ref().map(|t| format!("Bearer {}", t)),
+            basic,
+        }
+    }
+
+    /// An `Authorization` header value that passes [`Auth::check`], for
+    /// clients of the control API.
+    pub fn header(&self) -> Option<&str> {
+        self.bearer.as_deref().or(self.basic.as_deref())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.bearer.is_some() || self.basic.is_some()
+    }
+
+    /// Checks the value of an `Authorization` header against the configured
+    /// credentials. Always fails when no credentials are configured.
+    pub fn check(&self, authorization: Option<&str>) -> bool {
+        let authorization = match authorization {
+            Some(a) => a.trim().as_bytes(),
+            None => return false,
+        };
+
+        [&self.bearer, &self.basic]
+            .iter()
+            .filter_map(|expected| expected.as_ref())
+            .any(|expected| constant_time_eq(expected.as_bytes(), authorization))
+    }
+}
@@ -0,0 +1,154 @@
+//! Parks part of the fleet while the host is busy with other work,
+//! configured in the `autoscale` section of the config file.
+
+use duino_miner::config::AutoscaleConfig;
+use duino_miner::control::{DeviceControl, FleetControl};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+
+const LOADAVG: &str = "/proc/loadavg";
+
+/// The load average over the last minute, per CPU of the host.
+fn load_per_cpu() -> Option<f64> {
+    let loadavg = std::fs::read_to_string(LOADAVG).ok()?;
+    let load: f64 = loadavg.split_whitespace().next()?.parse().ok()?;
+    let cpus = std::thread::available_parallelism().ok()?.get();
+
+    Some(load / cpus as f64)
+}
+
+/// Devices parked out of `active` running ones, at least one.
+fn to_park(active: usize, fraction: f64) -> usize {
+    ((active as f64 * fraction).ceil() as usize).clamp(1, active.max(1))
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Park,
+    Resume,
+    Hold,
+}
+
+/// What to do at `load`, per CPU, with devices parked or not. The load has
+/// to fall well below where devices were parked before they are resumed, so
+/// the devices' own load does not make them come and go.
+fn decide(config: &AutoscaleConfig, load: f64, parked: bool) -> Action {
+    match parked {
+        false if load > config.high_load => Action::Park,
+        true if load < config.low_load => Action::Resume,
+        _ => Action::Hold,
+    }
+}
+
+pub struct Autoscaler {
+    config: AutoscaleConfig,
+}
+
+impl Autoscaler {
+    /// Checks that the host load can be read and the thresholds make sense,
+    /// so problems show at startup.
+    pub fn new(config: AutoscaleConfig) -> Result<Self, String> {
+        if config.low_load >= config.high_load {
+            return Err(format!(
+                "autoscale: low_load {} is not below high_load {}",
+                config.low_load, config.high_load
+            ));
+        }
+        if !(config.park_fraction > 0.0 && config.park_fraction <= 1.0) {
+            return Err(format!(
+                "autoscale: park_fraction {} is not within (0, 1]",
+                config.park_fraction
+            ));
+        }
+        if load_per_cpu().is_none() {
+            return Err(format!(
+                "autoscale: cannot read the host load from {}",
+                LOADAVG
+            ));
+        }
+
+        Ok(Self { config })
+    }
+
+    /// Checks the host load in the background, parking devices of `control`
+    /// when it is high and resuming them once it is low again. Only devices
+    /// parked here are resumed, so those paused otherwise stay paused.
+    pub fn start(self, control: Arc<FleetControl>) {
+        std::thread::spawn(move || {
+            let mut parked: Vec<Arc<DeviceControl>> = Vec::new();
+
+            loop {
+                std::thread::sleep(Duration::from_secs(self.config.check_secs));
+                if control.is_shutdown() {
+                    break;
+                }
+                let load = match load_per_cpu() {
+                    Some(load) => load,
+                    None => continue,
+                };
+
+                match decide(&self.config, load, !parked.is_empty()) {
+                    Action::Park => {
+                        let active: Vec<_> = control
+                            .devices()
+                            .iter()
+                            .filter(|d| !d.is_paused())
+                            .collect();
+                        if active.is_empty() {
+                            continue;
+                        }
+                        let count = to_park(active.len(), self.config.park_fraction);
+                        for device in active.into_iter().rev().take(count) {
+                            device.set_paused(true);
+                            parked.push(device.clone());
+                        }
+                        info!("host load {:.2} per CPU, parked {} devices", load, count);
+                    }
+                    Action::Resume => {
+                        let count = parked.len();
+                        for device in parked.drain(..) {
+                            device.set_paused(false);
+                        }
+                        info!("host load {:.2} per CPU, resumed {} devices", load, count);
+                    }
+                    Action::Hold => {}
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parks_a_fraction_of_active_devices() {
+        assert_eq!(to_park(10, 0.5), 5);
+        assert_eq!(to_park(3, 0.5), 2);
+        assert_eq!(to_park(3, 0.1), 1);
+        assert_eq!(to_park(3, 1.0), 3);
+    }
+
+    #[test]
+    fn resumes_only_once_load_is_low() {
+        let config = AutoscaleConfig::default();
+
+        assert_eq!(decide(&config, 0.95, false), Action::Park);
+        assert_eq!(decide(&config, 0.95, true), Action::Hold);
+        assert_eq!(decide(&config, 0.7, true), Action::Hold);
+        assert_eq!(decide(&config, 0.4, true), Action::Resume);
+        assert_eq!(decide(&config, 0.4, false), Action::Hold);
+    }
+
+    #[test]
+    fn rejects_inverted_thresholds() {
+        let config: AutoscaleConfig =
+            serde_yaml::from_str("high_load: 0.5\nlow_load: 0.8\n").unwrap();
+
+        assert!(Autoscaler::new(config).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::config::BrakeConfig;
+use crate::control::FleetControl;
+use crate::events::EventKind;
+use crate::stats::DeviceStats;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+/// Pauses every device of an account when several of them fail at once,
+/// which usually means the pool is banning or rate-limiting the account.
+pub struct SafetyBrake {
+    config: BrakeConfig,
+    control: Arc<FleetControl>,
+    /// Last failure of each failing device, by account. A device leaves the
+    /// map as soon as it has a share accepted.
+    failing: Mutex<HashMap<String, HashMap<String, Instant>>>,
+}
+
+impl SafetyBrake {
+    pub fn new(config: BrakeConfig, control: Arc<FleetControl>) -> Self {
+        Self {
+            config,
+            control,
+            failing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the outcome of a share or connection attempt. Returns how many
+    /// devices were failing when this outcome trips the brake.
+    fn note(&self, account: &str, device: &str, failed: bool, now: Instant) -> Option<usize> {
+        let mut failing = self.failing.lock().unwrap();
+
+        if !failed {
+            if let Some(devices) = failing.get_mut(account) {
+                devices.remove(device);
+            }
+            return None;
+        }
+
+        let window = Duration::from_secs(self.config.window_secs);
+        let devices = failing.entry(account.to_string()).or_default();
+        devices.insert(device.to_string(), now);
+        devices.retain(|_, last| now.saturating_duration_since(*last) <= window);
+
+        if devices.len() < self.config.min_devices.max(1) {
+            return None;
+        }
+
+        let count = devices.len();
+        devices.clear();
+        Some(count)
+    }
+
+    /// Records the outcome for `stats`' device, pausing its account if too
+    /// many of the account's devices are failing.
+    pub fn record(&self, account: &str, stats: &DeviceStats, failed: bool) {
+        if let Some(count) = self.note(account, stats.name(), failed, Instant::now()) {
+            let paused = self.control.pause_account(account);
+            let message = format!(
+                "{} devices of {} failed within {}s, paused {} devices",
+                count, account, self.config.window_secs, paused
+            );
+            error!("safety brake: {}", message);
+            stats.event(EventKind::SafetyBrake, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brake(min_devices: usize, window_secs: u64) -> SafetyBrake {
+        let config = BrakeConfig {
+            enabled: true,
+            min_devices,
+            window_secs,
+        };
+
+        SafetyBrake::new(config, Arc::new(FleetControl::new(vec![], Vec::new())))
+    }
+
+    #[test]
+    fn trips_when_enough_devices_fail() {
+        let brake = brake(3, 60);
+        let now = Instant::now();
+
+        assert_eq!(brake.note("alice", "avr-1", true, now), None);
+        assert_eq!(brake.note("alice", "avr-2", true, now), None);
+        assert_eq!(brake.note("alice", "avr-2", true, now), None);
+        assert_eq!(brake.note("alice", "avr-3", true, now), Some(3));
+    }
+
+    #[test]
+    fn success_clears_device() {
+        let brake = brake(2, 60);
+        let now = Instant::now();
+
+        brake.note("alice", "avr-1", true, now);
+        brake.note("alice", "avr-1", false, now);
+
+        assert_eq!(brake.note("alice", "avr-2", true, now), None);
+    }
+
+    #[test]
+    fn ignores_failures_outside_window() {
+        let brake = brake(2, 60);
+        let now = Instant::now();
+
+        brake.note("alice", "avr-1", true, now);
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(brake.note("alice", "avr-2", true, later), None);
+    }
+
+    #[test]
+    fn accounts_are_independent() {
+        let brake = brake(2, 60);
+        let now = Instant::now();
+
+        brake.note("alice", "avr-1", true, now);
+
+        assert_eq!(brake.note("bob", "avr-2", true, now), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! A corpus of the jobs and share responses the pool sent, for analysis
+//! rather than replay: unlike a transcript, nothing the devices sent is kept.
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Job,
+    /// The pool's answer to a share.
+    Response,
+}
+
+/// One message from the pool, as written to a capture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Captured {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub device: String,
+    pub kind: Kind,
+    pub data: String,
+}
+
+/// Appends the jobs and responses each device receives to a file, one JSON
+/// object per line.
+pub struct JobCapture {
+    file: Mutex<File>,
+}
+
+impl JobCapture {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, device: &str, kind: Kind, data: &str) -> std::io::Result<()> {
+        let captured = Captured {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            device: device.to_string(),
+            kind,
+            data: data.to_string(),
+        };
+        let mut line = serde_json::to_string(&captured)?;
+        line.push('\n');
+
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+/// Reads a capture file written by [`JobCapture`].
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Captured>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut captured = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            captured.push(serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_file() {
+        let path = std::env::temp_dir().join(format!("capture-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let capture = JobCapture::open(&path).unwrap();
+        capture.record("avr-1", Kind::Job, "aa,bb,6").unwrap();
+        capture.record("avr-1", Kind::Response, "GOOD").unwrap();
+
+        let captured = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let kinds: Vec<_> = captured.iter().map(|c| (c.kind, c.data.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![(Kind::Job, "aa,bb,6"), (Kind::Response, "GOOD")]
+        );
+        assert!(captured
+            .iter()
+            .all(|c| c.device == "avr-1" && c.timestamp > 0));
+    }
+}
@@ -0,0 +1,1435 @@
+use crate::api::{PriceSource, DEFAULT_PRICE_REFRESH};
+use crate::events::EventKind;
+use crate::transport::Transport;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+/// Schema version of config files written by this build. Bump it, and add a
+/// step to [`MIGRATIONS`], whenever a field is renamed or reshaped.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades of a parsed config file, one version each: step `i` turns a file
+/// of version `i` into one of version `i + 1`.
+const MIGRATIONS: [fn(&mut Mapping); CONFIG_VERSION as usize] = [
+    // Files from before versioning are version 0 and need no changes.
+    |_| {},
+];
+
+/// Upgrades a parsed config file to [`CONFIG_VERSION`] in place, returning
+/// the version it had.
+pub fn migrate(value: &mut Value) -> Result<u32, String> {
+    let config = match value {
+        Value::Mapping(config) => config,
+        // Not a config at all; leave the error to the parser.
+        _ => return Ok(CONFIG_VERSION),
+    };
+
+    let key = Value::String("version".to_string());
+    let version = match config.get(&key) {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("invalid config version: {:?}", version))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "config version {} is newer than this miner supports ({}); upgrade the miner",
+            version, CONFIG_VERSION
+        ));
+    }
+
+    for step in MIGRATIONS[version as usize..].iter() {
+        step(config);
+    }
+    config.insert(key, Value::Number(CONFIG_VERSION.into()));
+
+    Ok(version)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// Schema version the file was written for, see [`CONFIG_VERSION`].
+    #[serde(default)]
+    pub version: u32,
+    /// Further config files whose devices and sections are merged into this
+    /// one by [`Config::load`]. Paths are relative to this file, and `*` and
+    /// `?` match within a file name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<ServerConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety_brake: Option<BrakeConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_difficulty: Option<TunerConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_selection: Option<PoolSelectionConfig>,
+    /// Restart policy of devices that do not set their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<RestartPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slack: Option<SlackConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<EmailConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<ReportConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<PriceConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub too_slow: Option<SlowConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autoscale: Option<AutoscaleConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<LimitsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<KeepaliveConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// An empty config of the current schema version, to build generated
+/// configs from.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            include: Vec::new(),
+            devices: Vec::new(),
+            server: None,
+            safety_brake: None,
+            auto_difficulty: None,
+            pool_selection: None,
+            restart: None,
+            slack: None,
+            email: None,
+            report: None,
+            price: None,
+            too_slow: None,
+            autoscale: None,
+            limits: None,
+            keepalive: None,
+            webhooks: Vec::new(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub username: String,
+    pub device_name: String,
+    pub device_type: String,
+    pub chip_id: String,
+    pub firmware: String,
+    pub target_rate: u32,
+    /// Name of the entry in `profiles` describing this device's firmware.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Value of `{wallet_id}` in submission templates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet_id: Option<String>,
+    /// Value of `{mining_key}` in job request templates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mining_key: Option<String>,
+    /// Difficulty tier requested in job requests in place of `device_type`,
+    /// one of LOW, MEDIUM, NET or EXTREME for PC miners.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<String>,
+    /// Overrides the fleet's `restart` policy for this device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<RestartPolicy>,
+    /// Name of the group, such as a rack, the device is operated with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Transports tried in order until one connects, a direct connection if
+    /// empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<Transport>,
+    /// Namespace of the config file the device was read from, when several
+    /// were loaded with [`Config::load_all`].
+    #[serde(skip)]
+    pub namespace: Option<String>,
+}
+
+/// Tier requested by PC devices that do not set `difficulty`.
+pub const DEFAULT_PC_DIFFICULTY: &str = "MEDIUM";
+
+/// Prefix of selectors naming a group rather than a device.
+pub const GROUP_PREFIX: &str = "group:";
+
+impl Device {
+    /// `name` prefixed with the device's namespace, if it has one.
+    fn qualify(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}/{}", namespace, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// The device name within the fleet, as shown in stats and logs:
+    /// `device_name`, prefixed with the namespace as `namespace/device_name`
+    /// if there is one. The pool is only ever sent `device_name`.
+    pub fn name(&self) -> String {
+        self.qualify(&self.device_name)
+    }
+
+    /// Whether the device is picked by `selector`: its name, or `group:`
+    /// followed by its group, both with the namespace if there is one.
+    pub fn matches(&self, selector: &str) -> bool {
+        match selector.strip_prefix(GROUP_PREFIX) {
+            Some(group) => self.group.as_ref().map(|g| self.qualify(g)).as_deref() == Some(group),
+            None => self.name() == selector,
+        }
+    }
+
+    /// Difficulty tier to request jobs for. The pool has no PC tier, so PC
+    /// devices ask for [`DEFAULT_PC_DIFFICULTY`] unless told otherwise.
+    pub fn difficulty(&self) -> &str {
+        match &self.difficulty {
+            Some(difficulty) => difficulty,
+            None if self.device_type.eq_ignore_ascii_case("PC") => DEFAULT_PC_DIFFICULTY,
+            None => &self.device_type,
+        }
+    }
+}
+
+impl Config {
+    /// Parses a config, migrating it from older versions first.
+    pub fn from_yaml(c_serial: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut value: Value = serde_yaml::from_str(c_serial)?;
+        migrate(&mut value)?;
+
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    /// Reads `file_path` along with every file it includes. Errors name the
+    /// file they come from.
+    pub fn load(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = Path::new(file_path);
+        let mut c = Self::load_file(path)?;
+        let mut sources = Sources::new(path, &c)?;
+
+        for path in c.included_files(path)? {
+            let part = Self::load_file(&path)?;
+            if !part.include.is_empty() {
+                return Err(
+                    format!("{}: included files cannot include others", path.display()).into(),
+                );
+            }
+            sources.merge(&mut c, part, &path)?;
+        }
+        c.include.clear();
+
+        Ok(c)
+    }
+
+    /// Loads each of `file_paths` with [`Config::load`] into one fleet. With
+    /// several files, each file's devices are put in a namespace named after
+    /// the file, so device names need only be unique within a file. Sections
+    /// and profiles are merged as from `include`.
+    pub fn load_all(file_paths: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let (first, rest) = file_paths.split_first().ok_or("no config file given")?;
+        let mut c = Self::load(first)?;
+        if rest.is_empty() {
+            return Ok(c);
+        }
+
+        c.set_namespace(Path::new(first))?;
+        let mut sources = Sources::new(Path::new(first), &c)?;
+        for file_path in rest {
+            let mut part = Self::load(file_path)?;
+            part.set_namespace(Path::new(file_path))?;
+            sources.merge(&mut c, part, Path::new(file_path))?;
+        }
+
+        Ok(c)
+    }
+
+    /// Puts every device in the namespace named after the stem of
+    /// `file_path`.
+    fn set_namespace(&mut self, file_path: &Path) -> Result<(), String> {
+        let namespace = file_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| {
+                format!(
+                    "{}: no file name to name its namespace",
+                    file_path.display()
+                )
+            })?;
+        for device in self.devices.iter_mut() {
+            device.namespace = Some(namespace.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Files matched by `include`, for a config read from `file_path`.
+    pub fn included_files(&self, file_path: &Path) -> Result<Vec<PathBuf>, String> {
+        let dir = file_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut files = Vec::new();
+
+        for pattern in self.include.iter() {
+            let paths = expand(dir, pattern)
+                .map_err(|e| format!("{}: include {}: {}", file_path.display(), pattern, e))?;
+            if paths.is_empty() {
+                return Err(format!(
+                    "{}: include {} matches no files",
+                    file_path.display(),
+                    pattern
+                ));
+            }
+            files.extend(paths);
+        }
+
+        Ok(files)
+    }
+
+    /// Rewrites `file_path` in the current schema, keeping the original next
+    /// to it with a `.bak` extension. Returns the version the file had, or
+    /// `None` when it was already current and left alone.
+    pub fn migrate_file(file_path: &Path) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+        let (c_serial, encrypted) = read_file(file_path)?;
+        let mut value: Value = serde_yaml::from_str(&c_serial)?;
+
+        let version = migrate(&mut value)?;
+        if version == CONFIG_VERSION {
+            return Ok(None);
+        }
+
+        let mut backup = file_path.as_os_str().to_owned();
+        backup.push(".bak");
+        std::fs::copy(file_path, backup)?;
+        write_file(file_path, &serde_yaml::to_string(&value)?, encrypted)?;
+
+        Ok(Some(version))
+    }
+
+    /// Sets `field` of `device` to `value` in the file among `file_paths`,
+    /// or the files they include, that defines the device, keeping the
+    /// original next to it with a `.bak` extension. The file is rewritten
+    /// from its parsed values, so its comments and formatting are lost.
+    /// Returns the file written.
+    pub fn set_device_field(
+        file_paths: &[String],
+        device: &Device,
+        field: &str,
+        value: Value,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let file_path = match &device.namespace {
+            Some(namespace) => file_paths
+                .iter()
+                .map(Path::new)
+                .find(|path| path.file_stem().and_then(|s| s.to_str()) == Some(namespace.as_str())),
+            None => file_paths.first().map(Path::new),
+        }
+        .ok_or_else(|| format!("no config file defines {}", device.name()))?;
+
+        let mut paths = vec![file_path.to_path_buf()];
+        paths.extend(Self::load_file(file_path)?.included_files(file_path)?);
+        for path in paths {
+            let (c_serial, encrypted) = read_file(&path)?;
+            let mut file: Value = serde_yaml::from_str(&c_serial)?;
+
+            let entry = file
+                .get_mut("devices")
+                .and_then(Value::as_sequence_mut)
+                .and_then(|devices| {
+                    devices.iter_mut().find(|d| {
+                        d.get("device_name").and_then(Value::as_str)
+                            == Some(device.device_name.as_str())
+                    })
+                })
+                .and_then(Value::as_mapping_mut);
+            if let Some(entry) = entry {
+                entry.insert(Value::from(field), value);
+
+                let mut backup = path.as_os_str().to_owned();
+                backup.push(".bak");
+                std::fs::copy(&path, backup)?;
+                write_file(&path, &serde_yaml::to_string(&file)?, encrypted)?;
+                return Ok(path);
+            }
+        }
+
+        Err(format!("no config file defines {}", device.name()).into())
+    }
+
+    /// Keeps only the devices picked by one of `selectors`, see
+    /// [`Device::matches`]. Fails when a selector picks no device.
+    pub fn retain_selected(&mut self, selectors: &[String]) -> Result<(), String> {
+        for selector in selectors {
+            if !self.devices.iter().any(|d| d.matches(selector)) {
+                return Err(format!("no device matches {}", selector));
+            }
+        }
+        self.devices
+            .retain(|d| selectors.iter().any(|selector| d.matches(selector)));
+
+        Ok(())
+    }
+
+    /// Reads a single config file, leaving its `include` list unresolved.
+    pub fn load_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let (c_serial, _) = read_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self::from_yaml(c_serial.as_str()).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// Writes the config encrypted under `key`, see [`crate::encryption`].
+    #[cfg(feature = "encryption")]
+    pub fn save_encrypted(
+        &self,
+        file_path: &str,
+        key: &crate::encryption::Key,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let c_serial = serde_yaml::to_string(self)?;
+        create_private(Path::new(file_path))?
+            .write_all(crate::encryption::encrypt(&c_serial, key).as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let c_serial = serde_yaml::to_string(self)?;
+
+        let mut f = create_private(Path::new(file_path))?;
+        f.write_all(c_serial.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Creates or truncates a file that, when new, only its owner may read,
+/// since configs hold credentials.
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+/// Reads a config file, decrypting it with [`crate::encryption::Key::from_env`]
+/// if needed. Returns the contents and whether they were encrypted.
+fn read_file(path: &Path) -> Result<(String, bool), Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+
+    #[cfg(feature = "encryption")]
+    {
+        use crate::encryption::{self, Key};
+
+        if encryption::is_encrypted(&contents) {
+            return Ok((encryption::decrypt(&contents, &Key::from_env()?)?, true));
+        }
+    }
+    #[cfg(not(feature = "encryption"))]
+    {
+        if contents.starts_with("duino-miner encrypted config") {
+            return Err("the config is encrypted; build with the `encryption` feature".into());
+        }
+    }
+
+    Ok((contents, false))
+}
+
+/// Writes a config file, encrypted if `encrypted` is set.
+fn write_file(
+    path: &Path,
+    c_serial: &str,
+    encrypted: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "encryption")]
+    {
+        use crate::encryption::{self, Key};
+
+        if encrypted {
+            let sealed = encryption::encrypt(c_serial, &Key::from_env()?);
+            create_private(path)?.write_all(sealed.as_bytes())?;
+            return Ok(());
+        }
+    }
+    #[cfg(not(feature = "encryption"))]
+    let _ = encrypted;
+
+    create_private(path)?.write_all(c_serial.as_bytes())?;
+    Ok(())
+}
+
+/// Which file each device, profile and section of a merged config came from.
+struct Sources {
+    entries: BTreeMap<String, String>,
+}
+
+impl Sources {
+    fn new(path: &Path, c: &Config) -> Result<Self, String> {
+        let mut sources = Self {
+            entries: BTreeMap::new(),
+        };
+        sources.add_all(c, &path.display().to_string())?;
+
+        Ok(sources)
+    }
+
+    fn add(&mut self, kind: &str, name: &str, file: &str) -> Result<(), String> {
+        match self
+            .entries
+            .insert(format!("{} {}", kind, name), file.to_string())
+        {
+            Some(previous) if previous == file => {
+                Err(format!("{}: {} {} is defined twice", file, kind, name))
+            }
+            Some(previous) => Err(format!(
+                "{}: {} {} is already defined in {}",
+                file, kind, name, previous
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn add_all(&mut self, c: &Config, file: &str) -> Result<(), String> {
+        for device in c.devices.iter() {
+            self.add("device", &device.name(), file)?;
+        }
+        for name in c.profiles.keys() {
+            self.add("profile", name, file)?;
+        }
+        for (section, set) in [
+            ("server", c.server.is_some()),
+            ("safety_brake", c.safety_brake.is_some()),
+            ("auto_difficulty", c.auto_difficulty.is_some()),
+            ("pool_selection", c.pool_selection.is_some()),
+            ("restart", c.restart.is_some()),
+            ("slack", c.slack.is_some()),
+            ("email", c.email.is_some()),
+            ("report", c.report.is_some()),
+            ("price", c.price.is_some()),
+            ("too_slow", c.too_slow.is_some()),
+            ("autoscale", c.autoscale.is_some()),
+            ("limits", c.limits.is_some()),
+            ("keepalive", c.keepalive.is_some()),
+        ] {
+            if set {
+                self.add("section", section, file)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the devices, profiles and sections of `part`, read from `path`,
+    /// to `c`. Anything defined twice is an error.
+    fn merge(&mut self, c: &mut Config, part: Config, path: &Path) -> Result<(), String> {
+        self.add_all(&part, &path.display().to_string())?;
+
+        c.devices.extend(part.devices);
+        c.profiles.extend(part.profiles);
+        c.webhooks.extend(part.webhooks);
+        c.server = c.server.take().or(part.server);
+        c.safety_brake = c.safety_brake.take().or(part.safety_brake);
+        c.auto_difficulty = c.auto_difficulty.take().or(part.auto_difficulty);
+        c.pool_selection = c.pool_selection.take().or(part.pool_selection);
+        c.restart = c.restart.take().or(part.restart);
+        c.slack = c.slack.take().or(part.slack);
+        c.email = c.email.take().or(part.email);
+        c.report = c.report.take().or(part.report);
+        c.price = c.price.take().or(part.price);
+        c.too_slow = c.too_slow.take().or(part.too_slow);
+        c.autoscale = c.autoscale.take().or(part.autoscale);
+        c.limits = c.limits.take().or(part.limits);
+        c.keepalive = c.keepalive.take().or(part.keepalive);
+
+        Ok(())
+    }
+}
+
+/// Files in `dir` matching `pattern`, sorted. Only the file name may hold
+/// wildcards.
+fn expand(dir: &Path, pattern: &str) -> std::io::Result<Vec<PathBuf>> {
+    let path = dir.join(pattern);
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(['*', '?']) => name.to_string(),
+        _ => return Ok(vec![path]),
+    };
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(parent)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if let Some(file_name) = file_name.to_str() {
+            if entry.file_type()?.is_file() && matches_wildcard(&name, file_name) {
+                paths.push(parent.join(file_name));
+            }
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+/// and `?` any one character.
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Settings for the embedded status/control servers, read from the `server`
+/// section of the config file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<String>,
+}
+
+/// Wire format of a firmware, read from the `profiles` section of the config
+/// file and selected per device with `profile`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Share line layout with `{field}` placeholders, see
+    /// [`crate::protocol::DEFAULT_SUBMISSION`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submission: Option<String>,
+    /// Job request layout with `{field}` placeholders, see
+    /// [`crate::protocol::DEFAULT_JOB_REQUEST`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_request: Option<String>,
+    /// Extra values for placeholders in either template.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Thresholds for pausing an account whose devices all start failing, read
+/// from the `safety_brake` section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrakeConfig {
+    #[serde(default = "default_brake_enabled")]
+    pub enabled: bool,
+    /// Number of an account's devices that must be failing to trip the brake.
+    #[serde(default = "default_brake_min_devices")]
+    pub min_devices: usize,
+    /// Failures older than this many seconds are forgotten.
+    #[serde(default = "default_brake_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_brake_enabled() -> bool {
+    true
+}
+
+fn default_brake_min_devices() -> usize {
+    3
+}
+
+fn default_brake_window_secs() -> u64 {
+    120
+}
+
+impl Default for BrakeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_brake_enabled(),
+            min_devices: default_brake_min_devices(),
+            window_secs: default_brake_window_secs(),
+        }
+    }
+}
+
+/// Probing of pools for the one with the lowest latency, read from the
+/// `pool_selection` section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolSelectionConfig {
+    #[serde(default = "default_pool_selection_enabled")]
+    pub enabled: bool,
+    /// Pools to probe, as `host:port`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pools: Vec<String>,
+    /// Also probe the pools listed by the Duino API.
+    #[serde(default = "default_pool_selection_discover")]
+    pub discover: bool,
+    /// Seconds between probes.
+    #[serde(default = "default_pool_selection_interval_secs")]
+    pub interval_secs: u64,
+    /// Fraction of its latency another pool must save before devices move
+    /// off the current one, so close pools do not take turns.
+    #[serde(default = "default_pool_selection_switch_margin")]
+    pub switch_margin: f64,
+    /// Failures, at least a minute apart and with no healthy probe between
+    /// them, after which a pool is banned. Pools are never banned if 0.
+    #[serde(default = "default_pool_selection_ban_after")]
+    pub ban_after: u32,
+    #[serde(default = "default_pool_selection_ban_secs")]
+    pub ban_secs: u64,
+    /// File the bans are kept in, relative to the data dir.
+    #[serde(default = "default_pool_selection_banlist")]
+    pub banlist: String,
+}
+
+fn default_pool_selection_enabled() -> bool {
+    true
+}
+
+fn default_pool_selection_discover() -> bool {
+    true
+}
+
+fn default_pool_selection_interval_secs() -> u64 {
+    600
+}
+
+fn default_pool_selection_switch_margin() -> f64 {
+    0.2
+}
+
+fn default_pool_selection_ban_after() -> u32 {
+    3
+}
+
+fn default_pool_selection_ban_secs() -> u64 {
+    3600
+}
+
+fn default_pool_selection_banlist() -> String {
+    "pool-banlist.json".to_string()
+}
+
+impl Default for PoolSelectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_pool_selection_enabled(),
+            pools: Vec::new(),
+            discover: default_pool_selection_discover(),
+            interval_secs: default_pool_selection_interval_secs(),
+            switch_margin: default_pool_selection_switch_margin(),
+            ban_after: default_pool_selection_ban_after(),
+            ban_secs: default_pool_selection_ban_secs(),
+            banlist: default_pool_selection_banlist(),
+        }
+    }
+}
+
+/// Targets for moving PC devices between difficulty tiers, read from the
+/// `auto_difficulty` section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TunerConfig {
+    #[serde(default = "default_tuner_enabled")]
+    pub enabled: bool,
+    /// Seconds a share is expected to take at the right tier.
+    #[serde(default = "default_tuner_target_secs")]
+    pub target_secs: f64,
+    /// Number of shares averaged before deciding whether to change tier.
+    #[serde(default = "default_tuner_window")]
+    pub window: u32,
+    /// Factor by which the average may miss `target_secs` before the tier
+    /// changes.
+    #[serde(default = "default_tuner_tolerance")]
+    pub tolerance: f64,
+}
+
+fn default_tuner_enabled() -> bool {
+    true
+}
+
+fn default_tuner_target_secs() -> f64 {
+    10.0
+}
+
+fn default_tuner_window() -> u32 {
+    8
+}
+
+fn default_tuner_tolerance() -> f64 {
+    2.0
+}
+
+impl Default for TunerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_tuner_enabled(),
+            target_secs: default_tuner_target_secs(),
+            window: default_tuner_window(),
+            tolerance: default_tuner_tolerance(),
+        }
+    }
+}
+
+/// How a device reconnects after errors, read from the `restart` section of
+/// the config file or of a device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestartPolicy {
+    /// Restarts allowed within an hour before giving up. Unlimited if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_hour: Option<u32>,
+    /// Wait before reconnecting after the first connection failure.
+    #[serde(default = "default_backoff_secs")]
+    pub backoff_secs: f64,
+    /// Factor the wait grows by with each further failure in a row.
+    #[serde(default = "default_backoff_factor")]
+    pub backoff_factor: f64,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: f64,
+    #[serde(default)]
+    pub give_up: GiveUp,
+    /// Emit an event when the device gives up.
+    #[serde(default = "default_notify")]
+    pub notify: bool,
+}
+
+/// What a device does once it used up `max_per_hour` restarts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GiveUp {
+    /// Pause the device until it is resumed.
+    #[default]
+    Disable,
+    /// Keep reconnecting at the longest backoff.
+    KeepTrying,
+}
+
+fn default_backoff_secs() -> f64 {
+    5.0
+}
+
+fn default_backoff_factor() -> f64 {
+    2.0
+}
+
+fn default_max_backoff_secs() -> f64 {
+    300.0
+}
+
+fn default_notify() -> bool {
+    true
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_per_hour: None,
+            backoff_secs: default_backoff_secs(),
+            backoff_factor: default_backoff_factor(),
+            max_backoff_secs: default_max_backoff_secs(),
+            give_up: GiveUp::default(),
+            notify: default_notify(),
+        }
+    }
+}
+
+/// Notifications posted to a Slack incoming webhook, read from the `slack`
+/// section of the config file. Each message is a template; setting one to
+/// null turns that notification off.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlackConfig {
+    pub webhook_url: String,
+    /// Posted when a device finds a block, with `{device}` and `{message}`.
+    #[serde(default = "default_slack_block_found")]
+    pub block_found: Option<String>,
+    /// Posted when a device that is not paused has been disconnected for
+    /// `down_after_secs`, with `{device}` and `{minutes}`.
+    #[serde(default = "default_slack_device_down")]
+    pub device_down: Option<String>,
+    #[serde(default = "default_slack_down_after_secs")]
+    pub down_after_secs: u64,
+    /// Posted once a day, with `{summary}`, the fleet's summary line.
+    #[serde(default = "default_slack_daily_summary")]
+    pub daily_summary: Option<String>,
+}
+
+fn default_slack_block_found() -> Option<String> {
+    Some(":tada: {device} found a block: {message}".to_string())
+}
+
+fn default_slack_device_down() -> Option<String> {
+    Some(":warning: {device} has been down for {minutes} minutes".to_string())
+}
+
+fn default_slack_down_after_secs() -> u64 {
+    300
+}
+
+fn default_slack_daily_summary() -> Option<String> {
+    Some("Daily summary: {summary}".to_string())
+}
+
+/// Alerts mailed over SMTP when a rig needs attention, read from the `email`
+/// section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    /// Port of the SMTP server, which must offer STARTTLS.
+    #[serde(default = "default_email_smtp_port")]
+    pub smtp_port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Seconds every device that is not paused must have been disconnected
+    /// before the fleet is reported offline.
+    #[serde(default = "default_email_offline_after_secs")]
+    pub offline_after_secs: u64,
+    /// Rejected shares of one account within `rejection_window_secs` that
+    /// are reported as a rejection storm.
+    #[serde(default = "default_email_max_rejections")]
+    pub max_rejections: u64,
+    #[serde(default = "default_email_rejection_window_secs")]
+    pub rejection_window_secs: u64,
+}
+
+fn default_email_smtp_port() -> u16 {
+    587
+}
+
+fn default_email_offline_after_secs() -> u64 {
+    600
+}
+
+fn default_email_max_rejections() -> u64 {
+    20
+}
+
+fn default_email_rejection_window_secs() -> u64 {
+    300
+}
+
+/// A report of the past day made at a set local time, read from the `report`
+/// section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportConfig {
+    /// Local time of day the report is made, as HH:MM.
+    #[serde(default = "default_report_at")]
+    pub at: String,
+    /// Directory each report is also written to, as YYYY-MM-DD.json.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// Whether the report is also sent as a `daily_report` event, for the
+    /// webhooks and other event sinks.
+    #[serde(default)]
+    pub notify: bool,
+}
+
+fn default_report_at() -> String {
+    "00:00".to_string()
+}
+
+/// Where the DUCO price shown next to earnings comes from, read from the
+/// `price` section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceConfig {
+    /// Seconds a fetched price is used before it is fetched again.
+    #[serde(default = "default_price_refresh_secs")]
+    pub refresh_secs: u64,
+    /// Sources tried in order until one answers.
+    #[serde(default = "default_price_sources")]
+    pub sources: Vec<PriceSource>,
+}
+
+fn default_price_refresh_secs() -> u64 {
+    DEFAULT_PRICE_REFRESH.as_secs()
+}
+
+fn default_price_sources() -> Vec<PriceSource> {
+    vec![PriceSource::duino()]
+}
+
+/// What is done about devices that keep solving slower than their target
+/// rate, read from the `too_slow` section of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlowConfig {
+    /// Fraction of its target rate a device must solve a share at for the
+    /// share not to count as slow.
+    #[serde(default = "default_slow_ratio")]
+    pub ratio: f64,
+    /// Slow shares in a row before a `too_slow` event.
+    #[serde(default = "default_slow_after")]
+    pub after: u32,
+    /// Lower the device's target rate to the rate it managed when the event
+    /// is emitted.
+    #[serde(default)]
+    pub reduce_target: bool,
+}
+
+fn default_slow_ratio() -> f64 {
+    0.9
+}
+
+fn default_slow_after() -> u32 {
+    10
+}
+
+impl Default for SlowConfig {
+    fn default() -> Self {
+        Self {
+            ratio: default_slow_ratio(),
+            after: default_slow_after(),
+            reduce_target: false,
+        }
+    }
+}
+
+/// When part of the fleet is parked to leave the host to other work, read
+/// from the `autoscale` section of the config file. Loads are the load
+/// average over a minute divided by the number of CPUs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutoscaleConfig {
+    /// Load above which devices are parked.
+    #[serde(default = "default_autoscale_high_load")]
+    pub high_load: f64,
+    /// Load below which parked devices are resumed.
+    #[serde(default = "default_autoscale_low_load")]
+    pub low_load: f64,
+    /// Fraction of the running devices parked, at least one.
+    #[serde(default = "default_autoscale_park_fraction")]
+    pub park_fraction: f64,
+    /// Seconds between checks of the load.
+    #[serde(default = "default_autoscale_check_secs")]
+    pub check_secs: u64,
+}
+
+fn default_autoscale_high_load() -> f64 {
+    0.9
+}
+
+fn default_autoscale_low_load() -> f64 {
+    0.5
+}
+
+fn default_autoscale_park_fraction() -> f64 {
+    0.5
+}
+
+fn default_autoscale_check_secs() -> u64 {
+    30
+}
+
+impl Default for AutoscaleConfig {
+    fn default() -> Self {
+        Self {
+            high_load: default_autoscale_high_load(),
+            low_load: default_autoscale_low_load(),
+            park_fraction: default_autoscale_park_fraction(),
+            check_secs: default_autoscale_check_secs(),
+        }
+    }
+}
+
+/// Most devices the fleet may run, read from the `limits` section of the
+/// config file. Pools cap the workers of an account and of an address, and
+/// workers past the cap fail without saying why.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Devices of the whole fleet, which reach the pool from one address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_devices: Option<usize>,
+    /// Devices mining for any one account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_devices_per_account: Option<usize>,
+}
+
+impl LimitsConfig {
+    /// Checks `devices` against the limits, naming every one exceeded.
+    pub fn check(&self, devices: &[Device]) -> Result<(), String> {
+        let mut exceeded = Vec::new();
+        if let Some(max) = self.max_devices.filter(|max| devices.len() > *max) {
+            exceeded.push(format!("{} devices, max_devices is {}", devices.len(), max));
+        }
+        if let Some(max) = self.max_devices_per_account {
+            let mut accounts: BTreeMap<&str, usize> = BTreeMap::new();
+            for device in devices.iter() {
+                *accounts.entry(&device.username).or_default() += 1;
+            }
+            for (username, count) in accounts.into_iter().filter(|(_, count)| *count > max) {
+                exceeded.push(format!(
+                    "{} devices for {}, max_devices_per_account is {}",
+                    count, username, max
+                ));
+            }
+        }
+
+        if !exceeded.is_empty() {
+            return Err(format!("limits exceeded: {}", exceeded.join("; ")));
+        }
+
+        Ok(())
+    }
+}
+
+/// TCP keepalive on connections to the pool, read from the `keepalive`
+/// section of the config file. Devices with low target rates leave their
+/// connection idle for long, which some NAT routers take as dead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeepaliveConfig {
+    /// Seconds a connection is idle before keepalive probes are sent.
+    #[serde(default = "default_keepalive_idle_secs")]
+    pub idle_secs: u64,
+}
+
+fn default_keepalive_idle_secs() -> u64 {
+    30
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            idle_secs: default_keepalive_idle_secs(),
+        }
+    }
+}
+
+/// An HTTP request sent for each event of the selected kinds, read from the
+/// `webhooks` list of the config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_webhook_method")]
+    pub method: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Kinds of events sent, every kind if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventKind>,
+    /// Body template. `{id}`, `{timestamp}`, `{device}`, `{kind}` and
+    /// `{message}` are filled in escaped for use inside JSON strings.
+    #[serde(default = "default_webhook_body")]
+    pub body: String,
+}
+
+fn default_webhook_method() -> String {
+    "POST".to_string()
+}
+
+fn default_webhook_body() -> String {
+    r#"{"device": "{device}", "kind": "{kind}", "message": "{message}", "timestamp": {timestamp}}"#
+        .to_string()
+}
+
+/// PEM-encoded certificate chain and private key.
+pub struct Tls {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+impl ServerConfig {
+    pub fn tls(&self) -> Result<Option<Tls>, std::io::Error> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some(Tls {
+                cert: std::fs::read(cert)?,
+                key: std::fs::read(key)?,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(device_type: &str, difficulty: Option<&str>) -> Device {
+        Device {
+            username: "my_username".to_string(),
+            device_name: "dev-1".to_string(),
+            device_type: device_type.to_string(),
+            chip_id: "DUCOID01234567".to_string(),
+            firmware: "Official AVR Miner v2.6".to_string(),
+            target_rate: 190,
+            profile: None,
+            wallet_id: None,
+            mining_key: None,
+            difficulty: difficulty.map(str::to_string),
+            restart: None,
+            group: None,
+            transports: Vec::new(),
+            namespace: None,
+        }
+    }
+
+    #[test]
+    fn avr_requests_its_device_type() {
+        assert_eq!(device("AVR", None).difficulty(), "AVR");
+    }
+
+    #[test]
+    fn pc_requests_default_tier() {
+        assert_eq!(device("PC", None).difficulty(), DEFAULT_PC_DIFFICULTY);
+    }
+
+    #[test]
+    fn difficulty_overrides_device_type() {
+        assert_eq!(device("PC", Some("EXTREME")).difficulty(), "EXTREME");
+    }
+
+    #[test]
+    fn selects_devices_by_name_or_group() {
+        let mut c = Config::from_yaml(&devices_yaml(&["avr-1", "avr-2", "avr-3"])).unwrap();
+        c.devices[0].group = Some("rack-a".to_string());
+        c.devices[1].group = Some("rack-a".to_string());
+
+        assert!(c.devices[0].matches("group:rack-a"));
+        assert!(!c.devices[0].matches("rack-a"));
+        assert!(c.retain_selected(&["group:rack-b".to_string()]).is_err());
+
+        c.retain_selected(&["group:rack-a".to_string(), "avr-3".to_string()])
+            .unwrap();
+        assert_eq!(c.devices.len(), 3);
+        c.retain_selected(&["group:rack-a".to_string()]).unwrap();
+        let names: Vec<_> = c.devices.iter().map(|d| d.device_name.as_str()).collect();
+        assert_eq!(names, ["avr-1", "avr-2"]);
+    }
+
+    fn config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("config-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("accounts")).unwrap();
+        for (file, content) in files {
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+
+        dir
+    }
+
+    fn devices_yaml(names: &[&str]) -> String {
+        let mut yaml = "devices:\n".to_string();
+        for name in names {
+            yaml.push_str(&format!(
+                "  - {{username: alice, device_name: {}, device_type: AVR, chip_id: DUCOID00000000, \
+                 firmware: Official AVR Miner v2.6, target_rate: 190}}\n",
+                name
+            ));
+        }
+
+        yaml
+    }
+
+    #[test]
+    fn merges_included_files() {
+        let dir = config_dir(
+            "merge",
+            &[
+                ("config.yaml", "include: [accounts/*.yaml]\nrestart: {}\n"),
+                ("accounts/a.yaml", &devices_yaml(&["avr-1", "avr-2"])),
+                ("accounts/b.yaml", &devices_yaml(&["avr-3"])),
+                ("accounts/notes.txt", "not yaml: ["),
+            ],
+        );
+
+        let c = Config::load(dir.join("config.yaml").to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = c.devices.iter().map(|d| d.device_name.as_str()).collect();
+        assert_eq!(names, vec!["avr-1", "avr-2", "avr-3"]);
+        assert!(c.include.is_empty());
+        assert!(c.restart.is_some());
+    }
+
+    #[test]
+    fn names_both_files_of_a_duplicate() {
+        let dir = config_dir(
+            "duplicate",
+            &[
+                ("config.yaml", "include: [accounts/*.yaml]\n"),
+                ("accounts/a.yaml", &devices_yaml(&["avr-1"])),
+                ("accounts/b.yaml", &devices_yaml(&["avr-1"])),
+            ],
+        );
+
+        let e = Config::load(dir.join("config.yaml").to_str().unwrap()).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let message = e.to_string();
+        assert!(message.contains("b.yaml: device avr-1 is already defined in"));
+        assert!(message.contains("a.yaml"));
+    }
+
+    #[test]
+    fn namespaces_devices_of_several_files() {
+        let dir = config_dir(
+            "namespaces",
+            &[
+                ("alice.yaml", &devices_yaml(&["avr-1"])),
+                ("bob.yaml", &devices_yaml(&["avr-1", "avr-2"])),
+            ],
+        );
+        let files: Vec<String> = ["alice.yaml", "bob.yaml"]
+            .iter()
+            .map(|file| dir.join(file).to_str().unwrap().to_string())
+            .collect();
+
+        let c = Config::load_all(&files).unwrap();
+        let single = Config::load_all(&files[..1]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = c.devices.iter().map(|d| d.name()).collect();
+        assert_eq!(names, vec!["alice/avr-1", "bob/avr-1", "bob/avr-2"]);
+        assert_eq!(c.devices[1].device_name, "avr-1");
+        assert!(c.devices[1].matches("bob/avr-1"));
+        assert!(!c.devices[1].matches("avr-1"));
+        assert_eq!(single.devices[0].name(), "avr-1");
+    }
+
+    #[test]
+    fn checks_device_limits() {
+        let mut c = Config::from_yaml(&devices_yaml(&["avr-1", "avr-2", "avr-3"])).unwrap();
+        c.devices[2].username = "bob".to_string();
+        let limits = |yaml: &str| -> LimitsConfig { serde_yaml::from_str(yaml).unwrap() };
+
+        assert!(limits("{}").check(&c.devices).is_ok());
+        assert!(limits(
+            "max_devices: 3
+max_devices_per_account: 2
+"
+        )
+        .check(&c.devices)
+        .is_ok());
+        let e = limits(
+            "max_devices: 2
+max_devices_per_account: 1
+",
+        )
+        .check(&c.devices)
+        .unwrap_err();
+        assert_eq!(
+            e,
+            "limits exceeded: 3 devices, max_devices is 2; \
+             2 devices for alice, max_devices_per_account is 1"
+        );
+    }
+
+    #[test]
+    fn sets_device_field_in_defining_file() {
+        let dir = config_dir(
+            "set-field",
+            &[
+                ("alice.yaml", &devices_yaml(&["avr-1"])),
+                (
+                    "bob.yaml",
+                    &format!("include: [accounts/*.yaml]\n{}", devices_yaml(&["avr-1"])),
+                ),
+                ("accounts/more.yaml", &devices_yaml(&["avr-2"])),
+            ],
+        );
+        let files: Vec<String> = ["alice.yaml", "bob.yaml"]
+            .iter()
+            .map(|file| dir.join(file).to_str().unwrap().to_string())
+            .collect();
+
+        let c = Config::load_all(&files).unwrap();
+        let written =
+            Config::set_device_field(&files, &c.devices[2], "target_rate", Value::from(250))
+                .unwrap();
+        let updated = Config::load_all(&files).unwrap();
+        let backup = Config::load_file(&dir.join("accounts/more.yaml.bak")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written, dir.join("accounts/more.yaml"));
+        assert_eq!(backup.devices[0].target_rate, 190);
+        let rates: Vec<_> = updated.devices.iter().map(|d| d.target_rate).collect();
+        assert_eq!(rates, [190, 190, 250]);
+    }
+
+    #[test]
+    fn migrates_unversioned_config() {
+        let c = Config::from_yaml(&devices_yaml(&["avr-1"])).unwrap();
+
+        assert_eq!(c.version, CONFIG_VERSION);
+        assert_eq!(c.devices.len(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let e = Config::from_yaml(&format!("version: {}\ndevices: []\n", CONFIG_VERSION + 1))
+            .unwrap_err();
+
+        assert!(e.to_string().contains("newer than this miner supports"));
+    }
+
+    #[test]
+    fn migrates_file_once_keeping_backup() {
+        let original = devices_yaml(&["avr-1"]);
+        let dir = config_dir("migrate", &[("config.yaml", &original)]);
+        let path = dir.join("config.yaml");
+
+        assert_eq!(Config::migrate_file(&path).unwrap(), Some(0));
+        assert_eq!(Config::migrate_file(&path).unwrap(), None);
+        let backup = std::fs::read_to_string(dir.join("config.yaml.bak")).unwrap();
+        let migrated = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(backup, original);
+        assert!(migrated.contains(&format!("version: {}", CONFIG_VERSION)));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_wildcard("*.yaml", "rack-1.yaml"));
+        assert!(matches_wildcard("rack-?.y*", "rack-1.yaml"));
+        assert!(!matches_wildcard("*.yaml", "rack-1.yml"));
+        assert!(!matches_wildcard("rack-?.yaml", "rack-10.yaml"));
+    }
+
+    #[test]
+    fn null_slack_template_turns_notification_off() {
+        let c =
+            Config::from_yaml("slack:\n  webhook_url: https://example.com\n  block_found: null\n")
+                .unwrap();
+        let slack = c.slack.unwrap();
+
+        assert_eq!(slack.block_found, None);
+        assert_eq!(slack.device_down, default_slack_device_down());
+        assert_eq!(slack.down_after_secs, 300);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::config::{Config, Device};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde_yaml::Value;
+
+/// Wakes threads sleeping through an idle fleet when a device is paused,
+/// resumed or shut down, so they need no timers while they wait.
+#[derive(Default)]
+struct Wakeup {
+    lock: Mutex<()>,
+    changed: Condvar,
+}
+
+impl Wakeup {
+    fn notify(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.changed.notify_all();
+    }
+
+    /// Blocks while `idle` returns true, or until `timeout` elapses if given.
+    fn wait_while(&self, timeout: Option<Duration>, idle: impl Fn() -> bool) {
+        let guard = self.lock.lock().unwrap();
+        let _guard = match timeout {
+            Some(timeout) => {
+                self.changed
+                    .wait_timeout_while(guard, timeout, |_| idle())
+                    .unwrap()
+                    .0
+            }
+            None => self.changed.wait_while(guard, |_| idle()).unwrap(),
+        };
+    }
+}
+
+pub struct DeviceControl {
+    device: Mutex<Device>,
+    paused: AtomicBool,
+    reconnect: AtomicBool,
+    shutdown: Arc<AtomicBool>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl DeviceControl {
+    fn new(device: Device, shutdown: Arc<AtomicBool>, wakeup: Arc<Wakeup>) -> Self {
+        Self {
+            device: Mutex::new(device),
+            paused: AtomicBool::new(false),
+            reconnect: AtomicBool::new(false),
+            shutdown,
+            wakeup,
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device.lock().unwrap().clone()
+    }
+
+    pub fn target_rate(&self) -> u32 {
+        self.device.lock().unwrap().target_rate
+    }
+
+    pub fn set_target_rate(&self, target_rate: u32) {
+        self.device.lock().unwrap().target_rate = target_rate;
+    }
+
+    pub fn set_device(&self, device: Device) {
+        *self.device.lock().unwrap() = device;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        self.wakeup.notify();
+    }
+
+    /// Blocks without waking up until the device is resumed or the fleet is
+    /// shut down.
+    pub fn wait_while_paused(&self) {
+        self.wakeup
+            .wait_while(None, || self.is_paused() && !self.is_shutdown());
+    }
+
+    /// Sleeps for `duration`, returning early if the fleet is shut down.
+    pub fn sleep(&self, duration: Duration) {
+        self.wakeup
+            .wait_while(Some(duration), || !self.is_shutdown());
+    }
+
+    pub fn request_reconnect(&self) {
+        self.reconnect.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Stops every device of the fleet, not just this one.
+    pub fn shutdown_fleet(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.wakeup.notify();
+    }
+
+    /// Returns whether the device should drop its connection, clearing any
+    /// pending reconnect request.
+    pub fn should_disconnect(&self) -> bool {
+        self.reconnect.swap(false, Ordering::Relaxed) || self.is_paused() || self.is_shutdown()
+    }
+}
+
+pub struct FleetControl {
+    devices: Vec<Arc<DeviceControl>>,
+    config_files: Vec<String>,
+    shutdown: Arc<AtomicBool>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl FleetControl {
+    pub fn new(devices: Vec<Device>, config_files: Vec<String>) -> Self {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let wakeup = Arc::new(Wakeup::default());
+
+        Self {
+            devices: devices
+                .into_iter()
+                .map(|d| Arc::new(DeviceControl::new(d, shutdown.clone(), wakeup.clone())))
+                .collect(),
+            config_files,
+            shutdown,
+            wakeup,
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.wakeup.notify();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Whether every device is paused, leaving nothing for the fleet to do.
+    pub fn is_idle(&self) -> bool {
+        self.devices.iter().all(|d| d.is_paused())
+    }
+
+    /// Blocks without waking up while the fleet is idle, until a device is
+    /// resumed or the fleet is shut down.
+    pub fn wait_until_active(&self) {
+        self.wakeup
+            .wait_while(None, || self.is_idle() && !self.is_shutdown());
+    }
+
+    pub fn device(&self, index: usize) -> Arc<DeviceControl> {
+        self.devices[index].clone()
+    }
+
+    pub fn devices(&self) -> &[Arc<DeviceControl>] {
+        &self.devices
+    }
+
+    /// Pauses every device mining for `username`, returning how many were
+    /// paused.
+    pub fn pause_account(&self, username: &str) -> usize {
+        let mut paused = 0;
+        for device in self.devices.iter() {
+            if device.device().username == username && !device.is_paused() {
+                device.set_paused(true);
+                paused += 1;
+            }
+        }
+
+        paused
+    }
+
+    /// Devices picked by `selector`, a device name or `group:` followed by a
+    /// group name.
+    pub fn select(&self, selector: &str) -> Vec<&Arc<DeviceControl>> {
+        self.devices
+            .iter()
+            .filter(|d| d.device().matches(selector))
+            .collect()
+    }
+
+    /// The device named `name`, with its namespace if it has one.
+    pub fn find(&self, name: &str) -> Option<&Arc<DeviceControl>> {
+        self.devices.iter().find(|d| d.device().name() == name)
+    }
+
+    /// Writes the current target rate of `device` to the config file that
+    /// defines it, so it survives reloads and restarts.
+    pub fn persist_target_rate(
+        &self,
+        device: &DeviceControl,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if self.config_files.is_empty() {
+            return Err("miner was not started from a config file".into());
+        }
+
+        Config::set_device_field(
+            &self.config_files,
+            &device.device(),
+            "target_rate",
+            Value::from(device.target_rate()),
+        )
+    }
+
+    /// The config of each device, by name.
+    pub fn configs(&self) -> BTreeMap<String, Device> {
+        self.devices
+            .iter()
+            .map(|d| d.device())
+            .map(|device| (device.name(), device))
+            .collect()
+    }
+
+    /// Applies `configs`, by device name, to the running devices, keeping
+    /// their namespaces, and returns how many changed. Devices that were
+    /// added or removed require a restart.
+    pub fn apply(&self, configs: BTreeMap<String, Device>) -> usize {
+        let mut updated = 0;
+        for (name, mut device) in configs {
+            match self.find(&name) {
+                Some(control) => {
+                    let current = control.device();
+                    device.namespace = current.namespace.clone();
+                    if current != device {
+                        control.set_device(device);
+                        control.request_reconnect();
+                        updated += 1;
+                    }
+                }
+                None => warn!("{} is new in config, restart required to spawn it", name),
+            }
+        }
+
+        updated
+    }
+
+    /// Re-reads the config files and applies them to the running devices,
+    /// see [`FleetControl::apply`].
+    pub fn reload(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        if self.config_files.is_empty() {
+            return Err("miner was not started from a config file".into());
+        }
+        let c = Config::load_all(&self.config_files)?;
+
+        let updated = self.apply(c.devices.into_iter().map(|d| (d.name(), d)).collect());
+        info!("reloaded config, {} devices updated", updated);
+
+        Ok(updated)
+    }
+}
+
+/// How a proposed fleet differs from the running one, by device name.
+#[derive(Debug, Default, PartialEq)]
+pub struct FleetDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Devices in both, with the fields that differ.
+    pub modified: BTreeMap<String, Vec<String>>,
+}
+
+impl FleetDiff {
+    pub fn new(running: &BTreeMap<String, Device>, proposed: &BTreeMap<String, Device>) -> Self {
+        let mut diff = Self::default();
+        for (name, device) in proposed.iter() {
+            match running.get(name) {
+                Some(current) => {
+                    let fields = changed_fields(current, device);
+                    if !fields.is_empty() {
+                        diff.modified.insert(name.clone(), fields);
+                    }
+                }
+                None => diff.added.push(name.clone()),
+            }
+        }
+        diff.removed = running
+            .keys()
+            .filter(|name| !proposed.contains_key(*name))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Fields of `proposed` as written in config files that differ from
+/// `current`.
+fn changed_fields(current: &Device, proposed: &Device) -> Vec<String> {
+    let fields = |device: &Device| -> BTreeMap<String, Value> {
+        match serde_yaml::to_value(device) {
+            Ok(Value::Mapping(mapping)) => mapping
+                .into_iter()
+                .filter_map(|(k, v)| Some((k.as_str()?.to_string(), v)))
+                .collect(),
+            _ => BTreeMap::new(),
+        }
+    };
+    let (current, proposed) = (fields(current), fields(proposed));
+
+    let mut changed: Vec<String> = proposed
+        .iter()
+        .filter(|(field, value)| current.get(*field) != Some(*value))
+        .map(|(field, _)| field.clone())
+        .collect();
+    changed.extend(
+        current
+            .keys()
+            .filter(|field| !proposed.contains_key(*field))
+            .cloned(),
+    );
+    changed.sort();
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    fn fleet() -> Arc<FleetControl> {
+        let device: Device = serde_yaml::from_str(
+            "username: alice\ndevice_name: avr-1\ndevice_type: AVR\nchip_id: DUCOID01234567\nfirmware: Official AVR Miner v2.6\ntarget_rate: 190\n",
+        )
+        .unwrap();
+
+        Arc::new(FleetControl::new(vec![device], Vec::new()))
+    }
+
+    #[test]
+    fn wakes_when_device_resumed() {
+        let fleet = fleet();
+        fleet.device(0).set_paused(true);
+        assert!(fleet.is_idle());
+
+        let device = fleet.device(0);
+        let resumer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            device.set_paused(false);
+        });
+
+        fleet.wait_until_active();
+        fleet.device(0).wait_while_paused();
+        assert!(!fleet.is_idle());
+        resumer.join().unwrap();
+    }
+
+    #[test]
+    fn shutdown_cuts_sleep_short() {
+        let fleet = fleet();
+        let device = fleet.device(0);
+        let start = Instant::now();
+
+        let stopper = {
+            let fleet = fleet.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                fleet.shutdown();
+            })
+        };
+
+        device.sleep(Duration::from_secs(60));
+        assert!(start.elapsed() < Duration::from_secs(60));
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn diffs_fleets_by_name() {
+        let device = fleet().device(0).device();
+        let mut faster = device.clone();
+        faster.target_rate = 250;
+        faster.group = Some("rack-a".to_string());
+        let mut other = device.clone();
+        other.device_name = "avr-2".to_string();
+
+        let running: BTreeMap<_, _> = vec![
+            ("avr-1".to_string(), device.clone()),
+            ("avr-2".to_string(), other.clone()),
+        ]
+        .into_iter()
+        .collect();
+        let proposed: BTreeMap<_, _> =
+            vec![("avr-1".to_string(), faster), ("avr-3".to_string(), device)]
+                .into_iter()
+                .collect();
+
+        let diff = FleetDiff::new(&running, &proposed);
+        assert_eq!(diff.added, vec!["avr-3"]);
+        assert_eq!(diff.removed, vec!["avr-2"]);
+        assert_eq!(diff.modified["avr-1"], vec!["group", "target_rate"]);
+        assert!(FleetDiff::new(&running, &running).is_empty());
+    }
+
+    #[test]
+    fn applies_configs_to_running_devices() {
+        let fleet = fleet();
+        let mut configs = fleet.configs();
+        assert_eq!(fleet.apply(configs.clone()), 0);
+
+        configs.get_mut("avr-1").unwrap().target_rate = 250;
+        assert_eq!(fleet.apply(configs), 1);
+        assert_eq!(fleet.device(0).target_rate(), 250);
+    }
+}
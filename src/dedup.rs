@@ -0,0 +1,122 @@
+use crate::protocol::Job;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Number of recent blocks whose submitted share is remembered per device.
+const REMEMBERED_BLOCKS: usize = 16;
+
+struct Submitted {
+    last_block_hash: String,
+    expected_hash: String,
+    nonce: u64,
+}
+
+/// The last share a device submitted for each recent block, kept across
+/// reconnects so a share is never sent twice and a reissued job is not
+/// searched again.
+#[derive(Default)]
+pub struct SubmittedShares {
+    shares: Mutex<VecDeque<Submitted>>,
+}
+
+impl SubmittedShares {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the nonce already submitted for `job`, if any.
+    pub fn solved(&self, job: &Job) -> Option<u64> {
+        let shares = self.shares.lock().unwrap();
+
+        shares
+            .iter()
+            .find(|s| {
+                s.last_block_hash == job.last_block_hash && s.expected_hash == job.expected_hash
+            })
+            .map(|s| s.nonce)
+    }
+
+    /// Remembers `nonce` as submitted for `job`. Returns false if that nonce
+    /// was already submitted for the job's block.
+    pub fn insert(&self, job: &Job, nonce: u64) -> bool {
+        let mut shares = self.shares.lock().unwrap();
+
+        match shares
+            .iter_mut()
+            .find(|s| s.last_block_hash == job.last_block_hash)
+        {
+            Some(last) if last.nonce == nonce => return false,
+            Some(last) => {
+                last.expected_hash = job.expected_hash.clone();
+                last.nonce = nonce;
+            }
+            None => {
+                if shares.len() == REMEMBERED_BLOCKS {
+                    shares.pop_front();
+                }
+                shares.push_back(Submitted {
+                    last_block_hash: job.last_block_hash.clone(),
+                    expected_hash: job.expected_hash.clone(),
+                    nonce,
+                });
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(last_block_hash: &str, expected_hash: &str) -> Job {
+        Job {
+            last_block_hash: last_block_hash.to_string(),
+            expected_hash: expected_hash.to_string(),
+            difficulty: 1,
+        }
+    }
+
+    #[test]
+    fn rejects_repeated_share() {
+        let shares = SubmittedShares::new();
+
+        assert!(shares.insert(&job("a", "x"), 42));
+        assert!(!shares.insert(&job("a", "x"), 42));
+    }
+
+    #[test]
+    fn tracks_last_nonce_per_block() {
+        let shares = SubmittedShares::new();
+
+        assert!(shares.insert(&job("a", "x"), 42));
+        assert!(shares.insert(&job("a", "y"), 43));
+        assert!(shares.insert(&job("a", "x"), 42));
+        assert!(shares.insert(&job("b", "x"), 42));
+    }
+
+    #[test]
+    fn finds_reissued_job() {
+        let shares = SubmittedShares::new();
+        shares.insert(&job("a", "x"), 42);
+
+        assert_eq!(shares.solved(&job("a", "x")), Some(42));
+        assert_eq!(shares.solved(&job("a", "y")), None);
+        assert_eq!(shares.solved(&job("b", "x")), None);
+    }
+
+    #[test]
+    fn forgets_oldest_block() {
+        let shares = SubmittedShares::new();
+
+        shares.insert(&job("a", "x"), 42);
+        for i in 0..REMEMBERED_BLOCKS {
+            shares.insert(&job(&i.to_string(), "x"), 0);
+        }
+
+        assert_eq!(shares.solved(&job("a", "x")), None);
+        assert!(shares.insert(&job("a", "x"), 42));
+    }
+}
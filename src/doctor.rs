@@ -0,0 +1,518 @@
+//! Checks the environment the miner will run in and suggests fixes.
+
+use duino_miner::api::{Client, DEFAULT_BASE_URL};
+use duino_miner::config::{Config, Device};
+use duino_miner::hasher::Sha1Hasher;
+
+use crate::rlimit;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pool latency above which devices may time out.
+const SLOW_POOL: Duration = Duration::from_secs(1);
+
+/// Clock difference to the API server above which timing looks off.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Nonces hashed to measure the hash rate of one thread.
+const BENCH_HASHES: u64 = 200_000;
+
+/// Share of the host's hash rate the devices may claim, leaving the rest
+/// for bursts, the network and everything else running on it.
+const HEADROOM: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Ok,
+    Warning,
+    Problem,
+}
+
+pub struct Finding {
+    pub level: Level,
+    pub check: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Ok => "ok",
+            Level::Warning => "warning",
+            Level::Problem => "problem",
+        };
+        write!(f, "[{}] {}: {}", level, self.check, self.message)
+    }
+}
+
+fn finding(level: Level, check: &'static str, message: String) -> Finding {
+    Finding {
+        level,
+        check,
+        message,
+    }
+}
+
+/// Runs every check against `config_file`, mining on `pool` if given.
+pub fn diagnose(config_file: &str, pool: Option<String>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    match Config::load(config_file) {
+        Ok(c) => {
+            findings.extend(check_config(&c));
+            findings.push(check_fd_limit(c.devices.len()));
+            let available = bench();
+            findings.push(check_cpu(&c, available));
+            findings.extend(check_capacity(&c, available));
+        }
+        Err(e) => findings.push(finding(
+            Level::Problem,
+            "config",
+            format!(
+                "could not load {}: {}; fix it or create one with `generate`",
+                config_file, e
+            ),
+        )),
+    }
+
+    findings.push(match Sha1Hasher::new().self_test() {
+        Ok(()) => finding(Level::Ok, "hasher", "known answers match".to_string()),
+        Err(e) => finding(
+            Level::Problem,
+            "hasher",
+            format!(
+                "{}; rebuild with `--no-default-features --features cli` to drop the `asm` hasher",
+                e
+            ),
+        ),
+    });
+
+    let pool = match pool {
+        Some(pool) => pool,
+        None => match Client::shared().get_pool() {
+            Ok(pool) => pool.address(),
+            Err(e) => {
+                findings.push(finding(
+                    Level::Warning,
+                    "pool lookup",
+                    format!(
+                        "{}; the miner will fall back to server.duinocoin.com:2813",
+                        e
+                    ),
+                ));
+                format!("{}:{}", "server.duinocoin.com", 2813)
+            }
+        },
+    };
+    match resolve(&pool, &mut findings) {
+        Some(addr) => findings.push(check_pool(&pool, addr)),
+        None => findings.push(finding(
+            Level::Problem,
+            "pool",
+            format!("{} not reachable without an address", pool),
+        )),
+    }
+
+    findings.push(check_clock());
+
+    findings
+}
+
+fn check_config(c: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if c.devices.is_empty() {
+        findings.push(finding(
+            Level::Problem,
+            "config",
+            "no devices configured; add some with `generate`".to_string(),
+        ));
+    }
+
+    let mut names = BTreeSet::new();
+    for device in c.devices.iter() {
+        if !names.insert(&device.device_name) {
+            findings.push(finding(
+                Level::Problem,
+                "config",
+                format!(
+                    "device name {} is used more than once; the pool counts them as one",
+                    device.device_name
+                ),
+            ));
+        }
+        if device.target_rate == 0 {
+            findings.push(finding(
+                Level::Problem,
+                "config",
+                format!("{} has a target rate of 0", device.device_name),
+            ));
+        }
+        if let Some(profile) = &device.profile {
+            if !c.profiles.contains_key(profile) {
+                findings.push(finding(
+                    Level::Warning,
+                    "config",
+                    format!(
+                        "{} uses profile {}, which is not defined; the default layout is sent",
+                        device.device_name, profile
+                    ),
+                ));
+            }
+        }
+    }
+
+    if findings.is_empty() {
+        findings.push(finding(
+            Level::Ok,
+            "config",
+            format!("{} devices", c.devices.len()),
+        ));
+    }
+
+    findings
+}
+
+fn check_fd_limit(devices: usize) -> Finding {
+    let needed = rlimit::fds_needed(devices);
+
+    match rlimit::open_file_limit() {
+        Ok((soft, _)) if soft >= needed => finding(
+            Level::Ok,
+            "open files",
+            format!("limit {} covers the {} needed", soft, needed),
+        ),
+        Ok((soft, hard)) if hard >= needed => finding(
+            Level::Ok,
+            "open files",
+            format!(
+                "limit {} will be raised to the {} needed at startup",
+                soft, needed
+            ),
+        ),
+        Ok((soft, hard)) => finding(
+            Level::Problem,
+            "open files",
+            format!(
+                "limit {} (hard {}) is below the {} needed; raise it with `ulimit -n` \
+                 or run with --workers",
+                soft, hard, needed
+            ),
+        ),
+        Err(e) => finding(Level::Warning, "open files", e),
+    }
+}
+
+fn threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Measures the hash rate of this host over all of its threads.
+fn bench() -> f64 {
+    let started = Instant::now();
+    let _ = Sha1Hasher::new().search(
+        "0000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000",
+        0,
+        BENCH_HASHES,
+        |_| {},
+    );
+    let per_thread = BENCH_HASHES as f64 / started.elapsed().as_secs_f64().max(1e-9);
+
+    per_thread * threads() as f64
+}
+
+/// Compares the hash rate `available` on this host with the rates the
+/// devices claim.
+fn check_cpu(c: &Config, available: f64) -> Finding {
+    let claimed: u64 = c.devices.iter().map(|d| d.target_rate as u64).sum();
+
+    let message = format!(
+        "{} threads hash {:.0} H/s, devices claim {} H/s",
+        threads(),
+        available,
+        claimed
+    );
+    if claimed as f64 > available {
+        finding(
+            Level::Problem,
+            "cpu",
+            format!(
+                "{}; lower the target rates or split the fleet across hosts",
+                message
+            ),
+        )
+    } else if claimed as f64 > available * HEADROOM {
+        finding(
+            Level::Warning,
+            "cpu",
+            format!(
+                "{}; solved jobs are shared between devices, but leave headroom",
+                message
+            ),
+        )
+    } else {
+        finding(Level::Ok, "cpu", message)
+    }
+}
+
+/// Devices of one type and target rate, and how many of them this host
+/// sustains with headroom alongside the rest of the fleet.
+#[derive(Debug, PartialEq)]
+struct Capacity {
+    device_type: String,
+    target_rate: u32,
+    configured: usize,
+    sustainable: usize,
+}
+
+/// Splits `devices` by type and target rate and works out how many of each
+/// fit in the headroom of `available` H/s, keeping the others as they are.
+/// Solved jobs are shared between devices, so this errs on the safe side.
+fn plan(devices: &[Device], available: f64) -> Vec<Capacity> {
+    let mut profiles: BTreeMap<(String, u32), usize> = BTreeMap::new();
+    for device in devices.iter() {
+        *profiles
+            .entry((device.device_type.clone(), device.target_rate))
+            .or_default() += 1;
+    }
+
+    let claimed: f64 = devices.iter().map(|d| d.target_rate as f64).sum();
+    let budget = available * HEADROOM;
+
+    profiles
+        .into_iter()
+        .map(|((device_type, target_rate), configured)| {
+            let others = claimed - configured as f64 * target_rate as f64;
+            let sustainable = ((budget - others) / target_rate.max(1) as f64).max(0f64);
+
+            Capacity {
+                device_type,
+                target_rate,
+                configured,
+                sustainable: sustainable as usize,
+            }
+        })
+        .collect()
+}
+
+/// How many devices of each kind in `c` this host can run, so an overloaded
+/// host shows before it logs that the system is too slow.
+fn check_capacity(c: &Config, available: f64) -> Vec<Finding> {
+    plan(&c.devices, available)
+        .into_iter()
+        .map(|capacity| {
+            let message = format!(
+                "{} {} at {} H/s, this host sustains {} with headroom",
+                capacity.configured,
+                capacity.device_type,
+                capacity.target_rate,
+                capacity.sustainable
+            );
+            if capacity.configured > capacity.sustainable {
+                finding(
+                    Level::Warning,
+                    "capacity",
+                    format!("{}; run fewer or spread them across hosts", message),
+                )
+            } else {
+                finding(Level::Ok, "capacity", message)
+            }
+        })
+        .collect()
+}
+
+fn resolve(pool: &str, findings: &mut Vec<Finding>) -> Option<SocketAddr> {
+    match pool.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => {
+            findings.push(finding(
+                Level::Ok,
+                "dns",
+                format!("{} resolves to {}", pool, addr),
+            ));
+            Some(addr)
+        }
+        Ok(None) | Err(_) => {
+            findings.push(finding(
+                Level::Problem,
+                "dns",
+                format!(
+                    "could not resolve {}; check the resolver or pass --pool",
+                    pool
+                ),
+            ));
+            None
+        }
+    }
+}
+
+/// Connects to the pool and times the connect and its version greeting.
+fn check_pool(pool: &str, addr: SocketAddr) -> Finding {
+    let started = Instant::now();
+    let stream = match TcpStream::connect_timeout(&addr, TIMEOUT) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return finding(
+                Level::Problem,
+                "pool",
+                format!(
+                    "could not connect to {}: {}; check firewalls and --pool",
+                    pool, e
+                ),
+            )
+        }
+    };
+    let connected = started.elapsed();
+
+    let mut version = String::new();
+    let greeting = stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| BufReader::new(&stream).read_line(&mut version));
+    let greeted = started.elapsed();
+
+    match greeting {
+        Ok(n) if n > 0 => {
+            let message = format!(
+                "{} sent version {} after {} ms (connect {} ms)",
+                pool,
+                version.trim(),
+                greeted.as_millis(),
+                connected.as_millis()
+            );
+            if greeted > SLOW_POOL {
+                finding(
+                    Level::Warning,
+                    "pool",
+                    format!("{}; devices may time out", message),
+                )
+            } else {
+                finding(Level::Ok, "pool", message)
+            }
+        }
+        _ => finding(
+            Level::Problem,
+            "pool",
+            format!("{} accepted the connection but sent no version", pool),
+        ),
+    }
+}
+
+/// Compares the system clock with the `Date` of the API server.
+fn check_clock() -> Finding {
+    let response = match ureq::get(DEFAULT_BASE_URL).timeout(TIMEOUT).call() {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => {
+            return finding(
+                Level::Warning,
+                "clock",
+                format!("could not reach {} to compare: {}", DEFAULT_BASE_URL, e),
+            )
+        }
+    };
+
+    let server = match response.header("Date").map(httpdate::parse_http_date) {
+        Some(Ok(server)) => server,
+        _ => {
+            return finding(
+                Level::Warning,
+                "clock",
+                format!("{} sent no usable Date header", DEFAULT_BASE_URL),
+            )
+        }
+    };
+
+    let now = SystemTime::now();
+    let skew = now
+        .duration_since(server)
+        .or_else(|_| server.duration_since(now))
+        .unwrap_or_default();
+    if skew > MAX_CLOCK_SKEW {
+        finding(
+            Level::Problem,
+            "clock",
+            format!(
+                "{} s off from {}; sync the clock with NTP",
+                skew.as_secs(),
+                DEFAULT_BASE_URL
+            ),
+        )
+    } else {
+        finding(
+            Level::Ok,
+            "clock",
+            format!("within {} s of {}", skew.as_secs(), DEFAULT_BASE_URL),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        Config::from_yaml(yaml).unwrap()
+    }
+
+    const DEVICE: &str = "
+  - username: alice
+    device_name: avr-1
+    device_type: AVR
+    chip_id: DUCOID00000000
+    firmware: Official AVR Miner v2.6
+    target_rate: 190
+";
+
+    #[test]
+    fn accepts_valid_config() {
+        let findings = check_config(&config(&format!("devices:{}", DEVICE)));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].level, Level::Ok);
+    }
+
+    #[test]
+    fn plans_capacity_per_device_kind() {
+        let pc = DEVICE
+            .replace("avr-1", "pc-1")
+            .replace("AVR", "PC")
+            .replace("190", "1000");
+        let c = config(&format!("devices:{}{}", DEVICE, pc));
+
+        let plan = plan(&c.devices, 4000.0);
+
+        assert_eq!(
+            plan,
+            vec![
+                Capacity {
+                    device_type: "AVR".to_string(),
+                    target_rate: 190,
+                    configured: 1,
+                    sustainable: 5,
+                },
+                Capacity {
+                    device_type: "PC".to_string(),
+                    target_rate: 1000,
+                    configured: 1,
+                    sustainable: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_duplicate_device_names() {
+        let findings = check_config(&config(&format!("devices:{}{}", DEVICE, DEVICE)));
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].level, Level::Problem);
+        assert!(findings[0].message.contains("avr-1"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Estimates of what each account earns, from how its balance changes.
+
+use crate::api::{Client, PriceOracle};
+use crate::control::FleetControl;
+use crate::stats::FleetStats;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+const SECS_PER_DAY: f64 = 86400f64;
+
+/// First and last balance seen for an account.
+struct Samples {
+    first: (Instant, f64),
+    last: (Instant, f64),
+}
+
+#[derive(Default)]
+pub struct Earnings {
+    accounts: Mutex<BTreeMap<String, Samples>>,
+}
+
+impl Earnings {
+    /// Records the balance of `username` seen at `at`. A balance that went
+    /// down, as after a transfer, starts the estimate over.
+    pub fn record(&self, username: &str, balance: f64, at: Instant) {
+        let mut accounts = self.accounts.lock().unwrap();
+        match accounts.get_mut(username) {
+            Some(samples) if balance >= samples.last.1 => samples.last = (at, balance),
+            _ => {
+                accounts.insert(
+                    username.to_string(),
+                    Samples {
+                        first: (at, balance),
+                        last: (at, balance),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Estimated DUCO earned per day by `username`, once its balance was seen
+    /// at two different times.
+    pub fn per_day(&self, username: &str) -> Option<f64> {
+        let accounts = self.accounts.lock().unwrap();
+        let samples = accounts.get(username)?;
+        let secs = samples.last.0.duration_since(samples.first.0).as_secs_f64();
+        if secs <= 0f64 {
+            return None;
+        }
+
+        Some((samples.last.1 - samples.first.1) / secs * SECS_PER_DAY)
+    }
+
+    /// Starts every estimate over from the last balance seen.
+    pub fn reset(&self) {
+        for samples in self.accounts.lock().unwrap().values_mut() {
+            samples.first = samples.last;
+        }
+    }
+}
+
+/// Fetches the balance of each of `usernames` every `interval` into the
+/// fleet's [`Earnings`], while any device of `control` is mining. The shared
+/// [`PriceOracle`] is refreshed along with them.
+pub fn start_polling(
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+    usernames: Vec<String>,
+    interval: Duration,
+) {
+    std::thread::spawn(move || loop {
+        control.wait_until_active();
+        for username in usernames.iter() {
+            match Client::shared().get_balance(username) {
+                Ok(balance) => fleet
+                    .earnings()
+                    .record(username, balance.balance, Instant::now()),
+                Err(e) => debug!("could not fetch the balance of {}: {}", username, e),
+            }
+        }
+        PriceOracle::shared().price(Client::shared());
+        std::thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_from_balance_changes() {
+        let earnings = Earnings::default();
+        let start = Instant::now();
+
+        earnings.record("alice", 10.0, start);
+        assert_eq!(earnings.per_day("alice"), None);
+        earnings.record("alice", 12.0, start + Duration::from_secs(43200));
+        assert_eq!(earnings.per_day("alice"), Some(4.0));
+
+        earnings.record("alice", 1.0, start + Duration::from_secs(50000));
+        assert_eq!(earnings.per_day("alice"), None);
+        assert_eq!(earnings.per_day("bob"), None);
+    }
+}
@@ -0,0 +1,253 @@
+//! Alerts mailed over SMTP for the few events an unattended rig cannot
+//! recover from alone, configured in the `email` section of the config file.
+
+use duino_miner::config::EmailConfig;
+use duino_miner::control::FleetControl;
+use duino_miner::events::EventKind;
+use duino_miner::stats::FleetStats;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::{info, warn};
+
+/// How often the fleet is checked.
+const TICK: Duration = Duration::from_secs(10);
+
+/// Rejected shares seen per account, to spot a burst of them.
+struct Rejections {
+    window: Duration,
+    max: u64,
+    samples: BTreeMap<String, VecDeque<(Instant, u64)>>,
+}
+
+impl Rejections {
+    fn new(window: Duration, max: u64) -> Self {
+        Self {
+            window,
+            max,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// Records that `account` had `rejected` shares rejected in total at
+    /// `now`. Returns how many were rejected within the window when that
+    /// reaches the limit, then starts counting afresh.
+    fn update(&mut self, account: &str, rejected: u64, now: Instant) -> Option<u64> {
+        let samples = self.samples.entry(account.to_string()).or_default();
+        // Fewer rejections than before means the stats were reset.
+        if samples.back().is_some_and(|&(_, last)| rejected < last) {
+            samples.clear();
+        }
+        while samples
+            .front()
+            .is_some_and(|&(at, _)| now - at > self.window)
+        {
+            samples.pop_front();
+        }
+        samples.push_back((now, rejected));
+
+        let burst = rejected - samples.front().map_or(rejected, |&(_, first)| first);
+        if burst < self.max {
+            return None;
+        }
+        samples.clear();
+        samples.push_back((now, rejected));
+
+        Some(burst)
+    }
+}
+
+/// Whether the whole fleet has been offline long enough to report.
+#[derive(Default)]
+struct Offline {
+    since: Option<Instant>,
+    reported: bool,
+}
+
+impl Offline {
+    /// Records whether the fleet is `offline` at `now`, returning true once
+    /// it has been for `after`.
+    fn update(&mut self, offline: bool, after: Duration, now: Instant) -> bool {
+        if !offline {
+            *self = Self::default();
+            return false;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if self.reported || now - since < after {
+            return false;
+        }
+        self.reported = true;
+
+        true
+    }
+}
+
+pub struct EmailNotifier {
+    config: EmailConfig,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    mailer: SmtpTransport,
+}
+
+impl EmailNotifier {
+    /// Parses the addresses of `config` and sets up the SMTP connection, so
+    /// mistakes show at startup rather than when an alert is due.
+    pub fn new(config: EmailConfig) -> Result<Self, String> {
+        let from: Mailbox = config
+            .from
+            .parse()
+            .map_err(|e| format!("email: from {}: {}", config.from, e))?;
+        let to = config
+            .to
+            .iter()
+            .map(|to| to.parse().map_err(|e| format!("email: to {}: {}", to, e)))
+            .collect::<Result<Vec<Mailbox>, String>>()?;
+        if to.is_empty() {
+            return Err("email: no recipients in to".to_string());
+        }
+
+        let mut mailer = SmtpTransport::starttls_relay(&config.smtp_host)
+            .map_err(|e| format!("email: {}: {}", config.smtp_host, e))?
+            .port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            mailer = mailer.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            config,
+            from,
+            to,
+            mailer: mailer.build(),
+        })
+    }
+
+    fn send(&self, subject: &str, body: String) {
+        let message = self
+            .to
+            .iter()
+            .fold(Message::builder().from(self.from.clone()), |builder, to| {
+                builder.to(to.clone())
+            })
+            .subject(format!("duino-miner: {}", subject))
+            .body(body);
+
+        let result = match message {
+            Ok(message) => self
+                .mailer
+                .send(&message)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(()) => info!("mailed alert: {}", subject),
+            Err(e) => warn!("could not mail alert {}: {}", subject, e),
+        }
+    }
+
+    /// Mails alerts in the background while any device of `control` is
+    /// mining: when the whole fleet is offline, when an account's shares are
+    /// rejected in a burst, and when devices give up or are braked.
+    pub fn start(self, fleet: Arc<FleetStats>, control: Arc<FleetControl>) {
+        std::thread::spawn(move || {
+            let offline_after = Duration::from_secs(self.config.offline_after_secs);
+            let mut offline = Offline::default();
+            let mut rejections = Rejections::new(
+                Duration::from_secs(self.config.rejection_window_secs),
+                self.config.max_rejections,
+            );
+            let mut last_event = 0;
+
+            loop {
+                let now = Instant::now();
+
+                let events = fleet.events().since(last_event);
+                last_event = events.last().map_or(last_event, |e| e.id);
+                for event in events.iter() {
+                    let subject = match event.kind {
+                        EventKind::GaveUp => format!("{} gave up", event.device),
+                        EventKind::SafetyBrake => "safety brake tripped".to_string(),
+                        _ => continue,
+                    };
+                    self.send(&subject, format!("{}: {}", event.device, event.message));
+                }
+
+                let active: Vec<_> = fleet
+                    .devices()
+                    .iter()
+                    .zip(control.devices())
+                    .filter(|(_, device)| !device.is_paused())
+                    .collect();
+                let is_offline =
+                    !active.is_empty() && active.iter().all(|(stats, _)| !stats.is_connected());
+                if offline.update(is_offline, offline_after, now) {
+                    self.send(
+                        "fleet offline",
+                        format!(
+                            "No device has been connected for {} minutes.\n\n{}",
+                            offline_after.as_secs() / 60,
+                            fleet.summary()
+                        ),
+                    );
+                }
+
+                for (account, totals) in fleet.totals_by(&control, |d| Some(d.device().username)) {
+                    if let Some(burst) = rejections.update(&account, totals.rejected, now) {
+                        self.send(
+                            &format!("rejection storm on {}", account),
+                            format!(
+                                "{} shares of {} were rejected within {} minutes.\n\n{}",
+                                burst,
+                                account,
+                                self.config.rejection_window_secs / 60,
+                                fleet.summary()
+                            ),
+                        );
+                    }
+                }
+
+                std::thread::sleep(TICK);
+                control.wait_until_active();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_offline_fleet_once() {
+        let mut offline = Offline::default();
+        let after = Duration::from_secs(600);
+        let now = Instant::now();
+
+        assert!(!offline.update(true, after, now));
+        assert!(offline.update(true, after, now + after));
+        assert!(!offline.update(true, after, now + after * 2));
+        assert!(!offline.update(false, after, now + after * 2));
+    }
+
+    #[test]
+    fn reports_rejection_burst_within_window() {
+        let window = Duration::from_secs(300);
+        let mut rejections = Rejections::new(window, 20);
+        let now = Instant::now();
+
+        assert_eq!(rejections.update("alice", 5, now), None);
+        assert_eq!(rejections.update("alice", 20, now + window * 2), None);
+        assert_eq!(
+            rejections.update("alice", 40, now + window * 2 + TICK),
+            Some(20)
+        );
+        assert_eq!(rejections.update("alice", 45, now + window * 3), None);
+        assert_eq!(rejections.update("bob", 30, now), None);
+    }
+}
@@ -0,0 +1,156 @@
+//! Encryption of config files at rest, with AES-256-GCM under a key derived
+//! by scrypt from a passphrase or the contents of a key file.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use data_encoding::BASE64;
+use rand::Rng;
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// First line of an encrypted config file.
+pub const MAGIC: &str = "duino-miner encrypted config v1";
+
+/// Environment variable naming a key file.
+pub const KEY_FILE_ENV: &str = "DUINO_CONFIG_KEY_FILE";
+
+/// Environment variable holding a passphrase.
+pub const PASSPHRASE_ENV: &str = "DUINO_CONFIG_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// scrypt cost, about 32 MiB and a tenth of a second per load.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+static DEFAULT_KEY: OnceLock<Key> = OnceLock::new();
+
+/// The secret a config file is encrypted with.
+#[derive(Clone)]
+pub struct Key {
+    secret: Vec<u8>,
+}
+
+impl Key {
+    pub fn passphrase(passphrase: &str) -> Self {
+        Self {
+            secret: passphrase.as_bytes().to_vec(),
+        }
+    }
+
+    /// Reads a key file. Trailing whitespace is ignored, so the file may be
+    /// written by hand.
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut secret = std::fs::read(path)?;
+        while secret.last().is_some_and(u8::is_ascii_whitespace) {
+            secret.pop();
+        }
+
+        Ok(Self { secret })
+    }
+
+    /// The key set with [`Key::set_default`], else the key file named by
+    /// [`KEY_FILE_ENV`], else the passphrase in [`PASSPHRASE_ENV`].
+    pub fn from_env() -> Result<Self, String> {
+        if let Some(key) = DEFAULT_KEY.get() {
+            return Ok(key.clone());
+        }
+        if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+            return Self::from_file(&path).map_err(|e| format!("{}: {}", path, e));
+        }
+        if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+            return Ok(Self::passphrase(&passphrase));
+        }
+
+        Err(format!(
+            "the config is encrypted; pass --key-file or set {} or {}",
+            KEY_FILE_ENV, PASSPHRASE_ENV
+        ))
+    }
+
+    /// Makes `key` the one [`Key::from_env`] returns for the rest of the
+    /// process. Only the first call has an effect.
+    pub fn set_default(key: Key) {
+        let _ = DEFAULT_KEY.set(key);
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Aes256Gcm {
+        let params = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P, 32).unwrap();
+        let mut key = [0u8; 32];
+        scrypt::scrypt(&self.secret, salt, &params, &mut key).unwrap();
+
+        Aes256Gcm::new(&key.into())
+    }
+}
+
+/// Whether `contents` is an encrypted config file.
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.starts_with(MAGIC)
+}
+
+/// Encrypts a config file's contents under `key`.
+pub fn encrypt(plaintext: &str, key: &Key) -> String {
+    let mut rng = rand::thread_rng();
+    let salt: [u8; SALT_LEN] = rng.gen();
+    let nonce: [u8; NONCE_LEN] = rng.gen();
+
+    let ciphertext = key
+        .cipher(&salt)
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .unwrap();
+
+    let mut sealed = salt.to_vec();
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+
+    format!("{}\n{}\n", MAGIC, BASE64.encode(&sealed))
+}
+
+/// Decrypts what [`encrypt`] wrote, failing on a wrong key or a damaged file.
+pub fn decrypt(contents: &str, key: &Key) -> Result<String, String> {
+    let encoded: String = contents
+        .strip_prefix(MAGIC)
+        .ok_or("not an encrypted config")?
+        .split_whitespace()
+        .collect();
+    let sealed = BASE64
+        .decode(encoded.as_bytes())
+        .map_err(|e| format!("damaged encrypted config: {}", e))?;
+    if sealed.len() < SALT_LEN + NONCE_LEN {
+        return Err("damaged encrypted config: too short".to_string());
+    }
+
+    let (salt, rest) = sealed.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let plaintext = key
+        .cipher(salt)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "could not decrypt the config: wrong key or damaged file".to_string())?;
+
+    String::from_utf8(plaintext).map_err(|_| "decrypted config is not UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let key = Key::passphrase("correct horse");
+        let sealed = encrypt("devices: []\n", &key);
+
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("devices"));
+        assert_eq!(decrypt(&sealed, &key).unwrap(), "devices: []\n");
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let sealed = encrypt("devices: []\n", &Key::passphrase("correct horse"));
+
+        assert!(decrypt(&sealed, &Key::passphrase("battery staple")).is_err());
+    }
+}
@@ -10,6 +10,108 @@ pub enum MinerError {
     InvalidUTF8,
     #[error("Received malformed job: `{0}`")]
     MalformedJob(String),
+    #[error("Job difficulty out of range: {0}")]
+    DifficultyOutOfRange(u64),
+    #[error("Received message longer than {0} bytes")]
+    MessageTooLong(usize),
+    #[error("API request failed: {0}")]
+    Api(String),
+    #[error("Hasher self-test failed: {0}")]
+    SelfTest(String),
+    #[error("Panicked: {0}")]
+    Panicked(String),
     #[error("unknown error")]
     Unknown,
 }
+
+/// How a device should react to an error, from least to most drastic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Reconnect right away; the next job will likely be fine.
+    Retry,
+    /// Reconnect after a wait that grows while the error repeats.
+    Backoff,
+    /// Stop the device until it is resumed; retrying fails the same way.
+    Pause,
+    /// Stop the whole miner.
+    Fatal,
+}
+
+impl MinerError {
+    pub fn severity(&self) -> Severity {
+        match self {
+            MinerError::InvalidUTF8 | MinerError::MalformedJob(_) => Severity::Retry,
+            MinerError::Connection
+            | MinerError::SendCommand
+            | MinerError::RecvCommand
+            | MinerError::DifficultyOutOfRange(_)
+            | MinerError::MessageTooLong(_)
+            | MinerError::Api(_)
+            | MinerError::Panicked(_) => Severity::Backoff,
+            MinerError::SelfTest(_) | MinerError::Unknown => Severity::Fatal,
+        }
+    }
+
+    /// Whether reconnecting, right away or after a wait, may succeed.
+    pub fn is_retryable(&self) -> bool {
+        self.severity() <= Severity::Backoff
+    }
+
+    /// Whether the error shows the pool misbehaving: failing the connection
+    /// or sending garbage.
+    pub fn is_pool_failure(&self) -> bool {
+        self.is_connection_failure()
+            || matches!(
+                self,
+                MinerError::InvalidUTF8
+                    | MinerError::MalformedJob(_)
+                    | MinerError::MessageTooLong(_)
+            )
+    }
+
+    /// Whether the error came from the connection to the pool itself, such as
+    /// a refused connect or a reset.
+    pub fn is_connection_failure(&self) -> bool {
+        matches!(
+            self,
+            MinerError::Connection | MinerError::SendCommand | MinerError::RecvCommand
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbled_messages_retry_at_once() {
+        assert_eq!(
+            MinerError::MalformedJob("x".to_string()).severity(),
+            Severity::Retry
+        );
+    }
+
+    #[test]
+    fn connection_failures_back_off() {
+        assert_eq!(MinerError::RecvCommand.severity(), Severity::Backoff);
+        assert!(MinerError::Connection.is_retryable());
+    }
+
+    #[test]
+    fn bad_pool_messages_back_off() {
+        assert_eq!(
+            MinerError::MessageTooLong(1024).severity(),
+            Severity::Backoff
+        );
+        assert_eq!(
+            MinerError::DifficultyOutOfRange(u64::MAX).severity(),
+            Severity::Backoff
+        );
+    }
+
+    #[test]
+    fn persistent_errors_are_not_retryable() {
+        assert!(!MinerError::SelfTest("x".to_string()).is_retryable());
+        assert!(!MinerError::Unknown.is_retryable());
+    }
+}
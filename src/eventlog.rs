@@ -0,0 +1,86 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+    EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+};
+
+const SOURCE_NAME: &str = "duino-miner";
+
+fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+pub struct EventLogLogger {
+    handle: isize,
+    level: LevelFilter,
+}
+
+impl EventLogLogger {
+    pub fn new(level: LevelFilter) -> std::io::Result<Self> {
+        let source = to_wide(SOURCE_NAME);
+        let handle = unsafe { RegisterEventSourceW(std::ptr::null(), source.as_ptr()) };
+
+        if handle == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Self { handle, level })
+    }
+
+    pub fn init(level: LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
+        let logger = Self::new(level)?;
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(level);
+
+        Ok(())
+    }
+}
+
+impl Drop for EventLogLogger {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+impl Log for EventLogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let event_type = match record.level() {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+
+        let message = to_wide(&format!("{}: {}", record.target(), record.args()));
+        let strings = [message.as_ptr()];
+
+        unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+const MAX_EVENTS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Connected,
+    Disconnected,
+    Rejected,
+    BlockFound,
+    SafetyBrake,
+    GaveUp,
+    DailyReport,
+    TooSlow,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: u64,
+    pub timestamp: u64,
+    pub device: String,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct Events {
+    events: Mutex<VecDeque<Event>>,
+    next_id: AtomicU64,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, device: &str, kind: EventKind, message: String) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut events = self.events.lock().unwrap();
+        if events.len() == MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(Event {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            timestamp,
+            device: device.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    pub fn recent(&self, count: usize) -> Vec<Event> {
+        let events = self.events.lock().unwrap();
+        events.iter().rev().take(count).cloned().collect()
+    }
+
+    /// Events with an id greater than `id`, oldest first. Ids start at 1, so
+    /// polling from 0 returns everything still buffered.
+    pub fn since(&self, id: u64) -> Vec<Event> {
+        let events = self.events.lock().unwrap();
+        events.iter().filter(|e| e.id > id).cloned().collect()
+    }
+}
@@ -0,0 +1,144 @@
+//! C interface for embedding the miner in other programs. See
+//! `include/duino_miner.h` for the declarations.
+
+use crate::config::Config;
+use crate::miner::Miner;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::error;
+
+pub type EventCallback = extern "C" fn(
+    device: *const c_char,
+    kind: c_int,
+    message: *const c_char,
+    user_data: *mut c_void,
+);
+
+struct Callback {
+    callback: EventCallback,
+    user_data: *mut c_void,
+}
+
+// The embedder promises `user_data` may be used from the event thread.
+unsafe impl Send for Callback {}
+
+struct Running {
+    miner: Miner,
+    done: Arc<AtomicBool>,
+    forwarder: JoinHandle<()>,
+}
+
+static CALLBACK: Mutex<Option<Callback>> = Mutex::new(None);
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+fn to_cstring(s: String) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+/// Registers `callback` to receive miner events, replacing any previous one.
+/// Passing a null callback unregisters it. Callbacks are made from a
+/// background thread; the strings are only valid for the duration of the
+/// call.
+#[no_mangle]
+pub extern "C" fn duino_miner_set_event_callback(
+    callback: Option<EventCallback>,
+    user_data: *mut c_void,
+) {
+    *CALLBACK.lock().unwrap() = callback.map(|callback| Callback {
+        callback,
+        user_data,
+    });
+}
+
+/// Starts mining with the devices in `config_json`, the JSON form of the
+/// YAML config file. Returns 0 on success, -1 if the config is invalid or a
+/// miner is already running.
+///
+/// # Safety
+///
+/// `config_json` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn duino_miner_start(config_json: *const c_char) -> c_int {
+    if config_json.is_null() {
+        return -1;
+    }
+
+    let mut running = RUNNING.lock().unwrap();
+    if running.is_some() {
+        error!("miner is already running");
+        return -1;
+    }
+
+    let config = match CStr::from_ptr(config_json)
+        .to_str()
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str::<Config>(s).map_err(|e| e.to_string()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            error!("invalid config: {}", e);
+            return -1;
+        }
+    };
+
+    let mut miner = Miner::new(config, Vec::new());
+    miner.start();
+
+    let events = miner.fleet().events().clone();
+    let done = Arc::new(AtomicBool::new(false));
+    let forwarder = {
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut last = 0;
+            while !done.load(Ordering::Relaxed) {
+                for event in events.since(last) {
+                    last = event.id;
+
+                    if let Some(cb) = CALLBACK.lock().unwrap().as_ref() {
+                        let device = to_cstring(event.device);
+                        let message = to_cstring(event.message);
+                        (cb.callback)(
+                            device.as_ptr(),
+                            event.kind as c_int,
+                            message.as_ptr(),
+                            cb.user_data,
+                        );
+                    }
+                }
+
+                std::thread::sleep(Duration::from_millis(250));
+            }
+        })
+    };
+
+    *running = Some(Running {
+        miner,
+        done,
+        forwarder,
+    });
+
+    0
+}
+
+/// Stops the running miner, blocking until all devices have disconnected.
+/// Returns 0 on success, -1 if no miner is running.
+#[no_mangle]
+pub extern "C" fn duino_miner_stop() -> c_int {
+    let running = RUNNING.lock().unwrap().take();
+
+    match running {
+        Some(mut running) => {
+            running.miner.stop();
+            running.done.store(true, Ordering::Relaxed);
+            let _ = running.forwarder.join();
+            0
+        }
+        None => -1,
+    }
+}
@@ -0,0 +1,175 @@
+//! Status LEDs on GPIO pins, driven through sysfs, so a headless Pi shows at
+//! a glance whether its devices are mining.
+
+use duino_miner::control::FleetControl;
+use duino_miner::events::EventKind;
+use duino_miner::stats::FleetStats;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+const GPIO_ROOT: &str = "/sys/class/gpio";
+
+/// How often the LEDs are updated.
+const TICK: Duration = Duration::from_millis(50);
+
+/// How long the share LED lights up for each accepted share.
+const SHARE_BLINK: Duration = Duration::from_millis(150);
+
+/// How long the status LED keeps blinking after an error.
+const ERROR_BLINK: Duration = Duration::from_secs(5);
+
+/// Half a period of the status LED's blinking after an error.
+const ERROR_HALF_PERIOD: Duration = Duration::from_millis(100);
+
+/// An output pin exported through sysfs.
+pub struct Pin {
+    number: u32,
+    value: PathBuf,
+}
+
+impl Pin {
+    pub fn export(number: u32) -> std::io::Result<Self> {
+        let root = PathBuf::from(GPIO_ROOT);
+        let dir = root.join(format!("gpio{}", number));
+        if !dir.exists() {
+            std::fs::write(root.join("export"), number.to_string())?;
+        }
+        std::fs::write(dir.join("direction"), "out")?;
+
+        Ok(Self {
+            number,
+            value: dir.join("value"),
+        })
+    }
+
+    fn set(&self, on: bool) {
+        if let Err(e) = std::fs::write(&self.value, if on { "1" } else { "0" }) {
+            warn!("could not set GPIO {}: {}", self.number, e);
+        }
+    }
+}
+
+/// What the LEDs show, worked out from the fleet's counters and events.
+#[derive(Default)]
+struct Indicator {
+    shares: u64,
+    share_until: Option<Instant>,
+    error_since: Option<Instant>,
+}
+
+impl Indicator {
+    /// Records the fleet's state at `now` and returns whether the status and
+    /// share LEDs should be lit. The status LED is solid while every device
+    /// is connected and blinks fast for a while after an error.
+    fn update(&mut self, connected: bool, shares: u64, error: bool, now: Instant) -> (bool, bool) {
+        // Fewer shares than before means the stats were reset.
+        if shares > self.shares {
+            self.share_until = Some(now + SHARE_BLINK);
+        }
+        self.shares = shares;
+
+        if error {
+            self.error_since = Some(now);
+        }
+        let status = match self.error_since {
+            Some(since) if now - since < ERROR_BLINK => {
+                ((now - since).as_millis() / ERROR_HALF_PERIOD.as_millis()) % 2 == 0
+            }
+            _ => connected,
+        };
+        let share = self.share_until.is_some_and(|until| now < until);
+
+        (status, share)
+    }
+}
+
+fn is_error(kind: EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Disconnected | EventKind::Rejected | EventKind::SafetyBrake | EventKind::GaveUp
+    )
+}
+
+/// Drives `status`, and `share` if given, from the fleet in the background.
+/// Without a share pin, shares briefly flip the status LED instead.
+pub fn start(status: Pin, share: Option<Pin>, fleet: Arc<FleetStats>, control: Arc<FleetControl>) {
+    std::thread::spawn(move || {
+        let mut indicator = Indicator::default();
+        let mut last_event = 0;
+
+        loop {
+            if control.is_idle() {
+                status.set(false);
+                share.iter().for_each(|pin| pin.set(false));
+                control.wait_until_active();
+            }
+
+            let devices = fleet.devices();
+            let connected = devices.iter().all(|d| d.is_connected());
+            let shares = devices.iter().map(|d| d.accepted()).sum();
+            let events = fleet.events().since(last_event);
+            last_event = events.last().map_or(last_event, |e| e.id);
+            let error = events.iter().any(|e| is_error(e.kind));
+
+            let (status_on, share_on) = indicator.update(connected, shares, error, Instant::now());
+            match &share {
+                Some(pin) => {
+                    status.set(status_on);
+                    pin.set(share_on);
+                }
+                None => status.set(status_on != share_on),
+            }
+
+            std::thread::sleep(TICK);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solid_only_while_all_connected() {
+        let mut indicator = Indicator::default();
+        let now = Instant::now();
+
+        assert_eq!(indicator.update(true, 0, false, now), (true, false));
+        assert_eq!(indicator.update(false, 0, false, now), (false, false));
+    }
+
+    #[test]
+    fn blinks_on_share() {
+        let mut indicator = Indicator::default();
+        let now = Instant::now();
+
+        assert_eq!(indicator.update(true, 1, false, now), (true, true));
+        assert_eq!(
+            indicator.update(true, 1, false, now + SHARE_BLINK),
+            (true, false)
+        );
+        assert_eq!(
+            indicator.update(true, 0, false, now + SHARE_BLINK),
+            (true, false)
+        );
+    }
+
+    #[test]
+    fn blinks_fast_after_error() {
+        let mut indicator = Indicator::default();
+        let now = Instant::now();
+
+        assert!(indicator.update(true, 0, true, now).0);
+        assert!(!indicator.update(true, 0, false, now + ERROR_HALF_PERIOD).0);
+        assert!(
+            indicator
+                .update(true, 0, false, now + ERROR_HALF_PERIOD * 2)
+                .0
+        );
+        assert!(!indicator.update(false, 0, false, now + ERROR_BLINK).0);
+    }
+}
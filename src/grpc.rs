@@ -0,0 +1,270 @@
+use crate::auth::Auth;
+
+use duino_miner::config::ServerConfig;
+use duino_miner::control::{DeviceControl, FleetControl};
+use duino_miner::stats::FleetStats;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+mod proto {
+    tonic::include_proto!("duino_miner");
+}
+
+use proto::control_server::{Control, ControlServer};
+use proto::{
+    ControlReply, DeviceRequest, DeviceUpdate, ReloadRequest, SetTargetRateRequest,
+    WatchStatsRequest,
+};
+
+struct ControlService {
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+    auth: Auth,
+}
+
+fn reply(message: String) -> Response<ControlReply> {
+    info!("{}", message);
+    Response::new(ControlReply { message })
+}
+
+fn unknown_device() -> Status {
+    Status::not_found("unknown device")
+}
+
+impl ControlService {
+    /// Returns the status to fail with when the request is not allowed.
+    /// Control RPCs require credentials; stats require them only when set.
+    fn deny<T>(&self, request: &Request<T>, control: bool) -> Option<Status> {
+        if !self.auth.is_enabled() {
+            return if control {
+                Some(Status::permission_denied(
+                    "control API disabled, configure a token or password",
+                ))
+            } else {
+                None
+            };
+        }
+
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+
+        if self.auth.check(authorization) {
+            None
+        } else {
+            Some(Status::unauthenticated("unauthorized"))
+        }
+    }
+
+    /// Devices picked by a device name or a `group:` selector, or `None` if
+    /// there are none.
+    fn select(&self, selector: &str) -> Option<Vec<&Arc<DeviceControl>>> {
+        Some(self.control.select(selector)).filter(|devices| !devices.is_empty())
+    }
+
+    fn updates(&self) -> Vec<DeviceUpdate> {
+        self.fleet
+            .devices()
+            .iter()
+            .zip(self.control.devices().iter())
+            .map(|(stats, control)| DeviceUpdate {
+                name: stats.name().to_string(),
+                username: control.device().username,
+                group: control.device().group.unwrap_or_default(),
+                connected: stats.is_connected(),
+                paused: control.is_paused(),
+                target_rate: control.target_rate(),
+                reported_rate: stats.reported_rate(),
+                accepted: stats.accepted(),
+                rejected: stats.rejected(),
+                blocks: stats.blocks(),
+                bytes_sent: stats.bytes_sent(),
+                bytes_received: stats.bytes_received(),
+                panics: stats.panics(),
+                connected_secs: stats.connected_time().as_secs_f64(),
+                disconnects: stats.disconnects(),
+                mtbf_secs: stats
+                    .mean_time_between_failures()
+                    .map_or(0.0, |mtbf| mtbf.as_secs_f64()),
+            })
+            .collect()
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn pause(
+        &self,
+        request: Request<DeviceRequest>,
+    ) -> Result<Response<ControlReply>, Status> {
+        if let Some(status) = self.deny(&request, true) {
+            return Err(status);
+        }
+
+        let name = request.into_inner().device_name;
+        for device in self.select(&name).ok_or_else(unknown_device)? {
+            device.set_paused(true);
+        }
+        Ok(reply(format!("{} paused via gRPC", name)))
+    }
+
+    async fn resume(
+        &self,
+        request: Request<DeviceRequest>,
+    ) -> Result<Response<ControlReply>, Status> {
+        if let Some(status) = self.deny(&request, true) {
+            return Err(status);
+        }
+
+        let name = request.into_inner().device_name;
+        for device in self.select(&name).ok_or_else(unknown_device)? {
+            device.set_paused(false);
+        }
+        Ok(reply(format!("{} resumed via gRPC", name)))
+    }
+
+    async fn reconnect(
+        &self,
+        request: Request<DeviceRequest>,
+    ) -> Result<Response<ControlReply>, Status> {
+        if let Some(status) = self.deny(&request, true) {
+            return Err(status);
+        }
+
+        let name = request.into_inner().device_name;
+        for device in self.select(&name).ok_or_else(unknown_device)? {
+            device.request_reconnect();
+        }
+        Ok(reply(format!("{} reconnect requested via gRPC", name)))
+    }
+
+    async fn set_target_rate(
+        &self,
+        request: Request<SetTargetRateRequest>,
+    ) -> Result<Response<ControlReply>, Status> {
+        if let Some(status) = self.deny(&request, true) {
+            return Err(status);
+        }
+
+        let request = request.into_inner();
+        if request.target_rate == 0 {
+            return Err(Status::invalid_argument("target_rate must be positive"));
+        }
+
+        for device in self
+            .select(&request.device_name)
+            .ok_or_else(unknown_device)?
+        {
+            device.set_target_rate(request.target_rate);
+            if request.persist {
+                self.control
+                    .persist_target_rate(device)
+                    .map_err(|e| Status::internal(format!("could not persist: {}", e)))?;
+            }
+        }
+        Ok(reply(format!(
+            "{} target rate set to {} via gRPC",
+            request.device_name, request.target_rate
+        )))
+    }
+
+    async fn reload(
+        &self,
+        request: Request<ReloadRequest>,
+    ) -> Result<Response<ControlReply>, Status> {
+        if let Some(status) = self.deny(&request, true) {
+            return Err(status);
+        }
+
+        let updated = self
+            .control
+            .reload()
+            .map_err(|e| Status::internal(format!("reload failed: {}", e)))?;
+        Ok(reply(format!("{} devices updated", updated)))
+    }
+
+    type WatchStatsStream = ReceiverStream<Result<DeviceUpdate, Status>>;
+
+    async fn watch_stats(
+        &self,
+        request: Request<WatchStatsRequest>,
+    ) -> Result<Response<Self::WatchStatsStream>, Status> {
+        if let Some(status) = self.deny(&request, false) {
+            return Err(status);
+        }
+
+        let interval = match request.into_inner().interval_ms {
+            0 => Duration::from_secs(1),
+            ms => Duration::from_millis(ms as u64),
+        };
+
+        let service = ControlService {
+            fleet: self.fleet.clone(),
+            control: self.control.clone(),
+            auth: self.auth.clone(),
+        };
+        let (tx, rx) = mpsc::channel(service.fleet.devices().len().max(1));
+
+        tokio::spawn(async move {
+            loop {
+                for update in service.updates() {
+                    if tx.send(Ok(update)).await.is_err() {
+                        return;
+                    }
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+pub fn start(
+    bind: &str,
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+    config: &ServerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = bind.parse()?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let service = ControlService {
+        fleet,
+        control,
+        auth: Auth::new(config),
+    };
+
+    #[allow(unused_mut)]
+    let mut builder = tonic::transport::Server::builder();
+    match config.tls()? {
+        #[cfg(feature = "tls")]
+        Some(tls) => {
+            let identity = tonic::transport::Identity::from_pem(tls.cert, tls.key);
+            builder =
+                builder.tls_config(tonic::transport::ServerTlsConfig::new().identity(identity))?;
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(_) => return Err("built without the tls feature".into()),
+        None => {}
+    }
+
+    info!("gRPC server listening on {}", addr);
+
+    std::thread::spawn(move || {
+        let server = builder.add_service(ControlServer::new(service)).serve(addr);
+
+        if let Err(e) = runtime.block_on(server) {
+            log::error!("gRPC server exited with error: {}", e);
+        }
+    });
+
+    Ok(())
+}
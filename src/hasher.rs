@@ -1,8 +1,10 @@
-use duino_miner::error::MinerError;
+use crate::error::MinerError;
 
 use hex::FromHex;
 use sha1::{Digest, Sha1};
 
+use std::ops::Range;
+
 type BlockHash = [u8; 20];
 
 fn to_block_hash(s: &str) -> Result<BlockHash, MinerError> {
@@ -13,15 +15,30 @@ fn precompute_sha1(last_block_hash: &BlockHash) -> Sha1 {
     let mut hasher = Sha1::new();
 
     let mut encode_slice: [u8; 40] = [0; 40];
-    hex::encode_to_slice(&last_block_hash, &mut encode_slice).unwrap();
+    hex::encode_to_slice(last_block_hash, &mut encode_slice).unwrap();
 
-    sha1::Digest::update(&mut hasher, &encode_slice);
+    sha1::Digest::update(&mut hasher, encode_slice);
 
     hasher
 }
 
-fn next_compute_numeric(mut hasher: Sha1, duco_numeric_result: u32) -> BlockHash {
-    sha1::Digest::update(&mut hasher, duco_numeric_result.to_string().as_bytes());
+/// Writes `n` in decimal to the end of `buf`, without allocating, and returns
+/// the digits.
+fn decimal(mut n: u64, buf: &mut [u8; 20]) -> &[u8] {
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buf[i..];
+        }
+    }
+}
+
+fn next_compute_numeric(mut hasher: Sha1, duco_numeric_result: u64) -> BlockHash {
+    let mut digits = [0u8; 20];
+    sha1::Digest::update(&mut hasher, decimal(duco_numeric_result, &mut digits));
     let h = hasher.finalize();
 
     let mut hash: [u8; 20] = [0; 20];
@@ -30,7 +47,27 @@ fn next_compute_numeric(mut hasher: Sha1, duco_numeric_result: u32) -> BlockHash
     hash
 }
 
-#[derive(Clone)]
+/// Known answers as `(last_block_hash, expected_hash, nonce)`, computed with
+/// an independent SHA-1 implementation.
+const KNOWN_ANSWERS: [(&str, &str, u64); 3] = [
+    (
+        "0123456789abcdef0123456789abcdef01234567",
+        "9724c7351bf7865d8174d7876eeeda184f9e16f1",
+        0,
+    ),
+    (
+        "ba29a15896fd2d792d5c4b60668bf2b9feebc51d",
+        "db62f8b15be841b3f5aaffb2df965fbe7b483e31",
+        4242,
+    ),
+    (
+        "ffffffffffffffffffffffffffffffffffffffff",
+        "f0ac1fe16447bd5092d5d74b99e9b4cf067a7cc0",
+        99999,
+    ),
+];
+
+#[derive(Clone, Default)]
 pub struct Sha1Hasher {}
 
 impl Sha1Hasher {
@@ -42,18 +79,80 @@ impl Sha1Hasher {
         &self,
         last_block_hash: &str,
         expected_hash: &str,
-        diff: u32,
-    ) -> Result<u32, MinerError> {
+        diff: u64,
+    ) -> Result<u64, MinerError> {
+        self.search(last_block_hash, expected_hash, 0, diff, |_| {})
+    }
+
+    /// Checks both nonce searches against [`KNOWN_ANSWERS`]. A miscompiled
+    /// or misconfigured hasher would otherwise only show as rejected shares.
+    pub fn self_test(&self) -> Result<(), MinerError> {
+        for (last_block_hash, expected_hash, nonce) in KNOWN_ANSWERS {
+            let found = self.search(last_block_hash, expected_hash, 0, nonce + 1, |_| {});
+            if found.as_ref().ok() != Some(&nonce) {
+                return Err(MinerError::SelfTest(format!(
+                    "search for {} returned {:?} instead of {}",
+                    expected_hash, found, nonce
+                )));
+            }
+
+            let found = self.search_range(last_block_hash, expected_hash, nonce..nonce + 1);
+            if found.as_ref().ok() != Some(&nonce) {
+                return Err(MinerError::SelfTest(format!(
+                    "range search for {} returned {:?} instead of {}",
+                    expected_hash, found, nonce
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Searches the nonces in `nonces` only, so a large job can be split
+    /// across threads.
+    pub fn search_range(
+        &self,
+        last_block_hash: &str,
+        expected_hash: &str,
+        nonces: Range<u64>,
+    ) -> Result<u64, MinerError> {
         let last_block_hash = to_block_hash(last_block_hash)?;
         let expected_hash = to_block_hash(expected_hash)?;
 
         let hasher = precompute_sha1(&last_block_hash);
-        for duco_numeric_result in 0..diff {
+        for duco_numeric_result in nonces {
+            if next_compute_numeric(hasher.clone(), duco_numeric_result) == expected_hash {
+                return Ok(duco_numeric_result);
+            }
+        }
+
+        Err(MinerError::MalformedJob(
+            "Job impossible to solve.".to_string(),
+        ))
+    }
+
+    /// Searches nonces below `diff` starting at `start` and wrapping around.
+    /// `progress` is called after each hash with the number computed so far.
+    pub fn search(
+        &self,
+        last_block_hash: &str,
+        expected_hash: &str,
+        start: u64,
+        diff: u64,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64, MinerError> {
+        let last_block_hash = to_block_hash(last_block_hash)?;
+        let expected_hash = to_block_hash(expected_hash)?;
+
+        let hasher = precompute_sha1(&last_block_hash);
+        let start = if diff > 0 { start % diff } else { 0 };
+        for (hashes, duco_numeric_result) in (start..diff).chain(0..start).enumerate() {
             let hash = next_compute_numeric(hasher.clone(), duco_numeric_result);
 
             if hash == expected_hash {
                 return Ok(duco_numeric_result);
             }
+            progress(hashes as u64 + 1);
         }
 
         Err(MinerError::MalformedJob(
@@ -61,3 +160,63 @@ impl Sha1Hasher {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAST_BLOCK_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn expected_hash(nonce: u64) -> String {
+        let hasher = precompute_sha1(&to_block_hash(LAST_BLOCK_HASH).unwrap());
+        hex::encode(next_compute_numeric(hasher, nonce))
+    }
+
+    #[test]
+    fn writes_decimal_digits() {
+        let mut buf = [0u8; 20];
+
+        assert_eq!(decimal(0, &mut buf), b"0");
+        assert_eq!(decimal(4242, &mut buf), b"4242");
+        assert_eq!(decimal(u64::MAX, &mut buf), u64::MAX.to_string().as_bytes());
+    }
+
+    #[test]
+    fn passes_self_test() {
+        assert!(Sha1Hasher::new().self_test().is_ok());
+    }
+
+    #[test]
+    fn searches_range_only() {
+        let hasher = Sha1Hasher::new();
+
+        assert_eq!(
+            hasher
+                .search_range(LAST_BLOCK_HASH, &expected_hash(150), 100..200)
+                .unwrap(),
+            150
+        );
+        assert!(hasher
+            .search_range(LAST_BLOCK_HASH, &expected_hash(50), 100..200)
+            .is_err());
+    }
+
+    #[test]
+    fn search_wraps_around_from_start() {
+        let mut hashes = 0;
+        let nonce = Sha1Hasher::new()
+            .search(LAST_BLOCK_HASH, &expected_hash(10), 90, 101, |h| hashes = h)
+            .unwrap();
+
+        assert_eq!(nonce, 10);
+        assert_eq!(hashes, 21);
+    }
+
+    #[test]
+    fn search_fails_outside_range() {
+        let result =
+            Sha1Hasher::new().search(LAST_BLOCK_HASH, &expected_hash(200), 50, 101, |_| {});
+
+        assert!(result.is_err());
+    }
+}
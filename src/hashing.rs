@@ -0,0 +1,348 @@
+use crate::hasher::Sha1Hasher;
+use crate::protocol::Job;
+use crate::solutions::SolutionCache;
+
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+/// Nonces searched by one thread at a time. Larger jobs, such as those of
+/// the PC difficulty tiers, are split into chunks searched in parallel.
+const CHUNK_LEN: u64 = 1 << 20;
+
+/// Interval between progress reports for searches spanning several chunks.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A search requested by one or more devices.
+struct Search {
+    job: Job,
+    max_nonce: u64,
+    /// Start of the next chunk to hand out.
+    next: AtomicU64,
+    /// Nonces in chunks searched so far.
+    searched: AtomicU64,
+    finished: AtomicBool,
+    nonce: Mutex<Option<Option<u64>>>,
+    done: Condvar,
+    started: Instant,
+    last_report: Mutex<Instant>,
+}
+
+impl Search {
+    fn new(job: Job, max_nonce: u64) -> Self {
+        let now = Instant::now();
+
+        Self {
+            job,
+            max_nonce,
+            next: AtomicU64::new(0),
+            searched: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+            nonce: Mutex::new(None),
+            done: Condvar::new(),
+            started: now,
+            last_report: Mutex::new(now),
+        }
+    }
+
+    /// Hands out the next chunk of nonces, or `None` once every chunk is
+    /// handed out or the nonce is found.
+    fn claim(&self) -> Option<Range<u64>> {
+        if self.finished.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let start = self.next.fetch_add(CHUNK_LEN, Ordering::Relaxed);
+        if start >= self.max_nonce {
+            return None;
+        }
+
+        Some(start..(start.saturating_add(CHUNK_LEN)).min(self.max_nonce))
+    }
+
+    /// Records a searched chunk. Returns whether it was the last one.
+    fn searched(&self, nonces: &Range<u64>) -> bool {
+        let searched = self
+            .searched
+            .fetch_add(nonces.end - nonces.start, Ordering::Relaxed)
+            + (nonces.end - nonces.start);
+        if searched >= self.max_nonce {
+            return true;
+        }
+
+        let mut last_report = self.last_report.lock().unwrap();
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            *last_report = Instant::now();
+
+            let elapsed = self.started.elapsed().as_secs_f64();
+            info!(
+                "searched {} of {} nonces ({:.1}%) for {}, {:.2} MH/s",
+                searched,
+                self.max_nonce,
+                searched as f64 / self.max_nonce as f64 * 100f64,
+                self.job.expected_hash,
+                searched as f64 / elapsed / 1000000f64
+            );
+        }
+
+        false
+    }
+
+    /// Share of the nonces handed out so far.
+    fn progress(&self) -> f64 {
+        self.next.load(Ordering::Relaxed) as f64 / self.max_nonce as f64
+    }
+
+    fn wait(&self) -> Option<u64> {
+        let mut nonce = self.nonce.lock().unwrap();
+        loop {
+            if let Some(nonce) = *nonce {
+                return nonce;
+            }
+            nonce = self.done.wait(nonce).unwrap();
+        }
+    }
+}
+
+type JobKey = (String, String);
+
+fn job_key(job: &Job) -> JobKey {
+    (job.last_block_hash.clone(), job.expected_hash.clone())
+}
+
+/// Hands out the next chunk of the queued search least far along, for its
+/// size, dropping searches with nothing left to hand out. Small jobs thus
+/// never wait behind large ones, and large ones are searched side by side,
+/// each getting chunks in proportion to its size.
+fn claim_next(queue: &mut VecDeque<Arc<Search>>) -> Option<(Arc<Search>, Range<u64>)> {
+    loop {
+        let (index, search) = queue
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.progress().total_cmp(&b.progress()))?;
+        match search.claim() {
+            Some(nonces) => return Some((search.clone(), nonces)),
+            None => {
+                queue.remove(index);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Arc<Search>>,
+    in_flight: HashMap<JobKey, Arc<Search>>,
+    stopped: bool,
+}
+
+struct Inner {
+    hasher: Sha1Hasher,
+    solutions: Option<SolutionCache>,
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+impl Inner {
+    fn run(&self) {
+        loop {
+            let (search, nonces) = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if state.stopped {
+                        return;
+                    }
+                    if let Some(claimed) = claim_next(&mut state.queue) {
+                        break claimed;
+                    }
+                    state = self.ready.wait(state).unwrap();
+                }
+            };
+
+            let job = &search.job;
+            match self
+                .hasher
+                .search_range(&job.last_block_hash, &job.expected_hash, nonces.clone())
+            {
+                Ok(nonce) => self.finish(&search, Some(nonce)),
+                Err(_) => {
+                    if search.searched(&nonces) {
+                        self.finish(&search, None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands the result to every device waiting on `search`, the first time
+    /// it is called.
+    fn finish(&self, search: &Search, nonce: Option<u64>) {
+        if search.finished.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        if let (Some(solutions), Some(nonce)) = (&self.solutions, nonce) {
+            solutions.insert(&search.job, nonce);
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .in_flight
+            .remove(&job_key(&search.job));
+
+        *search.nonce.lock().unwrap() = Some(nonce);
+        search.done.notify_all();
+    }
+}
+
+/// Searches nonces for every device on a bounded pool of threads. Devices
+/// asking for a job that is already being searched wait for that search
+/// instead of starting their own.
+pub struct HashService {
+    inner: Arc<Inner>,
+}
+
+impl HashService {
+    /// Starts `threads` hashing threads. Solved jobs are remembered in a
+    /// cache of `solution_cache_len` entries, or not at all when it is 0.
+    pub fn new(threads: usize, solution_cache_len: usize) -> Self {
+        let inner = Arc::new(Inner {
+            hasher: Sha1Hasher::new(),
+            solutions: if solution_cache_len > 0 {
+                Some(SolutionCache::new(solution_cache_len))
+            } else {
+                None
+            },
+            state: Mutex::new(State::default()),
+            ready: Condvar::new(),
+        });
+
+        for _ in 0..threads.max(1) {
+            let inner = inner.clone();
+            std::thread::spawn(move || inner.run());
+        }
+
+        Self { inner }
+    }
+
+    /// Blocks until `job` is solved. Returns `None` if no nonce below the
+    /// job's difficulty solves it.
+    pub fn solve(&self, job: &Job) -> Option<u64> {
+        let max_nonce = job.max_nonce().ok().filter(|&n| n > 0)?;
+
+        let search = {
+            let mut state = self.inner.state.lock().unwrap();
+
+            // Checked under the lock, so a search finishing concurrently is
+            // either found here or still in flight.
+            if let Some(solutions) = &self.inner.solutions {
+                if let Some(nonce) = solutions.get(job) {
+                    return Some(nonce);
+                }
+            }
+
+            match state.in_flight.get(&job_key(job)) {
+                Some(search) => {
+                    debug!("joining search for {}", job.expected_hash);
+                    search.clone()
+                }
+                None => {
+                    let search = Arc::new(Search::new(job.clone(), max_nonce));
+                    state.in_flight.insert(job_key(job), search.clone());
+                    state.queue.push_back(search.clone());
+                    self.inner.ready.notify_all();
+                    search
+                }
+            }
+        };
+
+        search.wait()
+    }
+}
+
+impl Drop for HashService {
+    fn drop(&mut self) {
+        self.inner.state.lock().unwrap().stopped = true;
+        self.inner.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha1::{Digest, Sha1};
+
+    const LAST_BLOCK_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn job(nonce: u64) -> Job {
+        let expected = Sha1::digest(format!("{}{}", LAST_BLOCK_HASH, nonce).as_bytes());
+
+        Job {
+            last_block_hash: LAST_BLOCK_HASH.to_string(),
+            expected_hash: hex::encode(expected),
+            difficulty: 10,
+        }
+    }
+
+    #[test]
+    fn solves_job() {
+        let service = HashService::new(2, 0);
+
+        assert_eq!(service.solve(&job(123)), Some(123));
+    }
+
+    #[test]
+    fn unsolvable_job_has_no_nonce() {
+        let service = HashService::new(1, 0);
+
+        assert_eq!(service.solve(&job(5000)), None);
+    }
+
+    #[test]
+    fn splits_large_job_across_threads() {
+        let service = HashService::new(4, 0);
+        let mut large = job(CHUNK_LEN * 2 + 17);
+        large.difficulty = CHUNK_LEN * 3 / 100;
+
+        assert_eq!(service.solve(&large), Some(CHUNK_LEN * 2 + 17));
+    }
+
+    #[test]
+    fn concurrent_requests_share_result() {
+        let service = Arc::new(HashService::new(2, 16));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let service = service.clone();
+                std::thread::spawn(move || service.solve(&job(777)))
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Some(777));
+        }
+    }
+
+    #[test]
+    fn small_jobs_do_not_wait_behind_large_ones() {
+        let large = Arc::new(Search::new(job(0), CHUNK_LEN * 8));
+        let mut queue: VecDeque<_> = vec![large.clone()].into_iter().collect();
+        for _ in 0..2 {
+            claim_next(&mut queue).unwrap();
+        }
+
+        let small = Arc::new(Search::new(job(1), 1001));
+        queue.push_back(small.clone());
+        let (search, nonces) = claim_next(&mut queue).unwrap();
+        assert!(Arc::ptr_eq(&search, &small));
+        assert_eq!(nonces, 0..1001);
+
+        let (search, _) = claim_next(&mut queue).unwrap();
+        assert!(Arc::ptr_eq(&search, &large));
+    }
+}
@@ -0,0 +1,83 @@
+//! CPU frequency and temperature of the host, where the system exposes
+//! them, so throttling on single-board computers can be matched with dips
+//! in the fleet's hashrate. Read from sysfs, so only available on Linux.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+const CPUS: &str = "/sys/devices/system/cpu";
+const THERMAL_ZONES: &str = "/sys/class/thermal";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct HostStatus {
+    /// Mean current frequency of the CPUs, in MHz.
+    pub cpu_mhz: Option<f64>,
+    /// Temperature of the hottest thermal zone, in degrees Celsius.
+    pub temperature_c: Option<f64>,
+}
+
+impl HostStatus {
+    pub fn read() -> Self {
+        let khz = read_entries(CPUS, "cpu", "cpufreq/scaling_cur_freq");
+        let millidegrees = read_entries(THERMAL_ZONES, "thermal_zone", "temp");
+
+        Self {
+            cpu_mhz: mean(&khz).map(|khz| khz / 1000f64),
+            temperature_c: millidegrees
+                .into_iter()
+                .reduce(f64::max)
+                .map(|millidegrees| millidegrees / 1000f64),
+        }
+    }
+}
+
+/// The number in `file` of each entry of `dir` whose name starts with
+/// `prefix`, skipping those that cannot be read.
+fn read_entries(dir: impl AsRef<Path>, prefix: &str, file: &str) -> Vec<f64> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join(file)).ok())
+        .filter_map(|value| value.trim().parse().ok())
+        .collect()
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_numbers_of_matching_entries() {
+        let dir = std::env::temp_dir().join(format!("host-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (zone, temp) in [("thermal_zone0", "48500\n"), ("thermal_zone1", "61250\n")] {
+            std::fs::create_dir_all(dir.join(zone)).unwrap();
+            std::fs::write(dir.join(zone).join("temp"), temp).unwrap();
+        }
+        std::fs::create_dir_all(dir.join("cooling_device0")).unwrap();
+        std::fs::write(dir.join("cooling_device0").join("temp"), "1\n").unwrap();
+        std::fs::create_dir_all(dir.join("thermal_zone2")).unwrap();
+
+        let mut temps = read_entries(&dir, "thermal_zone", "temp");
+        std::fs::remove_dir_all(&dir).unwrap();
+        temps.sort_by(f64::total_cmp);
+
+        assert_eq!(temps, vec![48500f64, 61250f64]);
+        assert_eq!(mean(&temps), Some(54875f64));
+        assert_eq!(mean(&[]), None);
+    }
+}
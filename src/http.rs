@@ -0,0 +1,345 @@
+use crate::auth::Auth;
+use crate::host::HostStatus;
+
+use duino_miner::config::{ServerConfig, Tls};
+use duino_miner::control::{DeviceControl, FleetControl};
+use duino_miner::stats::{FleetStats, JobStats, Percentiles, SolveLag, Totals};
+
+use std::io::Read;
+use std::sync::Arc;
+
+use log::{info, warn};
+
+use serde::Serialize;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Serialize)]
+struct DeviceStatus {
+    name: String,
+    group: Option<String>,
+    connected: bool,
+    paused: bool,
+    target_rate: u32,
+    reported_rate: f64,
+    accepted: u64,
+    rejected: u64,
+    blocks: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    panics: u64,
+    slow_shares: u64,
+    connected_secs: f64,
+    disconnects: u64,
+    mtbf_secs: Option<f64>,
+    solve_lag: SolveLag,
+    jobs: JobStats,
+    transport: Option<String>,
+}
+
+/// Totals over the devices of a group or account.
+#[derive(Serialize)]
+struct AggregateStatus {
+    name: String,
+    #[serde(flatten)]
+    totals: Totals,
+    paused: usize,
+    /// Estimated DUCO per day, for accounts whose balance was polled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    earnings_per_day: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    devices: Vec<DeviceStatus>,
+    groups: Vec<AggregateStatus>,
+    accounts: Vec<AggregateStatus>,
+    /// Seconds the counters cover, since startup or the last reset.
+    elapsed_secs: f64,
+    job_latency: Percentiles,
+    submit_latency: Percentiles,
+    host: HostStatus,
+}
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+pub struct StatusServer {
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+    auth: Auth,
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header)
+}
+
+/// Whether the query of `url` sets `name`, as `name` or `name=true`.
+fn query_flag(url: &str, name: &str) -> bool {
+    url.split_once('?').is_some_and(|(_, query)| {
+        query
+            .split('&')
+            .any(|param| param == name || param.strip_prefix(name) == Some("=true"))
+    })
+}
+
+/// Largest request body read, so a client cannot exhaust memory.
+const MAX_BODY_LEN: u64 = 1024 * 1024;
+
+/// The body of `request`, or `None` when it is longer than
+/// [`MAX_BODY_LEN`].
+fn read_body(request: &mut Request) -> Option<String> {
+    let mut body = String::new();
+    let _ = request
+        .as_reader()
+        .take(MAX_BODY_LEN + 1)
+        .read_to_string(&mut body);
+
+    if body.len() as u64 > MAX_BODY_LEN {
+        None
+    } else {
+        Some(body)
+    }
+}
+
+fn message(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    #[derive(Serialize)]
+    struct Message<'a> {
+        message: &'a str,
+    }
+
+    json_response(status, &Message { message })
+}
+
+impl StatusServer {
+    pub fn new(fleet: Arc<FleetStats>, control: Arc<FleetControl>, config: &ServerConfig) -> Self {
+        Self {
+            fleet,
+            control,
+            auth: Auth::new(config),
+        }
+    }
+
+    pub fn start(
+        self,
+        bind: &str,
+        tls: Option<Tls>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = match tls {
+            #[cfg(feature = "tls")]
+            Some(tls) => Server::https(
+                bind,
+                tiny_http::SslConfig {
+                    certificate: tls.cert,
+                    private_key: tls.key,
+                },
+            )?,
+            #[cfg(not(feature = "tls"))]
+            Some(_) => return Err("built without the tls feature".into()),
+            None => Server::http(bind)?,
+        };
+        info!("status server listening on {}", bind);
+
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                self.handle(request);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn status(&self) -> Status {
+        let devices: Vec<DeviceStatus> = self
+            .fleet
+            .devices()
+            .iter()
+            .zip(self.control.devices().iter())
+            .map(|(stats, control)| DeviceStatus {
+                name: stats.name().to_string(),
+                group: control.device().group,
+                connected: stats.is_connected(),
+                paused: control.is_paused(),
+                target_rate: control.target_rate(),
+                reported_rate: stats.reported_rate(),
+                accepted: stats.accepted(),
+                rejected: stats.rejected(),
+                blocks: stats.blocks(),
+                bytes_sent: stats.bytes_sent(),
+                bytes_received: stats.bytes_received(),
+                panics: stats.panics(),
+                slow_shares: stats.slow_shares(),
+                connected_secs: stats.connected_time().as_secs_f64(),
+                disconnects: stats.disconnects(),
+                mtbf_secs: stats
+                    .mean_time_between_failures()
+                    .map(|mtbf| mtbf.as_secs_f64()),
+                solve_lag: stats.solve_lag(),
+                jobs: stats.jobs(),
+                transport: stats.transport(),
+            })
+            .collect();
+
+        let groups = self.aggregate(|d| d.device().group);
+        let mut accounts = self.aggregate(|d| Some(d.device().username));
+        for account in accounts.iter_mut() {
+            account.earnings_per_day = self.fleet.earnings().per_day(&account.name);
+        }
+
+        Status {
+            devices,
+            groups,
+            accounts,
+            elapsed_secs: self.fleet.elapsed().as_secs_f64(),
+            job_latency: self.fleet.job_latency(),
+            submit_latency: self.fleet.submit_latency(),
+            host: HostStatus::read(),
+        }
+    }
+
+    fn aggregate(&self, key: impl Fn(&DeviceControl) -> Option<String>) -> Vec<AggregateStatus> {
+        self.fleet
+            .totals_by(&self.control, &key)
+            .into_iter()
+            .map(|(name, totals)| AggregateStatus {
+                paused: self
+                    .control
+                    .devices()
+                    .iter()
+                    .filter(|d| d.is_paused() && key(d).as_ref() == Some(&name))
+                    .count(),
+                name,
+                totals,
+                earnings_per_day: None,
+            })
+            .collect()
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str());
+
+        self.auth.check(authorization)
+    }
+
+    fn handle(&self, mut request: Request) {
+        let url = request.url().to_string();
+        let segments: Vec<&str> = url
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let response = match (request.method(), segments.as_slice()) {
+            (Method::Get, _) if self.auth.is_enabled() && !self.is_authorized(&request) => {
+                let header = Header::from_bytes(
+                    &b"WWW-Authenticate"[..],
+                    &b"Basic realm=\"duino-miner\""[..],
+                )
+                .unwrap();
+                message(401, "unauthorized").with_header(header)
+            }
+            (Method::Get, []) => {
+                let header = Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap();
+                Response::from_data(DASHBOARD.as_bytes().to_vec()).with_header(header)
+            }
+            (Method::Get, ["status"]) => json_response(200, &self.status()),
+            (Method::Get, ["config"]) if !self.auth.is_enabled() => {
+                message(403, "control API disabled, configure a token or password")
+            }
+            (Method::Get, ["config"]) => json_response(200, &self.control.configs()),
+            (Method::Get, ["events"]) => json_response(200, &self.fleet.events().recent(50)),
+            (Method::Post, _) if !self.auth.is_enabled() => {
+                message(403, "control API disabled, configure a token or password")
+            }
+            (Method::Post, _) if !self.is_authorized(&request) => message(401, "unauthorized"),
+            (Method::Post, ["reload"]) => match self.control.reload() {
+                Ok(updated) => message(200, &format!("{} devices updated", updated)),
+                Err(e) => message(500, &format!("reload failed: {}", e)),
+            },
+            (Method::Post, ["config"]) => match read_body(&mut request) {
+                None => message(413, "request body too large"),
+                Some(body) => match serde_json::from_str(&body) {
+                    Ok(configs) => {
+                        let updated = self.control.apply(configs);
+                        info!(
+                            "applied config via control API, {} devices updated",
+                            updated
+                        );
+                        message(200, &format!("{} devices updated", updated))
+                    }
+                    Err(e) => message(400, &format!("invalid device configs: {}", e)),
+                },
+            },
+            (Method::Post, ["stats", "reset"]) => {
+                self.fleet.reset();
+                message(200, "stats reset")
+            }
+            (Method::Post, ["devices", name, action]) => match read_body(&mut request) {
+                None => message(413, "request body too large"),
+                Some(body) => {
+                    self.device_action(name, action, body.trim(), query_flag(&url, "persist"))
+                }
+            },
+            _ => message(404, "not found"),
+        };
+
+        if let Err(e) = request.respond(response) {
+            warn!("could not respond to status request: {}", e);
+        }
+    }
+
+    fn device_action(
+        &self,
+        name: &str,
+        action: &str,
+        body: &str,
+        persist: bool,
+    ) -> Response<std::io::Cursor<Vec<u8>>> {
+        let devices = self.control.select(name);
+        if devices.is_empty() {
+            return message(404, "unknown device");
+        }
+
+        match action {
+            "pause" => {
+                devices.iter().for_each(|d| d.set_paused(true));
+                info!("{} paused via control API", name);
+            }
+            "resume" => {
+                devices.iter().for_each(|d| d.set_paused(false));
+                info!("{} resumed via control API", name);
+            }
+            "reconnect" => {
+                devices.iter().for_each(|d| d.request_reconnect());
+                info!("{} reconnect requested via control API", name);
+            }
+            "target_rate" => match body.parse::<u32>() {
+                Ok(rate) if rate > 0 => {
+                    devices.iter().for_each(|d| d.set_target_rate(rate));
+                    info!("{} target rate set to {} via control API", name, rate);
+                    if persist {
+                        for device in devices.iter() {
+                            if let Err(e) = self.control.persist_target_rate(device) {
+                                return message(500, &format!("could not persist: {}", e));
+                            }
+                        }
+                    }
+                }
+                _ => return message(400, "body must be a positive integer rate"),
+            },
+            _ => return message(404, "unknown action"),
+        }
+
+        message(200, &format!("{} devices updated", devices.len()))
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+pub struct AgentOptions {
+    pub label: String,
+    pub config_file: String,
+    pub keepalive: bool,
+    pub log_path: Option<String>,
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn absolute(path: &str) -> String {
+    std::fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+pub fn render_plist(executable: &str, options: &AgentOptions) -> String {
+    let arguments = [
+        executable.to_string(),
+        "--config-file".to_string(),
+        absolute(&options.config_file),
+        "run".to_string(),
+    ];
+    let arguments: String = arguments
+        .iter()
+        .map(|a| format!("        <string>{}</string>\n", escape_xml(a)))
+        .collect();
+
+    let log_paths = match &options.log_path {
+        Some(path) => {
+            let path = escape_xml(path);
+            format!(
+                "    <key>StandardOutPath</key>\n    <string>{}</string>\n    <key>StandardErrorPath</key>\n    <string>{}</string>\n",
+                path, path
+            )
+        }
+        None => String::new(),
+    };
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>RUST_LOG</key>
+        <string>info</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <{}/>
+{}</dict>
+</plist>
+"#,
+        escape_xml(&options.label),
+        arguments,
+        options.keepalive,
+        log_paths
+    )
+}
+
+pub fn install_agent(options: &AgentOptions) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if !cfg!(target_os = "macos") {
+        return Err("launchd agents are only supported on macOS".into());
+    }
+
+    let home = std::env::var("HOME")?;
+    let dir = PathBuf::from(home).join("Library/LaunchAgents");
+    std::fs::create_dir_all(&dir)?;
+
+    let executable = std::env::current_exe()?;
+    let plist = render_plist(&executable.to_string_lossy(), options);
+
+    let path = dir.join(format!("{}.plist", options.label));
+    std::fs::write(&path, plist)?;
+
+    Ok(path)
+}
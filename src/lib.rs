@@ -1 +1,36 @@
+pub mod api;
+pub mod brake;
+pub mod capture;
+pub mod config;
+pub mod control;
+pub mod dedup;
+pub mod earnings;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod error;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod hasher;
+pub mod hashing;
+pub mod miner;
+#[cfg(feature = "mockpool")]
+pub mod mockpool;
+pub mod pools;
+pub mod protocol;
+#[cfg(feature = "python")]
+mod python;
+mod restart;
+mod schedule;
+pub mod share_log;
+pub mod solutions;
+mod stagger;
+pub mod stats;
+mod supervisor;
+pub mod throttle;
+pub mod transcript;
+pub mod transport;
+pub mod tuner;
+pub mod util;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,45 +1,85 @@
-mod hasher;
-mod util;
+mod auth;
+mod autoscale;
+mod doctor;
+#[cfg(feature = "email")]
+mod email;
+#[cfg(windows)]
+mod eventlog;
+#[cfg(feature = "gpio")]
+mod gpio;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod host;
+mod http;
+mod launchd;
+mod mqtt;
+#[cfg(all(feature = "oled", target_os = "linux"))]
+mod oled;
+mod paths;
+mod permissions;
+mod report;
+mod rlimit;
+#[cfg(windows)]
+mod service;
+mod slack;
+mod statsd;
+#[cfg(unix)]
+mod syslog;
+mod webhook;
 
+use duino_miner::api::{Client, Credentials, PriceOracle, Secret, Visibility};
+use duino_miner::capture::JobCapture;
+use duino_miner::config::{Config, Device, EmailConfig, ServerConfig, CONFIG_VERSION};
+use duino_miner::control::{FleetControl, FleetDiff};
+use duino_miner::earnings;
+use duino_miner::encryption::Key;
 use duino_miner::error::MinerError;
+use duino_miner::hasher::Sha1Hasher;
+use duino_miner::miner::Miner;
+use duino_miner::share_log::ShareLog;
+use duino_miner::stats::{start_reporter, FleetStats};
+use duino_miner::transcript::{self, Exchange, Recorder};
+use duino_miner::util::generate_8hex;
 
-use crate::hasher::Sha1Hasher;
-use crate::util::{generate_8hex, get_pool_info};
+use crate::autoscale::Autoscaler;
+use crate::http::StatusServer;
+use crate::launchd::{install_agent, AgentOptions};
+use crate::mqtt::MqttSink;
+use crate::report::Reporter;
+use crate::slack::SlackNotifier;
+use crate::statsd::StatsdSink;
+use crate::webhook::Webhooks;
 
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::IsTerminal;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use std::fs::File;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::time::{Duration, SystemTime};
+use log::{info, warn};
 
-use rand::Rng;
+use pretty_env_logger::env_logger::WriteStyle;
 
-use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 use clap::{AppSettings, Clap, Subcommand};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Config {
-    pub devices: Vec<Device>,
-}
-
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Device {
-    pub username: String,
-    pub device_name: String,
-    pub device_type: String,
-    pub chip_id: String,
-    pub firmware: String,
-    pub target_rate: u32,
-}
-
 #[derive(Clap)]
 #[clap(version = "0.1", author = "Black H. <encomblackhat@gmail.com>")]
 #[clap(setting = AppSettings::ColoredHelp)]
 struct Opts {
-    #[clap(short, long, default_value = "config.yaml")]
-    config_file: String,
+    #[clap(short, long, multiple_occurrences = true, number_of_values = 1)]
+    config_file: Vec<String>,
+    #[clap(long, default_value = "stderr", possible_values = &["stderr", "syslog", "eventlog"])]
+    log_target: String,
+    #[clap(long)]
+    no_color: bool,
+    #[clap(long)]
+    key_file: Option<String>,
+    #[clap(long)]
+    strict_permissions: bool,
     #[clap(subcommand)]
     sub_command: SubCommands,
 }
@@ -49,225 +89,1062 @@ enum SubCommands {
     #[clap(version = "0.1", author = "Black H. <encomblackhat@gmail.com>")]
     Generate(Generate),
     Run(Run),
+    Mine(Mine),
+    Status,
+    Verify,
+    Simulate(Simulate),
+    Doctor(Doctor),
+    MigrateConfig,
+    Stats(Stats),
+    Diff(ConfigChange),
+    Apply(ConfigChange),
+    Service(Service),
+    InstallAgent(InstallAgent),
 }
 
+/// Settings of a device made up on the command line, shared by `generate`
+/// and `mine`.
 #[derive(Clap)]
-struct Generate {
+struct DeviceArgs {
     #[clap(short, long, default_value = "my_username")]
     username: String,
-    #[clap(long, default_value = "16")]
-    device_count: u32,
-    #[clap(long, default_value = "avr-")]
-    device_name_prefix: String,
     #[clap(long, default_value = "AVR")]
     device_type: String,
+    #[clap(long)]
+    difficulty: Option<String>,
     #[clap(long, default_value = "Official AVR Miner v2.6")]
     firmware: String,
-    #[clap(long, default_value = "190")]
+    #[clap(long, alias = "rate", default_value = "190")]
     target_rate: u32,
+    #[clap(long)]
+    group: Option<String>,
+}
+
+impl DeviceArgs {
+    fn device(&self, device_name: String, rng: &mut StdRng) -> Device {
+        Device {
+            username: self.username.clone(),
+            device_name,
+            device_type: self.device_type.clone(),
+            chip_id: format!("DUCOID{}", generate_8hex(rng)),
+            firmware: self.firmware.clone(),
+            target_rate: self.target_rate,
+            profile: None,
+            wallet_id: None,
+            mining_key: None,
+            difficulty: self.difficulty.clone(),
+            restart: None,
+            group: self.group.clone(),
+            transports: Vec::new(),
+            namespace: None,
+        }
+    }
+}
+
+#[derive(Clap)]
+struct Generate {
+    #[clap(flatten)]
+    device: DeviceArgs,
+    #[clap(long, default_value = "16")]
+    device_count: u32,
+    #[clap(long, default_value = "avr-")]
+    device_name_prefix: String,
+    #[clap(long)]
+    seed: Option<u64>,
+    #[clap(long)]
+    encrypt: bool,
+}
+
+#[derive(Clap)]
+struct Mine {
+    #[clap(flatten)]
+    device: DeviceArgs,
+    #[clap(long, default_value = "avr-1")]
+    device_name: String,
+    #[clap(short, long)]
+    pool: Option<String>,
+    #[clap(long, default_value = "1")]
+    report_interval: u64,
+    #[clap(long)]
+    seed: Option<u64>,
 }
 
 #[derive(Clap)]
 struct Run {
     #[clap(short, long)]
     pool: Option<String>,
+    #[clap(long, default_value = "5")]
+    report_interval: u64,
+    #[clap(long, default_value = "10")]
+    earnings_interval: u64,
+    #[clap(long)]
+    share_log: Option<String>,
+    #[clap(long, default_value = "64")]
+    share_log_max_mb: u64,
+    #[clap(long)]
+    statsd: Option<String>,
+    #[clap(long, default_value = "duino_miner")]
+    statsd_prefix: String,
+    #[clap(long)]
+    statsd_tag: Vec<String>,
+    #[clap(long)]
+    dogstatsd: bool,
+    #[clap(long)]
+    mqtt: Option<String>,
+    #[clap(long, default_value = "duino_miner")]
+    mqtt_prefix: String,
+    #[clap(long, default_value = "homeassistant")]
+    mqtt_discovery_prefix: String,
+    #[clap(long)]
+    mqtt_username: Option<String>,
+    #[clap(long)]
+    mqtt_password: Option<String>,
+    #[clap(long)]
+    http_bind: Option<String>,
+    #[clap(long)]
+    api_token: Option<String>,
+    #[clap(long)]
+    grpc_bind: Option<String>,
+    #[clap(long)]
+    gpio_status_pin: Option<u32>,
+    #[clap(long)]
+    gpio_share_pin: Option<u32>,
+    #[clap(long)]
+    oled_bus: Option<String>,
+    #[clap(long, default_value = "0x3c")]
+    oled_address: String,
+    #[clap(long)]
+    seed: Option<u64>,
+    #[clap(long, default_value = "4096")]
+    max_message_len: usize,
+    #[clap(long)]
+    workers: Option<usize>,
+    #[clap(long)]
+    bandwidth_limit: Option<u64>,
+    #[clap(long, default_value = "1024")]
+    solution_cache: usize,
+    #[clap(long)]
+    hash_threads: Option<usize>,
+    #[clap(long)]
+    paced_search: bool,
+    #[clap(long)]
+    observe: bool,
+    #[clap(long)]
+    stagger: bool,
+    #[clap(long)]
+    record: Option<String>,
+    #[clap(long)]
+    capture_jobs: Option<String>,
+    #[clap(long)]
+    only: Vec<String>,
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Clap)]
+struct Simulate {
+    #[clap(long)]
+    replay: String,
+}
+
+#[derive(Clap)]
+struct Doctor {
+    #[clap(short, long)]
+    pool: Option<String>,
+}
+
+#[derive(Clap)]
+#[cfg_attr(not(windows), allow(dead_code))]
+struct Service {
+    #[clap(subcommand)]
+    action: ServiceAction,
+}
+
+#[derive(Subcommand)]
+#[cfg_attr(not(windows), allow(dead_code))]
+#[allow(clippy::large_enum_variant)]
+enum ServiceAction {
+    Install,
+    Uninstall,
+    Run(Run),
+}
+
+/// Reads or resets the stats of a running miner through its HTTP control
+/// API, authenticating with `--api-token` or the config's `server` section.
+#[derive(Clap)]
+struct Stats {
+    #[clap(long, default_value = "http://127.0.0.1:8080")]
+    url: String,
+    #[clap(long)]
+    api_token: Option<String>,
+    #[clap(subcommand)]
+    action: StatsAction,
+}
+
+#[derive(Subcommand)]
+enum StatsAction {
+    Snapshot(Snapshot),
+    Reset,
+}
+
+#[derive(Clap)]
+struct Snapshot {
+    #[clap(short, long)]
+    output: Option<String>,
+}
+
+/// Config files proposed for a running miner, compared with its devices by
+/// `diff` and applied to them by `apply` through its HTTP control API.
+#[derive(Clap)]
+struct ConfigChange {
+    #[clap(required = true)]
+    files: Vec<String>,
+    #[clap(long, default_value = "http://127.0.0.1:8080")]
+    url: String,
+    #[clap(long)]
+    api_token: Option<String>,
+}
+
+#[derive(Clap)]
+struct InstallAgent {
+    #[clap(long, default_value = "com.duino-miner")]
+    label: String,
+    #[clap(long)]
+    keepalive: bool,
+    #[clap(long)]
+    log_path: Option<String>,
 }
 
 fn generate_config(file_path: String, gen: &Generate) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rng = match gen.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
     let mut device_vec: Vec<Device> = Vec::new();
 
     for i in 0..gen.device_count {
-        let device = Device {
-            username: gen.username.clone(),
-            device_name: format!("{}{}", gen.device_name_prefix, i + 1),
-            device_type: gen.device_type.clone(),
-            chip_id: format!("DUCOID{}", generate_8hex()),
-            firmware: gen.firmware.clone(),
-            target_rate: gen.target_rate,
-        };
-
-        device_vec.push(device);
+        let device_name = format!("{}{}", gen.device_name_prefix, i + 1);
+        device_vec.push(gen.device.device(device_name, &mut rng));
     }
 
     let c = Config {
         devices: device_vec,
+        ..Default::default()
     };
-    let c_serial = serde_yaml::to_string(&c)?;
-
-    let mut f = File::create(file_path)?;
-    f.write_all(c_serial.as_bytes())?;
+    if let Some(dir) = Path::new(&file_path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if gen.encrypt {
+        c.save_encrypted(&file_path, &Key::from_env()?)?;
+    } else {
+        c.save(&file_path)?;
+    }
+    println!("wrote {}", file_path);
 
     Ok(())
 }
 
-fn start_miner(device: Device, pool: String, hasher: Sha1Hasher) -> Result<(), MinerError> {
-    let heatup_duration: u64 = rand::thread_rng().gen_range(10..10000);
-    std::thread::sleep(Duration::from_millis(heatup_duration));
+/// Runs the fleet until it shuts down, calling `started` with its control
+/// plane once the devices are mining.
+fn run_miners(
+    config_files: Vec<String>,
+    run: Run,
+    strict_permissions: bool,
+    started: impl FnOnce(Arc<FleetControl>),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut c = Config::load_all(&config_files)?;
+    if !run.only.is_empty() {
+        c.retain_selected(&run.only)?;
+    }
+    if let Some(limits) = &c.limits {
+        match limits.check(&c.devices) {
+            Err(e) if run.force => warn!("{}, running anyway with --force", e),
+            Err(e) => return Err(format!("{}; pass --force to run anyway", e).into()),
+            Ok(()) => {}
+        }
+    }
 
-    let mut stream = TcpStream::connect(&pool).map_err(|_| MinerError::Connection)?;
+    info!("running with {} miners", c.devices.len());
 
-    info!("{} connected to pool {}", device.device_name, pool);
+    Sha1Hasher::new().self_test()?;
 
-    let mut cmd_in: [u8; 200] = [0; 200];
-    let n = stream
-        .read(&mut cmd_in)
-        .map_err(|_| MinerError::RecvCommand)?;
-    info!(
-        "version: {}",
-        std::str::from_utf8(&cmd_in[..n]).map_err(|_| MinerError::InvalidUTF8)?
-    );
+    crate::rlimit::ensure_fd_limit(run.workers.unwrap_or(c.devices.len()))?;
 
-    let expected_interval = 1000000u128 / device.target_rate as u128;
+    let server = c.server.clone();
+    let slack = c.slack.clone().map(SlackNotifier::new).transpose()?;
+    let webhooks = Webhooks::new(c.webhooks.clone())?;
+    let email = c.email.clone();
+    let reporter = c.report.clone().map(Reporter::new).transpose()?;
+    let autoscaler = c.autoscale.clone().map(Autoscaler::new).transpose()?;
+    if let Some(price) = &c.price {
+        PriceOracle::set_shared(PriceOracle::new(
+            price.sources.clone(),
+            Duration::from_secs(price.refresh_secs),
+        ));
+    }
+    let banlist = c
+        .pool_selection
+        .as_ref()
+        .map(|selection| paths::data_file(&selection.banlist))
+        .transpose()?;
+    let usernames: BTreeSet<String> = c.devices.iter().map(|d| d.username.clone()).collect();
+    let mut miner = Miner::new(c, config_files);
+    let fleet = miner.fleet().clone();
+    let control = miner.control().clone();
 
-    loop {
-        let cmd_job = format!("JOB,{},{}\n", device.username, device.device_type);
-        stream
-            .write(cmd_job.as_bytes())
-            .map_err(|_| MinerError::SendCommand)?;
+    if run.report_interval > 0 {
+        start_reporter(
+            fleet.clone(),
+            control.clone(),
+            Duration::from_secs(run.report_interval * 60),
+        );
+    }
 
-        let n = stream
-            .read(&mut cmd_in)
-            .map_err(|_| MinerError::RecvCommand)?;
-        let job = std::str::from_utf8(&cmd_in[..n])
-            .map_err(|_| MinerError::InvalidUTF8)?
-            .trim();
+    if run.earnings_interval > 0 {
+        earnings::start_polling(
+            fleet.clone(),
+            control.clone(),
+            usernames.into_iter().collect(),
+            Duration::from_secs(run.earnings_interval * 60),
+        );
+    }
 
-        let args: Vec<&str> = job.split(',').collect();
-        if args.len() < 3 {
-            return Err(MinerError::MalformedJob(job.to_string()));
-        }
+    if let Some(addr) = run.statsd {
+        let sink = StatsdSink::new(&addr, run.statsd_prefix, run.statsd_tag, run.dogstatsd)?;
+        sink.start(fleet.clone(), control.clone(), Duration::from_secs(10));
+    }
 
-        let last_block_hash = args[0];
-        let expected_hash = args[1];
-        let diff = args[2]
-            .parse::<u32>()
-            .map_err(|_| MinerError::MalformedJob(job.to_string()))?
-            * 100
-            + 1;
-
-        info!(
-            "last: {}, expected: {}, diff: {}",
-            last_block_hash, expected_hash, diff
+    if let Some(addr) = run.mqtt {
+        let credentials = run.mqtt_username.zip(run.mqtt_password);
+        let sink = MqttSink::new(
+            addr,
+            run.mqtt_prefix,
+            run.mqtt_discovery_prefix,
+            credentials,
         );
+        sink.start(fleet.clone(), control.clone(), Duration::from_secs(10));
+    }
 
-        let start = SystemTime::now();
+    if let Some(slack) = slack {
+        slack.start(fleet.clone(), control.clone());
+    }
 
-        let duco_numeric_result = hasher
-            .get_hash(last_block_hash, expected_hash, diff)
-            .unwrap_or(0);
+    if !webhooks.is_empty() {
+        webhooks.start(fleet.clone(), control.clone());
+    }
 
-        let end = SystemTime::now();
-        let duration = end.duration_since(start).unwrap().as_micros();
-        let real_rate = duco_numeric_result as f64 / duration as f64 * 1000000f64;
+    if let Some(email) = email {
+        start_email(email, fleet.clone(), control.clone())?;
+    }
 
-        let expected_duration = expected_interval * duco_numeric_result as u128;
+    if let Some(reporter) = reporter {
+        reporter.start(fleet.clone(), control.clone());
+    }
 
-        if duration < expected_duration {
-            let wait_duration = (expected_duration - duration) as u64;
-            std::thread::sleep(Duration::from_micros(wait_duration));
-            info!("waited {} micro sec", wait_duration);
-        } else {
-            warn!(
-                "system too slow, lag {} micro sec",
-                duration - expected_duration
-            );
+    if let Some(autoscaler) = autoscaler {
+        autoscaler.start(control.clone());
+    }
+
+    let mut server = server.unwrap_or_default();
+    if run.api_token.is_some() {
+        server.token = run.api_token;
+    }
+
+    if let Some(bind) = run.http_bind {
+        StatusServer::new(fleet.clone(), control.clone(), &server)
+            .start(&bind, server.tls()?)
+            .map_err(|e| e.to_string())?;
+    }
+
+    if let Some(bind) = run.grpc_bind {
+        start_grpc(&bind, fleet.clone(), control.clone(), &server)?;
+    }
+
+    if let Some(status_pin) = run.gpio_status_pin {
+        start_gpio(
+            status_pin,
+            run.gpio_share_pin,
+            fleet.clone(),
+            control.clone(),
+        )?;
+    }
+
+    if let Some(bus) = run.oled_bus {
+        let oled_address = run.oled_address.clone();
+        let address = u16::from_str_radix(oled_address.trim_start_matches("0x"), 16)
+            .map_err(|e| format!("invalid OLED address {}: {}", oled_address, e))?;
+        start_oled(&bus, address, fleet.clone(), control.clone())?;
+    }
+
+    let state_files = [&run.share_log, &run.record, &run.capture_jobs]
+        .iter()
+        .filter_map(|path| path.as_deref().map(paths::data_file))
+        .collect::<Result<Vec<_>, _>>()?;
+    permissions::check(&state_files, strict_permissions)?;
+
+    let share_log = match run.share_log {
+        Some(path) => {
+            let path = paths::data_file(&path)?;
+            info!("logging shares to {}", path.display());
+            Some(Arc::new(ShareLog::open(
+                path,
+                run.share_log_max_mb * 1024 * 1024,
+            )?))
         }
+        None => None,
+    };
 
-        let end = SystemTime::now();
-        let duration = end.duration_since(start).unwrap().as_micros();
-        let emu_rate = duco_numeric_result as f64 / duration as f64 * 1000000f64;
+    let recorder = match run.record {
+        Some(path) => {
+            let path = paths::data_file(&path)?;
+            info!("recording the session to {}", path.display());
+            Some(Arc::new(Recorder::open(path)?))
+        }
+        None => None,
+    };
 
-        // let lag_duration: u64 = rand::thread_rng().gen_range(0..100);
-        // tokio::time::sleep(Duration::from_millis(lag_duration)).await;
+    let capture = match run.capture_jobs {
+        Some(path) => {
+            let path = paths::data_file(&path)?;
+            info!("capturing jobs and responses to {}", path.display());
+            Some(Arc::new(JobCapture::open(path)?))
+        }
+        None => None,
+    };
+
+    miner = miner
+        .with_pool(run.pool)
+        .with_pool_banlist(banlist)
+        .with_share_log(share_log)
+        .with_recorder(recorder)
+        .with_job_capture(capture)
+        .with_seed(run.seed)
+        .with_max_message_len(run.max_message_len)
+        .with_workers(run.workers)
+        .with_bandwidth_limit(run.bandwidth_limit)
+        .with_solution_cache(run.solution_cache)
+        .with_hash_threads(run.hash_threads)
+        .with_paced_search(run.paced_search)
+        .with_observe(run.observe)
+        .with_stagger(run.stagger);
+    miner.start();
+    started(control);
+    miner.wait();
+
+    Ok(())
+}
+
+/// Mines with a single device described on the command line, without a
+/// config file.
+fn mine_once(mine: Mine) -> Result<(), Box<dyn std::error::Error>> {
+    Sha1Hasher::new().self_test()?;
+
+    let mut rng = match mine.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let c = Config {
+        devices: vec![mine.device.device(mine.device_name, &mut rng)],
+        ..Default::default()
+    };
 
-        let cmd_out = format!(
-            "{},{:.2},{},{},{}\n",
-            duco_numeric_result, emu_rate, device.firmware, device.device_name, device.chip_id
+    let mut miner = Miner::new(c, Vec::new())
+        .with_pool(mine.pool)
+        .with_seed(mine.seed);
+    if mine.report_interval > 0 {
+        start_reporter(
+            miner.fleet().clone(),
+            miner.control().clone(),
+            Duration::from_secs(mine.report_interval * 60),
         );
-        stream
-            .write(cmd_out.as_bytes())
-            .map_err(|_| MinerError::SendCommand)?;
-
-        let n = stream
-            .read(&mut cmd_in)
-            .map_err(|_| MinerError::RecvCommand)?;
-        let resp = std::str::from_utf8(&cmd_in[..n])
-            .map_err(|_| MinerError::InvalidUTF8)?
-            .trim();
-
-        if resp == "GOOD" {
-            info!(
-                "result good, result: {}, rate: {:.2}, real: {:.2}",
-                duco_numeric_result, emu_rate, real_rate
-            );
-        } else if resp == "BLOCK" {
-            info!(
-                "FOUND BLOCK!, result: {}, rate: {:.2}, real: {:.2}",
-                duco_numeric_result, emu_rate, real_rate
-            );
-        } else {
-            warn!(
-                "resp: {}, result: {}, rate: {:.2}, real: {:.2}",
-                resp, duco_numeric_result, emu_rate, real_rate
-            );
+    }
+    miner.start();
+    miner.wait();
+
+    Ok(())
+}
+
+/// Compares the configured devices of each account with the pool's miner
+/// list, returning the account, its device count and the comparison. Logs
+/// in with the account's password from the environment or, failing that, a
+/// device's mining key, and falls back to the public miner list.
+fn pool_visibility(c: &Config) -> Vec<(&str, usize, Result<Visibility, MinerError>)> {
+    let client = Client::shared();
+
+    let mut accounts: BTreeMap<&str, Vec<&Device>> = BTreeMap::new();
+    for device in c.devices.iter() {
+        accounts.entry(&device.username).or_default().push(device);
+    }
+
+    accounts
+        .into_iter()
+        .map(|(username, devices)| {
+            let credentials = Credentials::from_env(username).or_else(|| {
+                let key = devices.iter().find_map(|d| d.mining_key.clone())?;
+                Some(Credentials {
+                    username: username.to_string(),
+                    secret: Secret::MiningKey(key),
+                })
+            });
+
+            let miners = match credentials {
+                Some(credentials) => client.login(credentials).and_then(|s| s.get_miners()),
+                None => client.get_miners(username),
+            };
+            let visibility = miners.map(|miners| {
+                Visibility::compare(devices.iter().map(|d| d.device_name.as_str()), &miners)
+            });
+
+            (username, devices.len(), visibility)
+        })
+        .collect()
+}
+
+/// Prints how many configured devices of each account the pool can see.
+fn show_status(config_file: String) -> Result<(), Box<dyn std::error::Error>> {
+    let c = Config::load(&config_file)?;
+
+    for (username, device_count, visibility) in pool_visibility(&c) {
+        let visibility = match visibility {
+            Ok(visibility) => visibility,
+            Err(e) => {
+                println!("{}: {}", username, e);
+                continue;
+            }
+        };
+
+        println!(
+            "{}: {} of {} devices visible to the pool",
+            username,
+            visibility.visible.len(),
+            device_count
+        );
+        if !visibility.missing.is_empty() {
+            println!("  not seen by pool: {}", visibility.missing.join(", "));
+        }
+        if !visibility.unknown.is_empty() {
+            println!("  not in config: {}", visibility.unknown.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists every device the pool does not report and every worker on the
+/// accounts that is not configured, failing if there are any.
+fn verify_devices(config_file: String) -> Result<(), Box<dyn std::error::Error>> {
+    let c = Config::load(&config_file)?;
+    let mut problems = 0;
+
+    for (username, _, visibility) in pool_visibility(&c) {
+        let visibility = match visibility {
+            Ok(visibility) => visibility,
+            Err(e) => {
+                println!("{}: could not fetch miners: {}", username, e);
+                problems += 1;
+                continue;
+            }
+        };
+
+        for device in visibility.missing.iter() {
+            println!("{}/{}: not listed by the pool", username, device);
+        }
+        for worker in visibility.unknown.iter() {
+            println!("{}/{}: unknown worker", username, worker);
+        }
+        problems += visibility.missing.len() + visibility.unknown.len();
+    }
+
+    if problems > 0 {
+        return Err(format!("problems found: {}", problems).into());
+    }
+
+    println!("all devices are listed by the pool");
+    Ok(())
+}
+
+/// Prints the findings of every environment check, failing if any found a
+/// problem.
+fn run_doctor(config_file: String, doctor: Doctor) -> Result<(), Box<dyn std::error::Error>> {
+    let findings = doctor::diagnose(&config_file, doctor.pool);
+
+    for finding in findings.iter() {
+        println!("{}", finding);
+    }
+
+    let problems = findings
+        .iter()
+        .filter(|f| f.level == doctor::Level::Problem)
+        .count();
+    if problems > 0 {
+        return Err(format!("problems found: {}", problems).into());
+    }
+
+    Ok(())
+}
+
+/// Rewrites the config file, and every file it includes, in the current
+/// schema.
+fn migrate_config(config_file: String) -> Result<(), Box<dyn std::error::Error>> {
+    let report = |file: &Path, migrated: Option<u32>| match migrated {
+        Some(version) => println!(
+            "{}: migrated from version {} to {}, original kept as {}.bak",
+            file.display(),
+            version,
+            CONFIG_VERSION,
+            file.display()
+        ),
+        None => println!("{}: already at version {}", file.display(), CONFIG_VERSION),
+    };
+
+    let path = Path::new(&config_file);
+    report(path, Config::migrate_file(path)?);
+
+    for file in Config::load_file(path)?.included_files(path)? {
+        report(&file, Config::migrate_file(&file)?);
+    }
+
+    Ok(())
+}
+
+/// Credentials for the HTTP control API of a running miner: `api_token`, or
+/// else the `server` section of `config_file`.
+fn control_auth(config_file: &str, api_token: Option<String>) -> crate::auth::Auth {
+    let server = match api_token {
+        Some(token) => ServerConfig {
+            token: Some(token),
+            ..Default::default()
+        },
+        None => Config::load(config_file)
+            .ok()
+            .and_then(|c| c.server)
+            .unwrap_or_default(),
+    };
+
+    crate::auth::Auth::new(&server)
+}
+
+/// Sends `request` to the miner at `url`, with `body` as JSON if given, and
+/// returns the JSON response.
+fn call_control(
+    request: ureq::Request,
+    auth: &crate::auth::Auth,
+    url: &str,
+    body: Option<serde_json::Value>,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let request = match auth.header() {
+        Some(header) => request.set("Authorization", header),
+        None => request,
+    };
+    let response = match body {
+        Some(body) => request.send_json(body),
+        None => request.call(),
+    };
+
+    match response {
+        Ok(response) => Ok(response.into_json()?),
+        Err(ureq::Error::Status(status, response)) => {
+            let body: serde_json::Value = response.into_json().unwrap_or_default();
+            Err(format!("{}: {}", status, body["message"].as_str().unwrap_or("")).into())
         }
+        Err(e) => Err(format!("could not reach the miner at {}: {}", url, e).into()),
     }
 }
 
-fn start_miner_loop(device: Device, pool: Option<String>, hasher: Sha1Hasher) {
-    info!("Spawning {}...", device.device_name);
+fn run_stats_command(config_file: String, stats: Stats) -> Result<(), Box<dyn std::error::Error>> {
+    let auth = control_auth(&config_file, stats.api_token);
+    let url = stats.url.trim_end_matches('/');
+
+    let request = match stats.action {
+        StatsAction::Snapshot(_) => ureq::get(&format!("{}/status", url)),
+        StatsAction::Reset => ureq::post(&format!("{}/stats/reset", url)),
+    };
+    let response = call_control(request, &auth, url, None)?;
+
+    match stats.action {
+        StatsAction::Snapshot(snapshot) => {
+            let json = serde_json::to_string_pretty(&response)?;
+            match snapshot.output {
+                Some(path) => {
+                    std::fs::write(&path, json + "\n")?;
+                    println!("wrote {}", path);
+                }
+                None => println!("{}", json),
+            }
+        }
+        StatsAction::Reset => println!("{}", response["message"].as_str().unwrap_or("")),
+    }
+
+    Ok(())
+}
+
+/// Prints how the devices of the proposed config files differ from those of
+/// the running miner, and with `apply` set, applies them. Added and removed
+/// devices are only reported, as they need a restart.
+fn change_config(
+    config_file: String,
+    change: ConfigChange,
+    apply: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let auth = control_auth(&config_file, change.api_token);
+    let url = change.url.trim_end_matches('/');
+
+    let proposed: BTreeMap<String, Device> = Config::load_all(&change.files)?
+        .devices
+        .into_iter()
+        .map(|d| (d.name(), d))
+        .collect();
+    let running: BTreeMap<String, Device> = serde_json::from_value(call_control(
+        ureq::get(&format!("{}/config", url)),
+        &auth,
+        url,
+        None,
+    )?)?;
 
-    loop {
-        let pool = if let Some(pool) = pool.clone() {
-            pool
-        } else {
-            get_pool_info().unwrap_or(format!("{}:{}", "server.duinocoin.com", 2813))
+    let diff = FleetDiff::new(&running, &proposed);
+    if diff.is_empty() {
+        println!("no changes");
+        return Ok(());
+    }
+    for name in diff.added.iter() {
+        println!("+ {} (restart required)", name);
+    }
+    for name in diff.removed.iter() {
+        println!("- {} (restart required)", name);
+    }
+    for (name, fields) in diff.modified.iter() {
+        println!("~ {}: {}", name, fields.join(", "));
+    }
+
+    if apply {
+        let response = call_control(
+            ureq::post(&format!("{}/config", url)),
+            &auth,
+            url,
+            Some(serde_json::to_value(&proposed)?),
+        )?;
+        println!("{}", response["message"].as_str().unwrap_or(""));
+    }
+
+    Ok(())
+}
+
+/// Replays each device's part of a recorded transcript against the miner,
+/// playing the pool's side, and prints where the miner's messages differ.
+/// Devices are taken from the config file and answer without pacing.
+fn simulate(config_file: String, sim: Simulate) -> Result<(), Box<dyn std::error::Error>> {
+    let c = Config::load(&config_file)?;
+
+    let mut transcripts: BTreeMap<String, Vec<Exchange>> = BTreeMap::new();
+    for exchange in transcript::load(paths::data_file(&sim.replay)?)? {
+        transcripts
+            .entry(exchange.device.clone())
+            .or_default()
+            .push(exchange);
+    }
+
+    let mut mismatches = 0;
+    for (name, exchanges) in transcripts {
+        let mut device = match c.devices.iter().find(|d| d.device_name == name) {
+            Some(device) => device.clone(),
+            None => {
+                println!("{}: not in config, skipping", name);
+                continue;
+            }
+        };
+        device.target_rate = u32::MAX;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let config = Config {
+            devices: vec![device],
+            ..c.clone()
         };
+        let mut miner = Miner::new(config, Vec::new())
+            .with_pool(Some(listener.local_addr()?.to_string()))
+            .with_seed(Some(0));
+        miner.start();
+
+        let report = transcript::replay(&listener, &exchanges)?;
+        drop(listener);
+        miner.stop();
 
-        match start_miner(device.clone(), pool, hasher.clone()) {
-            Ok(_) => error!("exited without error"),
-            Err(e) => error!("exited with error: {:?}", e),
+        println!(
+            "{}: replayed {} of {} messages, {} mismatched",
+            name,
+            report.replayed,
+            exchanges.len(),
+            report.mismatches.len()
+        );
+        for mismatch in report.mismatches.iter() {
+            println!(
+                "  #{}: expected `{}`, sent `{}`",
+                mismatch.index, mismatch.expected, mismatch.actual
+            );
         }
+        mismatches += report.mismatches.len();
     }
+
+    if mismatches > 0 {
+        return Err(format!("mismatched messages: {}", mismatches).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "grpc")]
+fn start_grpc(
+    bind: &str,
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+    server: &ServerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::grpc::start(bind, fleet, control, server)
 }
 
-fn start_miners(devices: Vec<Device>, pool: Option<String>, hasher: Sha1Hasher) {
-    let mut handles = vec![];
+#[cfg(not(feature = "grpc"))]
+fn start_grpc(
+    _bind: &str,
+    _fleet: Arc<FleetStats>,
+    _control: Arc<FleetControl>,
+    _server: &ServerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("built without the grpc feature".into())
+}
 
-    for device in devices {
-        let hasher = hasher.clone();
-        let pool = pool.clone();
+#[cfg(feature = "email")]
+fn start_email(
+    config: EmailConfig,
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::email::EmailNotifier::new(config)?.start(fleet, control);
 
-        let handle = std::thread::spawn(move || {
-            start_miner_loop(device, pool, hasher);
-        });
-        handles.push(handle);
+    Ok(())
+}
+
+#[cfg(not(feature = "email"))]
+fn start_email(
+    _config: EmailConfig,
+    _fleet: Arc<FleetStats>,
+    _control: Arc<FleetControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("built without the email feature".into())
+}
+
+#[cfg(feature = "gpio")]
+fn start_gpio(
+    status_pin: u32,
+    share_pin: Option<u32>,
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let export = |pin| crate::gpio::Pin::export(pin).map_err(|e| format!("GPIO {}: {}", pin, e));
+    let status = export(status_pin)?;
+    let share = share_pin.map(export).transpose()?;
+    crate::gpio::start(status, share, fleet, control);
+
+    Ok(())
+}
+
+#[cfg(not(feature = "gpio"))]
+fn start_gpio(
+    _status_pin: u32,
+    _share_pin: Option<u32>,
+    _fleet: Arc<FleetStats>,
+    _control: Arc<FleetControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("built without the gpio feature".into())
+}
+
+#[cfg(all(feature = "oled", target_os = "linux"))]
+fn start_oled(
+    bus: &str,
+    address: u16,
+    fleet: Arc<FleetStats>,
+    control: Arc<FleetControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let display =
+        crate::oled::Display::open(bus, address).map_err(|e| format!("{}: {}", bus, e))?;
+    crate::oled::start(display, fleet, control);
+
+    Ok(())
+}
+
+#[cfg(not(all(feature = "oled", target_os = "linux")))]
+fn start_oled(
+    _bus: &str,
+    _address: u16,
+    _fleet: Arc<FleetStats>,
+    _control: Arc<FleetControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("the OLED display needs Linux and the oled feature".into())
+}
+
+#[cfg(windows)]
+fn run_service_command(
+    config_file: String,
+    service: Service,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match service.action {
+        ServiceAction::Install => crate::service::install(config_file)?,
+        ServiceAction::Uninstall => crate::service::uninstall()?,
+        ServiceAction::Run(_) => crate::service::run()?,
     }
 
-    for handle in handles {
-        handle.join().unwrap();
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn run_service_command(
+    _config_file: String,
+    _service: Service,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("service is only supported on Windows".into())
+}
+
+/// Whether log lines on stderr should be colored. Colors garble log files
+/// and consoles that do not understand them.
+fn use_color(no_color: bool) -> bool {
+    !no_color
+        && std::env::var_os("NO_COLOR").is_none()
+        && std::env::var("TERM").as_deref() != Ok("dumb")
+        && std::io::stderr().is_terminal()
+}
+
+fn init_logger(log_target: &str, no_color: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match log_target {
+        #[cfg(unix)]
+        "syslog" => {
+            let level = std::env::var("RUST_LOG")
+                .ok()
+                .and_then(|l| l.parse().ok())
+                .unwrap_or(log::LevelFilter::Info);
+            crate::syslog::SyslogLogger::init(level)?;
+        }
+        #[cfg(not(unix))]
+        "syslog" => return Err("syslog is only supported on Unix".into()),
+        #[cfg(windows)]
+        "eventlog" => crate::eventlog::EventLogLogger::init(log::LevelFilter::Info)?,
+        #[cfg(not(windows))]
+        "eventlog" => return Err("eventlog is only supported on Windows".into()),
+        _ => {
+            let mut builder = pretty_env_logger::formatted_builder();
+            if let Ok(filters) = std::env::var("RUST_LOG") {
+                builder.parse_filters(&filters);
+            }
+            builder.write_style(if use_color(no_color) {
+                WriteStyle::Auto
+            } else {
+                WriteStyle::Never
+            });
+            builder.try_init()?;
+        }
     }
+
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    pretty_env_logger::init();
-
     let opts: Opts = Opts::parse();
 
+    init_logger(&opts.log_target, opts.no_color)?;
+
+    if let Some(key_file) = &opts.key_file {
+        Key::set_default(Key::from_file(key_file).map_err(|e| format!("{}: {}", key_file, e))?);
+    }
+
+    let config_files = match opts.config_file.len() {
+        0 => vec![paths::config_file(None)],
+        1 => opts.config_file,
+        _ if matches!(opts.sub_command, SubCommands::Run(_)) => opts.config_file,
+        _ => return Err("only run takes more than one config file".into()),
+    };
+    let config_file = config_files[0].clone();
+
+    if !matches!(opts.sub_command, SubCommands::Generate(_)) {
+        let mut files = Vec::new();
+        for config_file in config_files.iter() {
+            files.push(PathBuf::from(config_file));
+            if let Ok(c) = Config::load_file(Path::new(config_file)) {
+                files.extend(c.included_files(Path::new(config_file)).unwrap_or_default());
+            }
+        }
+        files.extend(opts.key_file.iter().map(PathBuf::from));
+        permissions::check(&files, opts.strict_permissions)?;
+    }
+
     match opts.sub_command {
         SubCommands::Generate(gen) => {
-            generate_config(opts.config_file, &gen)?;
+            generate_config(config_file, &gen)?;
         }
         SubCommands::Run(run) => {
-            let c_serial = std::fs::read_to_string(opts.config_file)?;
-            let c: Config = serde_yaml::from_str(c_serial.as_str())?;
-
-            info!("running with {} miners", c.devices.len());
-
-            let hasher = Sha1Hasher::new();
-            start_miners(c.devices, run.pool, hasher);
+            run_miners(config_files, run, opts.strict_permissions, |_| {})?;
+        }
+        SubCommands::Mine(mine) => {
+            mine_once(mine)?;
+        }
+        SubCommands::Status => {
+            show_status(config_file)?;
+        }
+        SubCommands::Verify => {
+            verify_devices(config_file)?;
+        }
+        SubCommands::Simulate(sim) => {
+            simulate(config_file, sim)?;
+        }
+        SubCommands::MigrateConfig => {
+            migrate_config(config_file)?;
+        }
+        SubCommands::Stats(stats) => {
+            run_stats_command(config_file, stats)?;
+        }
+        SubCommands::Diff(change) => {
+            change_config(config_file, change, false)?;
+        }
+        SubCommands::Apply(change) => {
+            change_config(config_file, change, true)?;
+        }
+        SubCommands::Doctor(doctor) => {
+            run_doctor(config_file, doctor)?;
+        }
+        SubCommands::Service(service) => {
+            run_service_command(config_file, service)?;
+        }
+        SubCommands::InstallAgent(agent) => {
+            let options = AgentOptions {
+                label: agent.label,
+                config_file,
+                keepalive: agent.keepalive,
+                log_path: agent.log_path,
+            };
+            let path = install_agent(&options)?;
+            println!("wrote {}", path.display());
+            println!("load it with: launchctl load -w {}", path.display());
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_files_take_one_value_each() {
+        let opts =
+            Opts::try_parse_from(&["duino-miner", "-c", "a.yaml", "-c", "b.yaml", "run"]).unwrap();
+        assert_eq!(opts.config_file, vec!["a.yaml", "b.yaml"]);
+        assert!(matches!(opts.sub_command, SubCommands::Run(_)));
+
+        let opts =
+            Opts::try_parse_from(&["duino-miner", "-c", "a.yaml", "service", "install"]).unwrap();
+        assert_eq!(opts.config_file, vec!["a.yaml"]);
+        assert!(matches!(
+            opts.sub_command,
+            SubCommands::Service(Service {
+                action: ServiceAction::Install
+            })
+        ));
+    }
+}
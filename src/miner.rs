@@ -0,0 +1,1172 @@
+use crate::api::Client;
+use crate::brake::SafetyBrake;
+use crate::capture::{JobCapture, Kind};
+use crate::config::{Config, Device, Profile, RestartPolicy, SlowConfig};
+use crate::control::{DeviceControl, FleetControl};
+use crate::dedup::SubmittedShares;
+use crate::error::{MinerError, Severity};
+use crate::events::EventKind;
+use crate::hasher::Sha1Hasher;
+use crate::hashing::HashService;
+use crate::pools::{unix_now, PoolSelector};
+use crate::protocol::{
+    check_template, normalize, Job, JobRequest, MessageReader, Share, ShareResponse,
+    DEFAULT_JOB_REQUEST, DEFAULT_SUBMISSION, JOB_REQUEST_FIELDS, SHARE_FIELDS,
+};
+use crate::restart::Restarts;
+use crate::schedule::Schedule;
+use crate::share_log::{ShareLog, ShareRecord};
+use crate::solutions::DEFAULT_SOLUTION_CACHE_LEN;
+use crate::stagger::Stagger;
+use crate::stats::{DeviceStats, FleetStats};
+use crate::supervisor::{self, Panic};
+use crate::throttle::Throttle;
+use crate::transcript::{Direction, Recorder};
+use crate::transport::{self, Stream, Transport};
+use crate::tuner::DifficultyTuner;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use log::{error, info, warn};
+
+/// Durations are floored to this many microseconds when computing rates, so
+/// instant solutions do not divide by zero.
+const MIN_DURATION_US: u128 = 1;
+
+/// Hashes per second for `hashes` computed in `duration_us` microseconds.
+fn hash_rate(hashes: u64, duration_us: u128) -> f64 {
+    hashes as f64 / duration_us.max(MIN_DURATION_US) as f64 * 1000000f64
+}
+
+/// Whether a share solved at `rate` hashes per second is too slow for a
+/// device targeting `target_rate`, short of it by more than `ratio` allows.
+fn is_slow(rate: f64, target_rate: u32, ratio: f64) -> bool {
+    rate < target_rate as f64 * ratio
+}
+
+/// Microseconds `hashes` take at `target_rate` hashes per second. Computed
+/// from the total rather than a per-hash interval, which would round to zero
+/// at PC rates.
+fn pacing_duration(hashes: u64, target_rate: u32) -> u128 {
+    hashes as u128 * 1000000 / target_rate.max(1) as u128
+}
+
+/// Shortest sleep taken during a paced search.
+const PACE_SLICE_US: u128 = 2000;
+
+/// Searches `job` on the calling thread, sleeping as it goes so hashes are
+/// computed at `target_rate` per second.
+fn paced_search(job: &Job, diff: u64, target_rate: u32) -> Option<u64> {
+    let search_start = Instant::now();
+
+    Sha1Hasher::new()
+        .search(
+            &job.last_block_hash,
+            &job.expected_hash,
+            0,
+            diff,
+            |hashes| {
+                let due = pacing_duration(hashes, target_rate);
+                let elapsed = search_start.elapsed().as_micros();
+                if due >= elapsed + PACE_SLICE_US {
+                    std::thread::sleep(Duration::from_micros((due - elapsed) as u64));
+                }
+            },
+        )
+        .ok()
+}
+
+/// Wait before asking for a new job when the pool reissues one already solved.
+const REISSUED_JOB_DELAY: Duration = Duration::from_secs(1);
+
+/// Default limit for a single message received from the pool.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 4096;
+
+/// Default longest wait before a device connects.
+pub const DEFAULT_MAX_HEATUP: Duration = Duration::from_secs(10);
+
+/// Stack of each device and worker thread. Hashing runs on the hash
+/// service's threads, so these need far less than the default of 2 MiB,
+/// which adds up to gigabytes of address space for large fleets.
+const THREAD_STACK_LEN: usize = 512 * 1024;
+
+/// Spawns a device or worker thread with a [`THREAD_STACK_LEN`] stack.
+fn spawn_thread(f: impl FnOnce() + Send + 'static) -> JoinHandle<()> {
+    std::thread::Builder::new()
+        .stack_size(THREAD_STACK_LEN)
+        .spawn(f)
+        .expect("failed to spawn thread")
+}
+
+/// A random wait of at least 10 milliseconds and less than `max` before a
+/// device connects, so devices started together do not connect at once.
+fn heatup(rng: &mut StdRng, max: Duration) -> Duration {
+    let max_ms = (max.as_millis() as u64).max(11);
+    Duration::from_millis(rng.gen_range(10..max_ms))
+}
+
+/// How a device spends the time between solving a job and submitting it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pacing {
+    /// Sleep on the open connection until the share is due at the target rate.
+    Hold,
+    /// Submit right away and leave the idle time to the caller.
+    Release,
+}
+
+/// Transports of devices that list none.
+const DIRECT: &[Transport] = &[Transport::Tcp];
+
+/// The byte stream to the pool, counting and throttling what passes through.
+struct Link<'a> {
+    stream: Box<dyn Stream>,
+    stats: &'a DeviceStats,
+    throttle: Option<&'a Throttle>,
+    recorder: Option<&'a Recorder>,
+    reader: MessageReader,
+}
+
+impl<'a> Link<'a> {
+    fn send(&mut self, message: &str) -> Result<(), MinerError> {
+        if let Some(throttle) = self.throttle {
+            throttle.acquire(message.len());
+        }
+
+        self.stream
+            .write(message.as_bytes())
+            .map_err(|_| MinerError::SendCommand)?;
+        self.stats.add_sent(message.len());
+        if let Some(recorder) = self.recorder {
+            if let Err(e) = recorder.record(self.stats.name(), Direction::Sent, message) {
+                warn!("could not record transcript: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<&str, MinerError> {
+        self.recv_message(false)
+    }
+
+    /// The pool's greeting, which may come without a newline.
+    fn recv_greeting(&mut self) -> Result<&str, MinerError> {
+        self.recv_message(true)
+    }
+
+    fn recv_message(&mut self, greeting: bool) -> Result<&str, MinerError> {
+        let message = if greeting {
+            self.reader.read_greeting(&mut self.stream)?
+        } else {
+            self.reader.read(&mut self.stream)?
+        };
+        self.stats.add_received(message.len());
+        if let Some(recorder) = self.recorder {
+            if let Err(e) = recorder.record(self.stats.name(), Direction::Received, message) {
+                warn!("could not record transcript: {}", e);
+            }
+        }
+
+        if let Some(throttle) = self.throttle {
+            throttle.acquire(message.len());
+        }
+
+        Ok(message)
+    }
+}
+
+/// Settings shared by every device's connection to the pool.
+#[derive(Clone)]
+struct SessionOptions {
+    share_log: Option<Arc<ShareLog>>,
+    capture: Option<Arc<JobCapture>>,
+    throttle: Option<Arc<Throttle>>,
+    recorder: Option<Arc<Recorder>>,
+    brake: Option<Arc<SafetyBrake>>,
+    tuner: Option<Arc<DifficultyTuner>>,
+    pools: Option<Arc<PoolSelector>>,
+    stagger: Option<Arc<Stagger>>,
+    paced_search: bool,
+    observe: bool,
+    profiles: Arc<BTreeMap<String, Profile>>,
+    restart: RestartPolicy,
+    too_slow: Option<SlowConfig>,
+    keepalive: Option<Duration>,
+    max_message_len: usize,
+    max_heatup: Duration,
+}
+
+impl SessionOptions {
+    /// The fastest probed pool, when pools are probed.
+    fn best_pool(&self) -> Option<String> {
+        self.pools.as_ref().and_then(|pools| pools.best())
+    }
+    /// Reports a failed connection to the safety brake, and a misbehaving
+    /// `pool` to the pool blocklist.
+    fn record_error(&self, device: &Device, stats: &DeviceStats, pool: &str, e: &MinerError) {
+        if let Some(brake) = &self.brake {
+            if e.is_connection_failure() {
+                brake.record(&device.username, stats, true);
+            }
+        }
+        if let Some(pools) = &self.pools {
+            if e.is_pool_failure() {
+                pools.blocklist().strike(pool, unix_now());
+            }
+        }
+    }
+}
+
+/// A device's connection to the pool.
+struct Session<'a> {
+    link: Link<'a>,
+    device: Device,
+    stats: &'a DeviceStats,
+    share_log: Option<&'a ShareLog>,
+    capture: Option<&'a JobCapture>,
+    brake: Option<&'a SafetyBrake>,
+    tuner: Option<&'a DifficultyTuner>,
+    stagger: Option<&'a Stagger>,
+    submitted: &'a SubmittedShares,
+    paced_search: bool,
+    observe: bool,
+    too_slow: Option<&'a SlowConfig>,
+    profile: Profile,
+}
+
+impl<'a> Session<'a> {
+    /// Connects `device` to `pool` over the first of its transports that
+    /// connects, and reads the server version.
+    fn connect(
+        pool: &str,
+        device: Device,
+        stats: &'a DeviceStats,
+        submitted: &'a SubmittedShares,
+        options: &'a SessionOptions,
+    ) -> Result<Self, MinerError> {
+        let transports = match device.transports.as_slice() {
+            [] => DIRECT,
+            transports => transports,
+        };
+        let (stream, transport) = transports
+            .iter()
+            .find_map(
+                |transport| match transport::connect(transport, pool, options.keepalive) {
+                    Ok(stream) => Some((stream, transport)),
+                    Err(e) => {
+                        info!(
+                            "{} could not connect over {}: {}",
+                            device.name(),
+                            transport,
+                            e
+                        );
+                        None
+                    }
+                },
+            )
+            .ok_or(MinerError::Connection)?;
+        stats.set_transport(transport.to_string());
+        let profile = device
+            .profile
+            .as_ref()
+            .and_then(|name| options.profiles.get(name))
+            .cloned()
+            .unwrap_or_default();
+        let mut session = Self {
+            link: Link {
+                stream,
+                stats,
+                throttle: options.throttle.as_deref(),
+                recorder: options.recorder.as_deref(),
+                reader: MessageReader::new(options.max_message_len),
+            },
+            device,
+            stats,
+            share_log: options.share_log.as_deref(),
+            capture: options.capture.as_deref(),
+            brake: options.brake.as_deref(),
+            tuner: options.tuner.as_deref(),
+            stagger: options.stagger.as_deref(),
+            submitted,
+            paced_search: options.paced_search,
+            observe: options.observe,
+            too_slow: options.too_slow.as_ref(),
+            profile,
+        };
+
+        // Read outside of `info!`, whose arguments are skipped when the
+        // level is off, or the greeting would be taken for the first job.
+        let version = normalize(session.link.recv_greeting()?);
+        info!("version: {}", version);
+
+        Ok(session)
+    }
+
+    /// Counts a share solved at `rate` against the target rate of `control`.
+    /// Once enough slow shares come in a row, emits a `too_slow` event and,
+    /// if configured, lowers the target rate to `rate`.
+    fn check_pace(&self, control: &DeviceControl, rate: f64) {
+        let default = SlowConfig::default();
+        let policy = self.too_slow.unwrap_or(&default);
+        let target_rate = control.target_rate();
+        if !is_slow(rate, target_rate, policy.ratio) {
+            self.stats.end_slow_streak();
+            return;
+        }
+
+        let streak = self.stats.add_slow();
+        if self.too_slow.is_none() || streak < policy.after as u64 {
+            return;
+        }
+        self.stats.end_slow_streak();
+
+        let mut message = format!(
+            "{} shares in a row solved at {:.2} H/s, short of the target rate of {}",
+            streak, rate, target_rate
+        );
+        if policy.reduce_target {
+            let reduced = (rate as u32).clamp(1, target_rate.max(1));
+            control.set_target_rate(reduced);
+            message.push_str(&format!(", lowered to {}", reduced));
+        }
+        warn!("{} {}", self.device.name(), message);
+        self.stats.event(EventKind::TooSlow, message);
+    }
+
+    /// Fetches a job, solves it and submits the share at the target rate of
+    /// `control`. Returns how long the device should stay idle before
+    /// fetching its next job.
+    fn mine_share(
+        &mut self,
+        hashing: &HashService,
+        control: &DeviceControl,
+        pacing: Pacing,
+    ) -> Result<Duration, MinerError> {
+        let device = &self.device;
+        let stats = self.stats;
+        let target_rate = control.target_rate();
+
+        if let (Some(stagger), Pacing::Hold) = (self.stagger, pacing) {
+            let now = Instant::now();
+            std::thread::sleep(stagger.slot(now) - now);
+        }
+
+        let difficulty = match self.tuner {
+            Some(tuner) => tuner.difficulty(device),
+            None => device.difficulty().to_string(),
+        };
+        let cmd_job = JobRequest {
+            username: &device.username,
+            device_type: &difficulty,
+            mining_key: device.mining_key.as_deref(),
+        }
+        .to_line_with(
+            self.profile
+                .job_request
+                .as_deref()
+                .unwrap_or(DEFAULT_JOB_REQUEST),
+            &self.profile.fields,
+        );
+        let job_requested = Instant::now();
+        self.link.send(&cmd_job)?;
+
+        let job = self.link.recv()?;
+        stats.job_latency.record(job_requested.elapsed());
+        if let Some(capture) = self.capture {
+            if let Err(e) = capture.record(stats.name(), Kind::Job, normalize(job)) {
+                warn!("could not capture job: {}", e);
+            }
+        }
+        let job = Job::parse(job)?;
+        let diff = job.max_nonce()?;
+        stats.add_job(job.difficulty);
+
+        info!(
+            "last: {}, expected: {}, diff: {}",
+            job.last_block_hash, job.expected_hash, diff
+        );
+
+        if self.observe {
+            // Fetch the next job after about as long as solving this one
+            // would take on average.
+            let wait = Duration::from_micros(pacing_duration(diff / 2, target_rate) as u64);
+            return match pacing {
+                Pacing::Hold => {
+                    std::thread::sleep(wait);
+                    Ok(Duration::from_micros(0))
+                }
+                Pacing::Release => Ok(wait),
+            };
+        }
+
+        if let Some(nonce) = self.submitted.solved(&job) {
+            warn!(
+                "{} was reissued a job it solved with result {}, fetching a new one",
+                device.name(),
+                nonce
+            );
+            return match pacing {
+                Pacing::Hold => {
+                    std::thread::sleep(REISSUED_JOB_DELAY);
+                    Ok(Duration::from_micros(0))
+                }
+                Pacing::Release => Ok(REISSUED_JOB_DELAY),
+            };
+        }
+
+        let start = Instant::now();
+
+        let duco_numeric_result = if self.paced_search && pacing == Pacing::Hold {
+            paced_search(&job, diff, target_rate)
+        } else {
+            hashing.solve(&job)
+        }
+        .unwrap_or(0);
+        // Searches start at nonce 0, so the answer is the number of hashes.
+        let hashes = duco_numeric_result;
+
+        let duration = start.elapsed().as_micros();
+        let real_rate = hash_rate(hashes, duration);
+
+        if !self.submitted.insert(&job, duco_numeric_result) {
+            warn!(
+                "{} already submitted result {} for block {}, skipping",
+                device.name(),
+                duco_numeric_result,
+                job.last_block_hash
+            );
+            return Ok(Duration::from_micros(0));
+        }
+
+        let expected_duration = pacing_duration(hashes, target_rate);
+        stats.solve_lag.record(Duration::from_micros(
+            duration.saturating_sub(expected_duration) as u64,
+        ));
+        // An answer at nonce 0, or a failed search, took no hashes and says
+        // nothing about the device's pace.
+        if hashes > 0 {
+            self.check_pace(control, hash_rate(hashes, duration));
+        }
+        let mut idle = Duration::from_micros(0);
+
+        if duration < expected_duration {
+            let wait_duration = (expected_duration - duration) as u64;
+            match pacing {
+                Pacing::Hold => {
+                    std::thread::sleep(Duration::from_micros(wait_duration));
+                    info!("waited {} micro sec", wait_duration);
+                }
+                Pacing::Release => idle = Duration::from_micros(wait_duration),
+            }
+        } else {
+            warn!(
+                "system too slow, lag {} micro sec",
+                duration - expected_duration
+            );
+        }
+
+        let duration = match pacing {
+            Pacing::Hold => start.elapsed().as_micros(),
+            Pacing::Release => duration.max(expected_duration),
+        };
+        let emu_rate = hash_rate(hashes, duration);
+        stats.set_reported_rate(emu_rate);
+
+        // let lag_duration: u64 = rand::thread_rng().gen_range(0..100);
+        // tokio::time::sleep(Duration::from_millis(lag_duration)).await;
+
+        let cmd_out = Share {
+            nonce: duco_numeric_result,
+            rate: emu_rate,
+            firmware: &device.firmware,
+            device_name: &device.device_name,
+            chip_id: &device.chip_id,
+            username: &device.username,
+            wallet_id: device.wallet_id.as_deref(),
+        }
+        .to_line_with(
+            self.profile
+                .submission
+                .as_deref()
+                .unwrap_or(DEFAULT_SUBMISSION),
+            &self.profile.fields,
+        );
+        let share_submitted = Instant::now();
+        self.link.send(&cmd_out)?;
+
+        let resp = normalize(self.link.recv()?);
+        let response = ShareResponse::parse(resp);
+        stats.submit_latency.record(share_submitted.elapsed());
+        if let Some(capture) = self.capture {
+            if let Err(e) = capture.record(stats.name(), Kind::Response, resp) {
+                warn!("could not capture response: {}", e);
+            }
+        }
+
+        if let Some(share_log) = self.share_log {
+            let record = ShareRecord {
+                device: stats.name(),
+                nonce: duco_numeric_result,
+                duration_us: duration,
+                response: resp,
+                reported_rate: emu_rate,
+            };
+            if let Err(e) = share_log.record(&record) {
+                warn!("could not write share log: {}", e);
+            }
+        }
+
+        match response {
+            ShareResponse::Good => {
+                stats.add_accepted();
+                info!(
+                    "result good, result: {}, rate: {:.2}, real: {:.2}",
+                    duco_numeric_result, emu_rate, real_rate
+                );
+            }
+            ShareResponse::Block => {
+                stats.add_block();
+                stats.event(
+                    EventKind::BlockFound,
+                    format!("found block with result {}", duco_numeric_result),
+                );
+                info!(
+                    "FOUND BLOCK!, result: {}, rate: {:.2}, real: {:.2}",
+                    duco_numeric_result, emu_rate, real_rate
+                );
+            }
+            ShareResponse::Rejected(reason) => {
+                stats.add_rejected();
+                stats.event(EventKind::Rejected, format!("share rejected: {}", reason));
+                warn!(
+                    "resp: {}, result: {}, rate: {:.2}, real: {:.2}",
+                    reason, duco_numeric_result, emu_rate, real_rate
+                );
+            }
+        }
+
+        if let Some(brake) = self.brake {
+            brake.record(&device.username, stats, !response.is_accepted());
+        }
+
+        if let Some(tuner) = self.tuner {
+            tuner.record(device, Duration::from_micros(duration as u64));
+        }
+
+        if let Some(stagger) = self.stagger {
+            stagger.record(Duration::from_micros(duration as u64));
+        }
+
+        Ok(idle)
+    }
+}
+
+fn start_miner(
+    control: &DeviceControl,
+    pool: String,
+    hashing: &HashService,
+    stats: &DeviceStats,
+    submitted: &SubmittedShares,
+    options: &SessionOptions,
+    rng: &mut StdRng,
+) -> Result<(), MinerError> {
+    let device = control.device();
+
+    std::thread::sleep(heatup(rng, options.max_heatup));
+
+    let device_name = device.name();
+    let mut session = Session::connect(&pool, device, stats, submitted, options)?;
+
+    info!("{} connected to pool {}", device_name, pool);
+    stats.set_connected(true);
+    stats.event(EventKind::Connected, format!("connected to {}", pool));
+
+    loop {
+        if control.should_disconnect() {
+            return Ok(());
+        }
+
+        session.mine_share(hashing, control, Pacing::Hold)?;
+    }
+}
+
+fn start_miner_loop(
+    control: Arc<DeviceControl>,
+    pool: Option<String>,
+    hashing: Arc<HashService>,
+    stats: Arc<DeviceStats>,
+    options: SessionOptions,
+    mut rng: StdRng,
+) {
+    info!("Spawning {}...", control.device().name());
+
+    let submitted = SubmittedShares::new();
+    let mut restarts = Restarts::new();
+    while !control.is_shutdown() {
+        if control.is_paused() {
+            control.wait_while_paused();
+            continue;
+        }
+
+        let pool = if let Some(pool) = pool.clone().or_else(|| options.best_pool()) {
+            pool
+        } else {
+            Client::shared()
+                .get_pool()
+                .map(|pool| pool.address())
+                .unwrap_or(format!("{}:{}", "server.duinocoin.com", 2813))
+        };
+
+        let shares = stats.accepted() + stats.rejected() + stats.blocks();
+        let result = supervisor::catch(|| {
+            start_miner(
+                &control,
+                pool.clone(),
+                &hashing,
+                &stats,
+                &submitted,
+                &options,
+                &mut rng,
+            )
+        })
+        .unwrap_or_else(|panic| Err(panicked(&control, &stats, panic)));
+        stats.set_connected(false);
+
+        // Not `>`, as the stats may have been reset meanwhile.
+        if stats.accepted() + stats.rejected() + stats.blocks() != shares {
+            restarts.succeeded();
+        }
+
+        match result {
+            Ok(_) => {
+                info!("{} disconnected", control.device().name());
+                stats.event(EventKind::Disconnected, "disconnected".to_string());
+            }
+            Err(e) => {
+                error!("exited with error: {:?}", e);
+                stats.event(EventKind::Disconnected, format!("exited with error: {}", e));
+                options.record_error(&control.device(), &stats, &pool, &e);
+
+                let delay = react_to_error(&control, &stats, &options, &mut restarts, &e);
+                control.sleep(delay);
+            }
+        }
+    }
+}
+
+/// Logs a panic caught while `control`'s device was mining and counts it.
+/// Returns it as an error, so the device restarts as after any other failure.
+fn panicked(control: &DeviceControl, stats: &DeviceStats, panic: Panic) -> MinerError {
+    error!(
+        "{} panicked: {}\n{}",
+        control.device().name(),
+        panic.message,
+        panic.backtrace
+    );
+    stats.add_panic();
+
+    MinerError::Panicked(panic.message)
+}
+
+/// Pauses the device or stops the miner when `e` or the device's restart
+/// policy calls for it, and returns how long the device should wait before
+/// reconnecting.
+fn react_to_error(
+    control: &DeviceControl,
+    stats: &DeviceStats,
+    options: &SessionOptions,
+    restarts: &mut Restarts,
+    e: &MinerError,
+) -> Duration {
+    let device = control.device();
+    let device_name = device.name();
+    let policy = device.restart.as_ref().unwrap_or(&options.restart);
+
+    match e.severity() {
+        Severity::Pause => {
+            error!("{} paused until resumed: {}", device_name, e);
+            control.set_paused(true);
+        }
+        Severity::Fatal => {
+            error!("{} stopping the miner: {}", device_name, e);
+            control.shutdown_fleet();
+        }
+        Severity::Retry | Severity::Backoff => {}
+    }
+
+    match restarts.next(policy, e.severity(), Instant::now()) {
+        Some(delay) => delay,
+        None => {
+            let message = format!(
+                "gave up after {} restarts within an hour, paused until resumed",
+                policy.max_per_hour.unwrap_or_default()
+            );
+            error!("{} {}", device_name, message);
+            if policy.notify {
+                stats.event(EventKind::GaveUp, message);
+            }
+            control.set_paused(true);
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// State shared by the workers of a pooled miner.
+struct WorkerPool {
+    control: Arc<FleetControl>,
+    fleet: Arc<FleetStats>,
+    schedule: Schedule,
+    pool: Option<String>,
+    options: SessionOptions,
+    submitted: Vec<SubmittedShares>,
+    restarts: Vec<Mutex<Restarts>>,
+}
+
+/// Connects device `index` for a single share. Returns how long the device
+/// should stay idle before its next job fetch.
+fn mine_pooled(
+    workers: &WorkerPool,
+    index: usize,
+    pool: &str,
+    hashing: &HashService,
+) -> Result<Duration, MinerError> {
+    let control = workers.control.device(index);
+    let stats = workers.fleet.device(index);
+
+    let mut session = Session::connect(
+        pool,
+        control.device(),
+        &stats,
+        &workers.submitted[index],
+        &workers.options,
+    )?;
+    stats.set_connected(true);
+
+    session.mine_share(hashing, &control, Pacing::Release)
+}
+
+fn start_worker(workers: Arc<WorkerPool>, hashing: Arc<HashService>) {
+    let mut pool = workers.pool.clone();
+
+    while let Some(index) = workers.schedule.next(|| workers.control.is_shutdown()) {
+        let control = workers.control.device(index);
+        if control.is_paused() {
+            // With the whole fleet paused, sleep until it resumes rather
+            // than cycling through paused devices every second.
+            let due = if workers.control.is_idle() {
+                workers.control.wait_until_active();
+                Instant::now()
+            } else {
+                Instant::now() + Duration::from_secs(1)
+            };
+            workers.schedule.push(due, index);
+            continue;
+        }
+
+        let pool_addr = match (&workers.pool, workers.options.best_pool()) {
+            (None, Some(best)) => best,
+            _ => pool
+                .get_or_insert_with(|| {
+                    Client::shared()
+                        .get_pool()
+                        .map(|pool| pool.address())
+                        .unwrap_or(format!("{}:{}", "server.duinocoin.com", 2813))
+                })
+                .clone(),
+        };
+
+        let result = supervisor::catch(|| mine_pooled(&workers, index, &pool_addr, &hashing))
+            .unwrap_or_else(|panic| Err(panicked(&control, &workers.fleet.device(index), panic)));
+        // The session is dropped with each share, success or not.
+        workers.fleet.device(index).set_connected(false);
+
+        let due = match result {
+            Ok(idle) => {
+                workers.restarts[index].lock().unwrap().succeeded();
+                match &workers.options.stagger {
+                    Some(stagger) => stagger.slot(Instant::now() + idle),
+                    None => Instant::now() + idle,
+                }
+            }
+            Err(e) => {
+                let stats = workers.fleet.device(index);
+                error!("{} exited with error: {:?}", control.device().name(), e);
+                stats.event(EventKind::Disconnected, format!("exited with error: {}", e));
+                workers
+                    .options
+                    .record_error(&control.device(), &stats, &pool_addr, &e);
+                pool = workers.pool.clone();
+
+                let mut restarts = workers.restarts[index].lock().unwrap();
+                Instant::now()
+                    + react_to_error(&control, &stats, &workers.options, &mut restarts, &e)
+            }
+        };
+        workers.schedule.push(due, index);
+    }
+}
+
+/// A fleet of emulated devices mining in background threads.
+pub struct Miner {
+    control: Arc<FleetControl>,
+    fleet: Arc<FleetStats>,
+    pool: Option<String>,
+    options: SessionOptions,
+    hash_threads: Option<usize>,
+    solution_cache_len: usize,
+    seed: Option<u64>,
+    workers: Option<usize>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Miner {
+    /// Creates a miner for the devices in `config`. `config_files` are the
+    /// paths the config was loaded from, used to reload it at runtime.
+    pub fn new(config: Config, config_files: Vec<String>) -> Self {
+        let fleet = Arc::new(FleetStats::new(
+            config.devices.iter().map(|d| d.name()).collect(),
+        ));
+        for (name, profile) in config.profiles.iter() {
+            let templates = [
+                (&profile.submission, SHARE_FIELDS),
+                (&profile.job_request, JOB_REQUEST_FIELDS),
+            ];
+            for (template, fields) in templates.iter() {
+                if let Some(template) = template {
+                    if let Err(e) = check_template(template, fields, &profile.fields) {
+                        warn!("profile {}: {}", name, e);
+                    }
+                }
+            }
+        }
+        for device in config.devices.iter() {
+            if let Some(profile) = &device.profile {
+                if !config.profiles.contains_key(profile) {
+                    warn!(
+                        "{} uses unknown profile {}, using defaults",
+                        device.name(),
+                        profile
+                    );
+                }
+            }
+        }
+        let control = Arc::new(FleetControl::new(config.devices, config_files));
+
+        let brake_config = config.safety_brake.unwrap_or_default();
+        let brake = if brake_config.enabled {
+            Some(Arc::new(SafetyBrake::new(brake_config, control.clone())))
+        } else {
+            None
+        };
+        let tuner = config
+            .auto_difficulty
+            .filter(|tuner_config| tuner_config.enabled)
+            .map(|tuner_config| Arc::new(DifficultyTuner::new(tuner_config)));
+        let pools = config
+            .pool_selection
+            .filter(|selection_config| selection_config.enabled)
+            .map(|selection_config| Arc::new(PoolSelector::new(selection_config)));
+
+        Self {
+            control,
+            fleet,
+            pool: None,
+            options: SessionOptions {
+                share_log: None,
+                capture: None,
+                throttle: None,
+                recorder: None,
+                brake,
+                tuner,
+                pools,
+                stagger: None,
+                paced_search: false,
+                observe: false,
+                profiles: Arc::new(config.profiles),
+                restart: config.restart.unwrap_or_default(),
+                too_slow: config.too_slow,
+                keepalive: config
+                    .keepalive
+                    .map(|keepalive| Duration::from_secs(keepalive.idle_secs)),
+                max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+                max_heatup: DEFAULT_MAX_HEATUP,
+            },
+            hash_threads: None,
+            solution_cache_len: DEFAULT_SOLUTION_CACHE_LEN,
+            seed: None,
+            workers: None,
+            handles: vec![],
+        }
+    }
+
+    /// Keeps the bans of probed pools in `path` across restarts.
+    pub fn with_pool_banlist(self, path: Option<PathBuf>) -> Self {
+        if let (Some(pools), Some(path)) = (&self.options.pools, path) {
+            pools.blocklist().load(path);
+        }
+        self
+    }
+
+    /// Mines on `pool` instead of asking the pool balancer on each connect,
+    /// or probing pools when the config has a `pool_selection` section.
+    pub fn with_pool(mut self, pool: Option<String>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    pub fn with_share_log(mut self, share_log: Option<Arc<ShareLog>>) -> Self {
+        self.options.share_log = share_log;
+        self
+    }
+
+    /// Appends the jobs and share responses each device receives to
+    /// `capture`.
+    pub fn with_job_capture(mut self, capture: Option<Arc<JobCapture>>) -> Self {
+        self.options.capture = capture;
+        self
+    }
+
+    /// Appends every message exchanged with the pool to `recorder`, for
+    /// replaying later.
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.options.recorder = recorder;
+        self
+    }
+
+    /// Seeds each device's random number generator from `seed`, so that runs
+    /// are reproducible.
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Limits the size of a single message from the pool. Devices that
+    /// receive a longer one disconnect with an error.
+    pub fn with_max_message_len(mut self, max_message_len: usize) -> Self {
+        self.options.max_message_len = max_message_len;
+        self
+    }
+
+    /// Waits less than `max_heatup` at random before each device connects.
+    /// Defaults to [`DEFAULT_MAX_HEATUP`].
+    pub fn with_max_heatup(mut self, max_heatup: Duration) -> Self {
+        self.options.max_heatup = max_heatup;
+        self
+    }
+
+    /// Remembers up to `capacity` solved jobs, so devices handed a job
+    /// another device already solved answer without searching. Answers are
+    /// still paced to each device's target rate. A capacity of 0 disables
+    /// the cache.
+    pub fn with_solution_cache(mut self, capacity: usize) -> Self {
+        self.solution_cache_len = capacity;
+        self
+    }
+
+    /// Spaces the fleet's job fetches evenly across the time a device
+    /// spends on a share, instead of letting devices started together
+    /// fetch, hash and submit in bursts.
+    pub fn with_stagger(mut self, stagger: bool) -> Self {
+        self.options.stagger = if stagger {
+            Some(Arc::new(Stagger::new(self.control.devices().len())))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Has each device search its own nonces, sleeping between hashes to
+    /// keep its target rate, rather than solving at full speed on the shared
+    /// hashing threads and sleeping before submitting. CPU use is smooth and
+    /// timing authentic, but identical jobs are no longer searched once.
+    /// Ignored with [`Miner::with_workers`], whose devices do not hold a
+    /// thread while mining.
+    pub fn with_paced_search(mut self, paced_search: bool) -> Self {
+        self.options.paced_search = paced_search;
+        self
+    }
+
+    /// Fetches jobs without ever solving them or submitting shares, only
+    /// keeping stats of the jobs.
+    pub fn with_observe(mut self, observe: bool) -> Self {
+        self.options.observe = observe;
+        self
+    }
+
+    /// Searches nonces on `threads` shared threads, however many devices
+    /// there are. Defaults to the number of CPUs.
+    pub fn with_hash_threads(mut self, threads: Option<usize>) -> Self {
+        self.hash_threads = threads;
+        self
+    }
+
+    /// Limits the combined protocol traffic of all devices to
+    /// `bytes_per_sec`.
+    pub fn with_bandwidth_limit(mut self, bytes_per_sec: Option<u64>) -> Self {
+        self.options.throttle = bytes_per_sec.map(|limit| Arc::new(Throttle::new(limit)));
+        self
+    }
+
+    /// Mines with a fixed pool of `workers` threads instead of a thread per
+    /// device. Devices connect for each share and wait for their next job
+    /// off the socket, so open connections are bounded by the worker count.
+    /// A device reports as connected only while it holds a connection, so
+    /// its uptime counts the time spent on shares.
+    pub fn with_workers(mut self, workers: Option<usize>) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    pub fn fleet(&self) -> &Arc<FleetStats> {
+        &self.fleet
+    }
+
+    pub fn control(&self) -> &Arc<FleetControl> {
+        &self.control
+    }
+
+    pub fn start(&mut self) {
+        let hash_threads = self.hash_threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        });
+        let hashing = Arc::new(HashService::new(hash_threads, self.solution_cache_len));
+
+        if let (None, Some(pools)) = (&self.pool, &self.options.pools) {
+            pools.clone().start(self.control.clone());
+        }
+
+        if let Some(workers) = self.workers {
+            self.start_pooled(workers, hashing);
+            return;
+        }
+
+        for i in 0..self.control.devices().len() {
+            let device = self.control.device(i);
+            let hashing = hashing.clone();
+            let pool = self.pool.clone();
+            let stats = self.fleet.device(i);
+            let options = self.options.clone();
+            let rng = match self.seed {
+                Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(i as u64)),
+                None => StdRng::from_entropy(),
+            };
+
+            let handle = spawn_thread(move || {
+                start_miner_loop(device, pool, hashing, stats, options, rng);
+            });
+            self.handles.push(handle);
+        }
+    }
+
+    fn start_pooled(&mut self, workers: usize, hashing: Arc<HashService>) {
+        let device_count = self.control.devices().len();
+        let workers = workers.clamp(1, device_count.max(1));
+        info!(
+            "Spawning {} workers for {} devices...",
+            workers, device_count
+        );
+
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let schedule = Schedule::new();
+        let now = Instant::now();
+        for i in 0..device_count {
+            schedule.push(now + heatup(&mut rng, self.options.max_heatup), i);
+        }
+
+        let pool = Arc::new(WorkerPool {
+            control: self.control.clone(),
+            fleet: self.fleet.clone(),
+            schedule,
+            pool: self.pool.clone(),
+            options: self.options.clone(),
+            submitted: (0..device_count).map(|_| SubmittedShares::new()).collect(),
+            restarts: (0..device_count)
+                .map(|_| Mutex::new(Restarts::new()))
+                .collect(),
+        });
+
+        for _ in 0..workers {
+            let pool = pool.clone();
+            let hashing = hashing.clone();
+
+            let handle = spawn_thread(move || start_worker(pool, hashing));
+            self.handles.push(handle);
+        }
+    }
+
+    /// Blocks until every device thread has exited.
+    pub fn wait(&mut self) {
+        for handle in self.handles.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+
+    /// Asks every device to disconnect at its next job boundary and waits
+    /// for the device threads to exit.
+    pub fn stop(&mut self) {
+        self.control.shutdown();
+        self.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_avr_rates() {
+        assert_eq!(pacing_duration(380, 190), 2_000_000);
+    }
+
+    #[test]
+    fn paces_pc_rates() {
+        assert_eq!(pacing_duration(3_000_000, 1_500_000), 2_000_000);
+        assert_eq!(pacing_duration(1_000_000, 3_000_000), 333_333);
+    }
+
+    #[test]
+    fn slow_shares_fall_short_by_more_than_ratio() {
+        assert!(!is_slow(190.0, 190, 0.9));
+        assert!(!is_slow(171.0, 190, 0.9));
+        assert!(is_slow(170.9, 190, 0.9));
+        assert!(is_slow(0.0, 190, 0.9));
+    }
+
+    #[test]
+    fn rate_of_typical_share() {
+        assert_eq!(hash_rate(500, 2_000_000), 250.0);
+    }
+
+    #[test]
+    fn rate_of_nonce_zero_is_zero() {
+        assert_eq!(hash_rate(0, 0), 0.0);
+        assert_eq!(hash_rate(0, 1500), 0.0);
+    }
+
+    #[test]
+    fn rate_of_instant_solution_is_finite() {
+        let rate = hash_rate(1, 0);
+
+        assert!(rate.is_finite());
+        assert_eq!(rate, hash_rate(1, MIN_DURATION_US));
+    }
+
+    #[test]
+    fn rate_of_tiny_nonce() {
+        assert_eq!(hash_rate(1, 1_000_000), 1.0);
+        assert_eq!(hash_rate(3, 10), 300000.0);
+    }
+}
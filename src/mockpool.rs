@@ -0,0 +1,317 @@
+//! A pool speaking the Duino protocol in-process, to test the whole mining
+//! flow without the network. Every job it hands out is answered by the same
+//! nonce, so shares can be checked without searching.
+
+use sha1::{Digest, Sha1};
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const VERSION: &str = "2.7\n";
+
+/// A share line received by the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submission {
+    pub line: String,
+    pub accepted: bool,
+    /// Time from sending the job to receiving the share.
+    pub elapsed: Duration,
+}
+
+struct State {
+    nonce: u64,
+    shares_per_connection: Option<usize>,
+    jobs: AtomicU64,
+    connections: AtomicUsize,
+    submissions: Mutex<Vec<Submission>>,
+}
+
+impl State {
+    /// A new job, answered by `nonce`. Each job has its own last block hash,
+    /// so devices never see one they already solved.
+    fn job(&self) -> String {
+        let id = self.jobs.fetch_add(1, Ordering::Relaxed);
+        let last = hex::encode(Sha1::digest(id.to_string().as_bytes()));
+        let expected = hex::encode(Sha1::digest(format!("{}{}", last, self.nonce).as_bytes()));
+
+        format!("{},{},{}\n", last, expected, self.nonce / 100 + 1)
+    }
+
+    fn serve(&self, stream: TcpStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut stream = stream;
+        stream.write_all(VERSION.as_bytes())?;
+
+        let mut job_sent = None;
+        let mut shares = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            if line.starts_with("JOB,") {
+                stream.write_all(self.job().as_bytes())?;
+                job_sent = Some(Instant::now());
+                continue;
+            }
+
+            let accepted = line.split(',').next() == Some(self.nonce.to_string().as_str());
+            let response = if accepted {
+                "GOOD\n"
+            } else {
+                "BAD,Incorrect result\n"
+            };
+            stream.write_all(response.as_bytes())?;
+            self.submissions.lock().unwrap().push(Submission {
+                line: line.trim_end().to_string(),
+                accepted,
+                elapsed: job_sent
+                    .take()
+                    .map_or(Duration::ZERO, |sent| sent.elapsed()),
+            });
+
+            shares += 1;
+            if Some(shares) == self.shares_per_connection {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub struct MockPool {
+    addr: SocketAddr,
+    state: Arc<State>,
+}
+
+impl MockPool {
+    /// Starts a pool on a free local port whose jobs are answered by
+    /// `nonce`. With `shares_per_connection`, the pool hangs up after that
+    /// many shares, so devices have to reconnect.
+    pub fn start(nonce: u64, shares_per_connection: Option<usize>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State {
+            nonce,
+            shares_per_connection,
+            jobs: AtomicU64::new(0),
+            connections: AtomicUsize::new(0),
+            submissions: Mutex::new(Vec::new()),
+        });
+
+        let pool = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                pool.connections.fetch_add(1, Ordering::Relaxed);
+                let pool = pool.clone();
+                std::thread::spawn(move || pool.serve(stream));
+            }
+        });
+
+        Ok(Self { addr, state })
+    }
+
+    /// The address to mine on, as `host:port`.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn submissions(&self) -> Vec<Submission> {
+        self.state.submissions.lock().unwrap().clone()
+    }
+
+    /// Waits until `count` shares were submitted or `timeout` elapsed, and
+    /// returns those submitted.
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> Vec<Submission> {
+        let deadline = Instant::now() + timeout;
+        while self.state.submissions.lock().unwrap().len() < count && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        self.submissions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::miner::Miner;
+
+    /// Answers jobs of difficulty 43, taking 0.1 s at the devices' rate.
+    const NONCE: u64 = 4242;
+    const TARGET_RATE: u32 = 42420;
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    fn miner(pool: &MockPool) -> Miner {
+        let config = Config::from_yaml(&format!(
+            "devices:\n\
+             - username: alice\n  device_name: avr-1\n  device_type: AVR\n  \
+             chip_id: DUCOID0123456789\n  firmware: Official AVR Miner v2.6\n  \
+             target_rate: {}\n\
+             restart:\n  backoff_secs: 0.1\n",
+            TARGET_RATE
+        ))
+        .unwrap();
+
+        Miner::new(config, Vec::new())
+            .with_pool(Some(pool.addr()))
+            .with_seed(Some(1))
+            .with_max_heatup(Duration::from_millis(20))
+    }
+
+    /// Runs `test` on its own thread and fails if it takes longer than
+    /// `timeout`, so a device stuck reconnecting fails the test instead of
+    /// hanging it.
+    fn within(timeout: Duration, test: impl FnOnce() + Send + 'static) {
+        let (done, finished) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            test();
+            let _ = done.send(());
+        });
+
+        match finished.recv_timeout(timeout) {
+            Ok(()) => thread.join().unwrap(),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                std::panic::resume_unwind(thread.join().unwrap_err())
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                panic!("test did not finish within {:?}", timeout)
+            }
+        }
+    }
+
+    fn fields(submission: &Submission) -> Vec<&str> {
+        submission.line.split(',').collect()
+    }
+
+    #[test]
+    fn submits_solved_shares() {
+        let pool = MockPool::start(NONCE, None).unwrap();
+        let mut miner = miner(&pool);
+
+        miner.start();
+        let submissions = pool.wait_for(2, TIMEOUT);
+        miner.stop();
+
+        assert!(submissions.len() >= 2);
+        for submission in submissions.iter() {
+            assert!(submission.accepted, "rejected {}", submission.line);
+            let fields = fields(submission);
+            assert_eq!(fields[0], "4242");
+            assert_eq!(
+                &fields[2..],
+                &["Official AVR Miner v2.6", "avr-1", "DUCOID0123456789"]
+            );
+        }
+        assert!(miner.fleet().device(0).accepted() >= 2);
+        assert_eq!(pool.connections(), 1);
+    }
+
+    #[test]
+    fn paces_shares_to_target_rate() {
+        let pool = MockPool::start(NONCE, None).unwrap();
+        let mut miner = miner(&pool);
+
+        miner.start();
+        let submissions = pool.wait_for(2, TIMEOUT);
+        miner.stop();
+
+        let due = Duration::from_micros(NONCE * 1_000_000 / TARGET_RATE as u64);
+        assert!(!submissions.is_empty());
+        for submission in submissions.iter() {
+            assert!(submission.elapsed >= due, "{:?}", submission.elapsed);
+            assert!(submission.elapsed < due * 10, "{:?}", submission.elapsed);
+
+            let rate: f64 = fields(submission)[1].parse().unwrap();
+            assert!(rate <= TARGET_RATE as f64, "{}", rate);
+            assert!(rate > TARGET_RATE as f64 / 10f64, "{}", rate);
+        }
+    }
+
+    #[test]
+    fn reconnects_after_pool_hangs_up() {
+        within(TIMEOUT * 2, || {
+            let pool = MockPool::start(NONCE, Some(1)).unwrap();
+            let mut miner = miner(&pool);
+
+            miner.start();
+            let submissions = pool.wait_for(3, TIMEOUT);
+            miner.stop();
+
+            assert!(submissions.len() >= 3);
+            assert!(submissions.iter().all(|s| s.accepted));
+            assert!(pool.connections() >= 3);
+            assert!(miner.fleet().device(0).disconnects() >= 2);
+        });
+    }
+
+    #[test]
+    fn pooled_devices_disconnect_after_each_share() {
+        let pool = MockPool::start(NONCE, None).unwrap();
+        let mut miner = miner(&pool).with_workers(Some(1));
+
+        miner.start();
+        let submissions = pool.wait_for(2, TIMEOUT);
+        miner.stop();
+
+        assert!(submissions.len() >= 2);
+        assert!(pool.connections() >= 2);
+        assert!(miner.fleet().device(0).disconnects() >= 2);
+    }
+
+    /// Resident memory of this process, in KiB.
+    fn rss_kb() -> Option<u64> {
+        std::fs::read_to_string("/proc/self/status")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Measures the memory 1000 connected devices take, pool threads
+    /// included. Run alone, in release mode and on Linux:
+    /// `cargo test --release --features mockpool -- --ignored fleet_memory`.
+    #[test]
+    #[ignore]
+    fn fleet_memory() {
+        const DEVICES: usize = 1000;
+
+        let pool = MockPool::start(NONCE, None).unwrap();
+        let mut yaml = "devices:\n".to_string();
+        for i in 0..DEVICES {
+            yaml.push_str(&format!(
+                "  - {{username: alice, device_name: avr-{}, device_type: AVR, \
+                 chip_id: DUCOID0123456789, firmware: Official AVR Miner v2.6, \
+                 target_rate: {}}}\n",
+                i, TARGET_RATE
+            ));
+        }
+        let mut miner = Miner::new(Config::from_yaml(&yaml).unwrap(), Vec::new())
+            .with_pool(Some(pool.addr()))
+            .with_seed(Some(1))
+            .with_max_heatup(Duration::from_secs(1));
+
+        let before = rss_kb().unwrap();
+        miner.start();
+        assert!(pool.wait_for(DEVICES, Duration::from_secs(60)).len() >= DEVICES);
+        let used = rss_kb().unwrap().saturating_sub(before);
+        miner.stop();
+
+        println!("{} devices use {} MiB", DEVICES, used / 1024);
+        assert!(used < 100 * 1024, "{} KiB", used);
+    }
+}
@@ -0,0 +1,500 @@
+//! Device state published over MQTT, with Home Assistant discovery configs
+//! so each device shows up as sensors and a pause switch without any YAML.
+
+use duino_miner::control::{DeviceControl, FleetControl};
+use duino_miner::stats::{DeviceStats, FleetStats};
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde_json::json;
+
+/// Seconds the broker waits without hearing from us before dropping us.
+const KEEP_ALIVE_SECS: u16 = 60;
+
+/// How long to wait before reconnecting to a broker that went away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+
+/// Payloads of the mining switch, on unless the device is paused.
+const ON: &str = "ON";
+const OFF: &str = "OFF";
+
+pub struct MqttSink {
+    addr: String,
+    prefix: String,
+    discovery_prefix: String,
+    credentials: Option<(String, String)>,
+}
+
+/// Appends `len` in MQTT's variable length encoding.
+fn encode_len(buf: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    encode_len(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A CONNECT packet with a clean session, leaving `will` retained on
+/// `will_topic` if the connection drops.
+fn connect_packet(
+    client_id: &str,
+    will_topic: &str,
+    will: &str,
+    credentials: Option<&(String, String)>,
+) -> Vec<u8> {
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if credentials.is_some() {
+        flags |= 0x80 | 0x40;
+    }
+
+    let mut body = Vec::new();
+    encode_str(&mut body, "MQTT");
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+    encode_str(&mut body, client_id);
+    encode_str(&mut body, will_topic);
+    encode_str(&mut body, will);
+    if let Some((username, password)) = credentials {
+        encode_str(&mut body, username);
+        encode_str(&mut body, password);
+    }
+
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &str, retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_str(&mut body, topic);
+    body.extend_from_slice(payload.as_bytes());
+
+    packet(PUBLISH | retain as u8, &body)
+}
+
+fn subscribe_packet(packet_id: u16, filter: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    encode_str(&mut body, filter);
+    body.push(0);
+
+    packet(SUBSCRIBE, &body)
+}
+
+/// Reads one packet, returning its first header byte and its body.
+fn read_packet(stream: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+
+    let mut len = 0;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+
+    Ok((header, body))
+}
+
+/// Topic and payload of a PUBLISH packet's `body`, at QoS 0 as subscribed.
+fn parse_publish(body: &[u8]) -> Option<(&str, &str)> {
+    let len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + len)?).ok()?;
+    let payload = std::str::from_utf8(&body[2 + len..]).ok()?;
+
+    Some((topic, payload))
+}
+
+/// `name` reduced to characters allowed in topics and entity ids.
+fn object_id(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn state(control: &DeviceControl, stats: &DeviceStats) -> &'static str {
+    if control.is_paused() {
+        "paused"
+    } else if stats.is_connected() {
+        "mining"
+    } else {
+        "disconnected"
+    }
+}
+
+impl MqttSink {
+    pub fn new(
+        addr: String,
+        prefix: String,
+        discovery_prefix: String,
+        credentials: Option<(String, String)>,
+    ) -> Self {
+        Self {
+            addr,
+            prefix,
+            discovery_prefix,
+            credentials,
+        }
+    }
+
+    fn availability_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    fn state_topic(&self, id: &str) -> String {
+        format!("{}/{}/state", self.prefix, id)
+    }
+
+    fn command_topic(&self, id: &str) -> String {
+        format!("{}/{}/mining/set", self.prefix, id)
+    }
+
+    /// Discovery topics and configs of the sensors and the switch of the
+    /// device named `name`.
+    fn discovery(&self, name: &str, device_type: &str) -> Vec<(String, String)> {
+        let id = object_id(name);
+        let device = json!({
+            "identifiers": [format!("duino_miner_{}", id)],
+            "name": name,
+            "model": device_type,
+            "manufacturer": "duino-miner",
+        });
+        let sensors = [
+            (
+                "hashrate",
+                "Hashrate",
+                "{{ value_json.rate }}",
+                Some("H/s"),
+                "measurement",
+            ),
+            (
+                "accepted",
+                "Accepted shares",
+                "{{ value_json.accepted }}",
+                None,
+                "total_increasing",
+            ),
+            (
+                "rejected",
+                "Rejected shares",
+                "{{ value_json.rejected }}",
+                None,
+                "total_increasing",
+            ),
+        ];
+
+        let mut configs: Vec<(String, String)> = sensors
+            .iter()
+            .map(|(key, label, template, unit, state_class)| {
+                let mut config = json!({
+                    "name": label,
+                    "unique_id": format!("duino_miner_{}_{}", id, key),
+                    "state_topic": self.state_topic(&id),
+                    "value_template": template,
+                    "state_class": state_class,
+                    "availability_topic": self.availability_topic(),
+                    "device": device,
+                });
+                if let Some(unit) = unit {
+                    config["unit_of_measurement"] = json!(unit);
+                }
+
+                (
+                    format!(
+                        "{}/sensor/duino_miner_{}/{}/config",
+                        self.discovery_prefix, id, key
+                    ),
+                    config.to_string(),
+                )
+            })
+            .collect();
+        configs.push((
+            format!(
+                "{}/sensor/duino_miner_{}/state/config",
+                self.discovery_prefix, id
+            ),
+            json!({
+                "name": "State",
+                "unique_id": format!("duino_miner_{}_state", id),
+                "state_topic": self.state_topic(&id),
+                "value_template": "{{ value_json.state }}",
+                "availability_topic": self.availability_topic(),
+                "device": device,
+            })
+            .to_string(),
+        ));
+        let switch_template = format!(
+            "{{{{ '{}' if value_json.state != 'paused' else '{}' }}}}",
+            ON, OFF
+        );
+        configs.push((
+            format!(
+                "{}/switch/duino_miner_{}/mining/config",
+                self.discovery_prefix, id
+            ),
+            json!({
+                "name": "Mining",
+                "unique_id": format!("duino_miner_{}_mining", id),
+                "state_topic": self.state_topic(&id),
+                "value_template": switch_template,
+                "command_topic": self.command_topic(&id),
+                "payload_on": ON,
+                "payload_off": OFF,
+                "availability_topic": self.availability_topic(),
+                "device": device,
+            })
+            .to_string(),
+        ));
+
+        configs
+    }
+
+    fn publish_state(
+        &self,
+        stream: &Mutex<TcpStream>,
+        control: &DeviceControl,
+        stats: &DeviceStats,
+    ) -> std::io::Result<()> {
+        let payload = json!({
+            "state": state(control, stats),
+            "rate": (stats.reported_rate() * 100.0).round() / 100.0,
+            "accepted": stats.accepted(),
+            "rejected": stats.rejected(),
+        });
+
+        stream.lock().unwrap().write_all(&publish_packet(
+            &self.state_topic(&object_id(stats.name())),
+            &payload.to_string(),
+            false,
+        ))
+    }
+
+    /// Connects, announces every device to Home Assistant and subscribes to
+    /// the switches' command topics.
+    fn connect(&self, fleet: &FleetStats, control: &FleetControl) -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.addr)?;
+        stream.set_read_timeout(Some(RECONNECT_DELAY))?;
+
+        let client_id = format!("duino-miner-{}", std::process::id());
+        stream.write_all(&connect_packet(
+            &client_id,
+            &self.availability_topic(),
+            "offline",
+            self.credentials.as_ref(),
+        ))?;
+        match read_packet(&mut stream)? {
+            (CONNACK, body) if body.get(1) == Some(&0) => {}
+            (_, body) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    format!("broker refused connection with code {:?}", body.get(1)),
+                ))
+            }
+        }
+        // The broker may stay silent for much longer while no one touches
+        // a switch.
+        stream.set_read_timeout(None)?;
+
+        for device in control.devices() {
+            let device = device.device();
+            for (topic, config) in self.discovery(&device.name(), &device.device_type) {
+                stream.write_all(&publish_packet(&topic, &config, true))?;
+            }
+        }
+        stream.write_all(&publish_packet(&self.availability_topic(), "online", true))?;
+        stream.write_all(&subscribe_packet(
+            1,
+            &format!("{}/+/mining/set", self.prefix),
+        ))?;
+
+        info!(
+            "connected to MQTT broker {}, {} devices announced",
+            self.addr,
+            fleet.devices().len()
+        );
+
+        Ok(stream)
+    }
+
+    /// Handles the switches' commands until the connection drops.
+    fn serve_commands(
+        &self,
+        mut stream: TcpStream,
+        writer: &Mutex<TcpStream>,
+        fleet: &FleetStats,
+        control: &FleetControl,
+    ) {
+        loop {
+            let (header, body) = match read_packet(&mut stream) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    warn!("MQTT connection lost: {}", e);
+                    return;
+                }
+            };
+            if header & 0xf0 != PUBLISH {
+                continue;
+            }
+
+            let (topic, paused) = match parse_publish(&body) {
+                Some((topic, ON)) => (topic, false),
+                Some((topic, OFF)) => (topic, true),
+                _ => continue,
+            };
+            let index = fleet
+                .devices()
+                .iter()
+                .position(|d| self.command_topic(&object_id(d.name())) == topic);
+            if let Some(index) = index {
+                let device = control.device(index);
+                device.set_paused(paused);
+                info!(
+                    "{} {} via MQTT",
+                    device.device().name(),
+                    if paused { "paused" } else { "resumed" }
+                );
+
+                if let Err(e) = self.publish_state(writer, &device, &fleet.device(index)) {
+                    warn!("could not publish MQTT state: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Publishes the state of every device each `interval` in the
+    /// background, reconnecting whenever the broker goes away.
+    pub fn start(self, fleet: Arc<FleetStats>, control: Arc<FleetControl>, interval: Duration) {
+        let sink = Arc::new(self);
+
+        std::thread::spawn(move || loop {
+            let stream = match sink.connect(&fleet, &control) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("could not connect to MQTT broker {}: {}", sink.addr, e);
+                    std::thread::sleep(RECONNECT_DELAY);
+                    continue;
+                }
+            };
+            let writer = match stream.try_clone() {
+                Ok(writer) => Arc::new(Mutex::new(writer)),
+                Err(e) => {
+                    warn!("could not set up MQTT connection: {}", e);
+                    std::thread::sleep(RECONNECT_DELAY);
+                    continue;
+                }
+            };
+
+            let reader = {
+                let sink = sink.clone();
+                let writer = writer.clone();
+                let fleet = fleet.clone();
+                let control = control.clone();
+                std::thread::spawn(move || sink.serve_commands(stream, &writer, &fleet, &control))
+            };
+
+            'connected: loop {
+                for (device, stats) in control.devices().iter().zip(fleet.devices()) {
+                    if let Err(e) = sink.publish_state(&writer, device, stats) {
+                        warn!("could not publish MQTT state: {}", e);
+                        break 'connected;
+                    }
+                }
+
+                std::thread::sleep(interval);
+                if reader.is_finished() {
+                    break;
+                }
+            }
+
+            let _ = writer.lock().unwrap().shutdown(std::net::Shutdown::Both);
+            let _ = reader.join();
+            std::thread::sleep(RECONNECT_DELAY);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_remaining_length() {
+        let mut buf = Vec::new();
+        encode_len(&mut buf, 0);
+        encode_len(&mut buf, 127);
+        encode_len(&mut buf, 321);
+
+        assert_eq!(buf, vec![0x00, 0x7f, 0xc1, 0x02]);
+    }
+
+    #[test]
+    fn reads_back_published_message() {
+        let packet = publish_packet("duino_miner/avr-1/mining/set", "OFF", false);
+        let (header, body) = read_packet(&mut packet.as_slice()).unwrap();
+
+        assert_eq!(header, PUBLISH);
+        assert_eq!(
+            parse_publish(&body),
+            Some(("duino_miner/avr-1/mining/set", "OFF"))
+        );
+    }
+
+    #[test]
+    fn announces_sensors_and_switch() {
+        let sink = MqttSink::new(
+            "localhost:1883".to_string(),
+            "duino_miner".to_string(),
+            "homeassistant".to_string(),
+            None,
+        );
+
+        let configs = sink.discovery("rack a/avr 1", "AVR");
+        let topics: Vec<&str> = configs.iter().map(|(topic, _)| topic.as_str()).collect();
+
+        assert_eq!(topics.len(), 5);
+        assert!(topics.contains(&"homeassistant/switch/duino_miner_rack_a_avr_1/mining/config"));
+        let switch: serde_json::Value = serde_json::from_str(&configs[4].1).unwrap();
+        assert_eq!(
+            switch["command_topic"],
+            "duino_miner/rack_a_avr_1/mining/set"
+        );
+        assert_eq!(
+            switch["value_template"],
+            "{{ 'ON' if value_json.state != 'paused' else 'OFF' }}"
+        );
+    }
+}
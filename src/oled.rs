@@ -0,0 +1,227 @@
+//! A status screen on an SSD1306 OLED over Linux i2c-dev, so a rig can be
+//! checked on without logging in.
+
+use duino_miner::control::FleetControl;
+use duino_miner::stats::FleetStats;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+/// Request selecting the peripheral an i2c-dev file talks to.
+const I2C_SLAVE: u64 = 0x0703;
+
+const WIDTH: usize = 128;
+const PAGES: usize = 8;
+
+/// Columns a character takes, including the gap after it.
+const CHAR_WIDTH: usize = 6;
+
+/// How often the screen is redrawn.
+const REFRESH: Duration = Duration::from_secs(5);
+
+/// Sets up a 128x64 panel with its charge pump and horizontal addressing.
+const INIT: &[u8] = &[
+    0xae, 0xd5, 0x80, 0xa8, 0x3f, 0xd3, 0x00, 0x40, 0x8d, 0x14, 0x20, 0x00, 0xa1, 0xc8, 0xda, 0x12,
+    0x81, 0xcf, 0xd9, 0xf1, 0xdb, 0x40, 0xa4, 0xa6, 0xaf,
+];
+
+const DISPLAY_OFF: u8 = 0xae;
+const DISPLAY_ON: u8 = 0xaf;
+
+pub struct Display {
+    i2c: File,
+}
+
+impl Display {
+    /// Opens the panel at `address` on the i2c-dev `bus`, such as
+    /// `/dev/i2c-1`, and turns it on.
+    pub fn open(bus: &str, address: u16) -> std::io::Result<Self> {
+        let i2c = OpenOptions::new().read(true).write(true).open(bus)?;
+        if unsafe { libc::ioctl(i2c.as_raw_fd(), I2C_SLAVE as _, address as libc::c_ulong) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut display = Self { i2c };
+        display.command(INIT)?;
+
+        Ok(display)
+    }
+
+    fn command(&mut self, command: &[u8]) -> std::io::Result<()> {
+        let mut message = vec![0x00];
+        message.extend_from_slice(command);
+        self.i2c.write_all(&message)
+    }
+
+    fn draw(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        self.command(&[0x21, 0x00, (WIDTH - 1) as u8, 0x22, 0x00, (PAGES - 1) as u8])?;
+
+        let mut message = vec![0x40];
+        message.extend_from_slice(frame);
+        self.i2c.write_all(&message)
+    }
+}
+
+/// Columns of a 5x7 character, least significant bit at the top. Characters
+/// without a glyph are drawn blank.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '%' => [0x23, 0x13, 0x08, 0x64, 0x62],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '/' => [0x20, 0x10, 0x08, 0x04, 0x02],
+        '0' => [0x3e, 0x51, 0x49, 0x45, 0x3e],
+        '1' => [0x00, 0x42, 0x7f, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4b, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7f, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3c, 0x4a, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1e],
+        ':' => [0x00, 0x36, 0x36, 0x00, 0x00],
+        'A' => [0x7e, 0x11, 0x11, 0x11, 0x7e],
+        'B' => [0x7f, 0x49, 0x49, 0x49, 0x36],
+        'C' => [0x3e, 0x41, 0x41, 0x41, 0x22],
+        'D' => [0x7f, 0x41, 0x41, 0x22, 0x1c],
+        'E' => [0x7f, 0x49, 0x49, 0x49, 0x41],
+        'F' => [0x7f, 0x09, 0x09, 0x09, 0x01],
+        'G' => [0x3e, 0x41, 0x49, 0x49, 0x7a],
+        'H' => [0x7f, 0x08, 0x08, 0x08, 0x7f],
+        'I' => [0x00, 0x41, 0x7f, 0x41, 0x00],
+        'J' => [0x20, 0x40, 0x41, 0x3f, 0x01],
+        'K' => [0x7f, 0x08, 0x14, 0x22, 0x41],
+        'L' => [0x7f, 0x40, 0x40, 0x40, 0x40],
+        'M' => [0x7f, 0x02, 0x0c, 0x02, 0x7f],
+        'N' => [0x7f, 0x04, 0x08, 0x10, 0x7f],
+        'O' => [0x3e, 0x41, 0x41, 0x41, 0x3e],
+        'P' => [0x7f, 0x09, 0x09, 0x09, 0x06],
+        'Q' => [0x3e, 0x41, 0x51, 0x21, 0x5e],
+        'R' => [0x7f, 0x09, 0x19, 0x29, 0x46],
+        'S' => [0x46, 0x49, 0x49, 0x49, 0x31],
+        'T' => [0x01, 0x01, 0x7f, 0x01, 0x01],
+        'U' => [0x3f, 0x40, 0x40, 0x40, 0x3f],
+        'V' => [0x1f, 0x20, 0x40, 0x20, 0x1f],
+        'W' => [0x3f, 0x40, 0x38, 0x40, 0x3f],
+        'X' => [0x63, 0x14, 0x08, 0x14, 0x63],
+        'Y' => [0x07, 0x08, 0x70, 0x08, 0x07],
+        'Z' => [0x61, 0x51, 0x49, 0x45, 0x43],
+        _ => [0x00; 5],
+    }
+}
+
+/// Renders `lines` on every other page of the screen, cutting off what
+/// does not fit.
+fn render(lines: &[String]) -> Vec<u8> {
+    let mut frame = vec![0u8; WIDTH * PAGES];
+
+    for (row, line) in lines.iter().take(PAGES / 2).enumerate() {
+        let page = &mut frame[row * 2 * WIDTH..(row * 2 + 1) * WIDTH];
+        for (column, c) in line.chars().take(WIDTH / CHAR_WIDTH).enumerate() {
+            let start = column * CHAR_WIDTH;
+            page[start..start + 5].copy_from_slice(&glyph(c));
+        }
+    }
+
+    frame
+}
+
+fn format_rate(rate: f64) -> String {
+    if rate >= 1e6 {
+        format!("{:.2} MH/s", rate / 1e6)
+    } else if rate >= 1e3 {
+        format!("{:.2} kH/s", rate / 1e3)
+    } else {
+        format!("{:.0} H/s", rate)
+    }
+}
+
+/// The status screen: devices up, aggregate rate and shares per minute
+/// since the previous redraw.
+fn status_lines(up: usize, devices: usize, rate: f64, shares_per_min: f64) -> Vec<String> {
+    vec![
+        "duino-miner".to_string(),
+        format!("up {}/{}", up, devices),
+        format!("rate {}", format_rate(rate)),
+        format!("shares {:.1}/min", shares_per_min),
+    ]
+}
+
+/// Redraws `display` from the fleet every few seconds in the background.
+/// The screen is turned off while every device is paused.
+pub fn start(mut display: Display, fleet: Arc<FleetStats>, control: Arc<FleetControl>) {
+    std::thread::spawn(move || {
+        let mut last = (0u64, Instant::now());
+
+        loop {
+            if control.is_idle() {
+                if let Err(e) = display.command(&[DISPLAY_OFF]) {
+                    warn!("could not turn off the OLED: {}", e);
+                }
+                control.wait_until_active();
+                if let Err(e) = display.command(&[DISPLAY_ON]) {
+                    warn!("could not turn on the OLED: {}", e);
+                }
+            }
+
+            let devices = fleet.devices();
+            let connected = devices.iter().filter(|d| d.is_connected());
+            let up = connected.clone().count();
+            let rate = connected.map(|d| d.reported_rate()).sum();
+
+            // Fewer shares than before means the stats were reset.
+            let shares: u64 = devices.iter().map(|d| d.accepted() + d.rejected()).sum();
+            let minutes = last.1.elapsed().as_secs_f64() / 60f64;
+            let shares_per_min = if minutes > 0f64 {
+                shares.saturating_sub(last.0) as f64 / minutes
+            } else {
+                0f64
+            };
+            last = (shares, Instant::now());
+
+            let frame = render(&status_lines(up, devices.len(), rate, shares_per_min));
+            if let Err(e) = display.draw(&frame) {
+                warn!("could not draw to the OLED: {}", e);
+            }
+
+            std::thread::sleep(REFRESH);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_lines_on_alternate_pages() {
+        let frame = render(&["1".to_string(), " A".to_string()]);
+
+        assert_eq!(frame[..5], glyph('1'));
+        assert_eq!(
+            frame[2 * WIDTH + CHAR_WIDTH..2 * WIDTH + CHAR_WIDTH + 5],
+            glyph('A')
+        );
+        assert!(frame[WIDTH..2 * WIDTH].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn cuts_off_long_lines() {
+        let frame = render(&["8".repeat(40)]);
+
+        assert_eq!(frame[..WIDTH].iter().filter(|&&b| b != 0).count(), 21 * 5);
+    }
+
+    #[test]
+    fn formats_rates_with_units() {
+        assert_eq!(format_rate(190.0), "190 H/s");
+        assert_eq!(format_rate(3050.0), "3.05 kH/s");
+        assert_eq!(format_rate(2_500_000.0), "2.50 MH/s");
+    }
+}
@@ -0,0 +1,52 @@
+//! Default locations of the config file and of files the miner writes, so
+//! runs do not depend on the working directory.
+
+use directories::ProjectDirs;
+
+use std::path::{Path, PathBuf};
+
+/// Name of the config file, in the working directory or the config dir.
+const CONFIG_FILE: &str = "config.yaml";
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "duino-miner")
+}
+
+/// The config file to use: `explicit` if given, else `config.yaml` in the
+/// working directory if there is one, else `config.yaml` in the user's
+/// config dir, such as `$XDG_CONFIG_HOME/duino-miner` on Linux.
+pub fn config_file(explicit: Option<String>) -> String {
+    if let Some(path) = explicit {
+        return path;
+    }
+    if Path::new(CONFIG_FILE).exists() {
+        return CONFIG_FILE.to_string();
+    }
+
+    match project_dirs() {
+        Some(dirs) => dirs
+            .config_dir()
+            .join(CONFIG_FILE)
+            .to_string_lossy()
+            .into_owned(),
+        None => CONFIG_FILE.to_string(),
+    }
+}
+
+/// Where a state file such as the share log goes: relative paths are taken
+/// from the user's data dir, such as `$XDG_DATA_HOME/duino-miner` on Linux,
+/// which is created if needed.
+pub fn data_file(path: &str) -> std::io::Result<PathBuf> {
+    let path = Path::new(path);
+    let dirs = match project_dirs() {
+        Some(dirs) if path.is_relative() => dirs,
+        _ => return Ok(path.to_path_buf()),
+    };
+
+    let path = dirs.data_dir().join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    Ok(path)
+}
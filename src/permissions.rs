@@ -0,0 +1,87 @@
+//! Checks that files holding credentials or mining history are private.
+
+use std::path::Path;
+
+use log::warn;
+
+/// Warns about each of `paths` that other users can read or that another
+/// user owns. With `strict`, such files are an error instead. Missing files
+/// are skipped.
+pub fn check<P: AsRef<Path>>(paths: &[P], strict: bool) -> Result<(), String> {
+    let problems: Vec<String> = paths
+        .iter()
+        .flat_map(|path| problems_of(path.as_ref()))
+        .collect();
+
+    if strict && !problems.is_empty() {
+        return Err(format!(
+            "refusing to start (--strict-permissions): {}",
+            problems.join("; ")
+        ));
+    }
+    for problem in problems {
+        warn!("{}", problem);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn problems_of(path: &Path) -> Vec<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut problems = Vec::new();
+    if metadata.mode() & 0o004 != 0 {
+        problems.push(format!(
+            "{} is readable by all users; restrict it with `chmod o-r {}`",
+            path.display(),
+            path.display()
+        ));
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        problems.push(format!(
+            "{} is owned by uid {}, not by the miner's uid {}",
+            path.display(),
+            metadata.uid(),
+            uid
+        ));
+    }
+
+    problems
+}
+
+#[cfg(not(unix))]
+fn problems_of(_path: &Path) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn flags_world_readable_files() {
+        let path = std::env::temp_dir().join(format!("permissions-{}.yaml", std::process::id()));
+        std::fs::write(&path, "devices: []\n").unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let open = problems_of(&path);
+        let strict = check(&[&path], true);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let private = problems_of(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(open.len(), 1);
+        assert!(strict.is_err());
+        assert!(private.is_empty());
+    }
+}
@@ -0,0 +1,412 @@
+//! Probing of pools for the one with the lowest latency, so devices follow
+//! the best pool rather than one picked by hand.
+
+use crate::api::Client;
+use crate::config::PoolSelectionConfig;
+use crate::control::FleetControl;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+
+/// Longest a pool may take to connect or send its version.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shortest time between two failures counted against a pool, so a whole
+/// fleet dropped by one restart of the pool counts once.
+const STRIKE_INTERVAL: u64 = 60;
+
+/// Time `pool` takes to accept a connection and send its version line.
+pub fn probe(pool: &str) -> Result<Duration, String> {
+    let started = Instant::now();
+    let addr = pool
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("no address")?;
+    let stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(PROBE_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    let mut version = String::new();
+    match BufReader::new(&stream).read_line(&mut version) {
+        Ok(n) if n > 0 && version.trim().parse::<f64>().is_ok() => Ok(started.elapsed()),
+        Ok(n) if n > 0 => Err(format!("sent {:?} for a version", version.trim())),
+        Ok(_) => Err("closed without a version".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Default)]
+struct BlocklistState {
+    /// Failures of each pool since it last behaved, and when the last one
+    /// was counted.
+    strikes: BTreeMap<String, (u32, u64)>,
+    /// When the ban of each banned pool ends.
+    banned: BTreeMap<String, u64>,
+    /// Where the bans are saved.
+    file: Option<PathBuf>,
+}
+
+impl BlocklistState {
+    fn save(&self) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let saved = serde_json::to_string_pretty(&self.banned)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(file, json));
+        if let Err(e) = saved {
+            warn!(
+                "could not save the pool banlist to {}: {}",
+                file.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Pools that kept failing, through resets or garbage where the version
+/// should be, kept out of selection until their ban expires. Times are in
+/// Unix seconds.
+pub struct Blocklist {
+    ban_after: u32,
+    ban_secs: u64,
+    state: Mutex<BlocklistState>,
+}
+
+impl Blocklist {
+    /// Bans a pool for `ban_secs` once it failed `ban_after` times without
+    /// behaving in between. Nothing is banned if `ban_after` is 0.
+    pub fn new(ban_after: u32, ban_secs: u64) -> Self {
+        Self {
+            ban_after,
+            ban_secs,
+            state: Mutex::new(BlocklistState::default()),
+        }
+    }
+
+    /// Loads the bans saved in `path`, and saves them there from now on, so
+    /// a restart does not forget which pools are bad.
+    pub fn load(&self, path: PathBuf) {
+        let banned = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("ignoring the pool banlist {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!("could not read the pool banlist {}: {}", path.display(), e);
+                BTreeMap::new()
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.banned = banned;
+        state.file = Some(path);
+    }
+
+    /// Counts a failure of `pool` at `now`, unless one was counted within
+    /// [`STRIKE_INTERVAL`]. Returns whether that got the pool banned.
+    pub fn strike(&self, pool: &str, now: u64) -> bool {
+        if self.ban_after == 0 {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let (strikes, last) = state.strikes.entry(pool.to_string()).or_default();
+        if *strikes > 0 && now < *last + STRIKE_INTERVAL {
+            return false;
+        }
+        *strikes += 1;
+        *last = now;
+        if *strikes < self.ban_after {
+            return false;
+        }
+
+        state.strikes.remove(pool);
+        state.banned.insert(pool.to_string(), now + self.ban_secs);
+        state.save();
+        warn!(
+            "pool {} banned for {} s after failing {} times",
+            pool, self.ban_secs, self.ban_after
+        );
+
+        true
+    }
+
+    /// Forgets the failures of `pool`, which just behaved.
+    pub fn forgive(&self, pool: &str) {
+        self.state.lock().unwrap().strikes.remove(pool);
+    }
+
+    pub fn is_banned(&self, pool: &str, now: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.banned.get(pool) {
+            Some(&until) if until > now => true,
+            Some(_) => {
+                state.banned.remove(pool);
+                state.save();
+                info!("ban of pool {} expired", pool);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keeps track of the lowest-latency healthy pool among those probed.
+pub struct PoolSelector {
+    config: PoolSelectionConfig,
+    best: RwLock<Option<String>>,
+    blocklist: Blocklist,
+}
+
+impl PoolSelector {
+    pub fn new(config: PoolSelectionConfig) -> Self {
+        Self {
+            blocklist: Blocklist::new(config.ban_after, config.ban_secs),
+            config,
+            best: RwLock::new(None),
+        }
+    }
+
+    /// Pools left out of selection for failing too often.
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    /// The pool new connections should go to, once a probe found one that
+    /// has not been banned since.
+    pub fn best(&self) -> Option<String> {
+        self.best
+            .read()
+            .unwrap()
+            .clone()
+            .filter(|pool| !self.blocklist.is_banned(pool, unix_now()))
+    }
+
+    /// Moves to the fastest healthy pool of a probe round, unless the
+    /// current pool is still healthy and within the switch margin of it.
+    /// Returns whether the best pool changed.
+    pub fn choose(&self, latencies: &[(String, Result<Duration, String>)]) -> bool {
+        let fastest = latencies
+            .iter()
+            .filter_map(|(pool, latency)| latency.as_ref().ok().map(|l| (pool, *l)))
+            .min_by_key(|(_, latency)| *latency);
+
+        let mut best = self.best.write().unwrap();
+        let current = best.as_ref().and_then(|current| {
+            latencies
+                .iter()
+                .find(|(pool, _)| pool == current)
+                .and_then(|(pool, latency)| latency.as_ref().ok().map(|l| (pool, *l)))
+        });
+
+        let chosen = match (current, fastest) {
+            (Some((current, latency)), Some((_, fastest)))
+                if fastest.as_secs_f64()
+                    >= latency.as_secs_f64() * (1.0 - self.config.switch_margin) =>
+            {
+                Some(current.clone())
+            }
+            (_, fastest) => fastest.map(|(pool, _)| pool.clone()),
+        };
+
+        let changed = *best != chosen;
+        *best = chosen;
+        changed
+    }
+
+    /// Pools from the config, and those the API lists if `discover` is set,
+    /// unless banned.
+    fn candidates(&self) -> Vec<String> {
+        let mut pools: BTreeSet<String> = self.config.pools.iter().cloned().collect();
+        if self.config.discover {
+            match Client::shared().get_pools() {
+                Ok(listed) => pools.extend(listed.iter().map(|pool| pool.address())),
+                Err(e) => warn!("could not list pools: {}", e),
+            }
+            if let Ok(pool) = Client::shared().get_pool() {
+                pools.insert(pool.address());
+            }
+        }
+
+        let now = unix_now();
+        pools
+            .into_iter()
+            .filter(|pool| !self.blocklist.is_banned(pool, now))
+            .collect()
+    }
+
+    /// Probes every candidate at once and chooses among them.
+    pub fn probe_all(&self) {
+        let candidates = self.candidates();
+        let latencies: Vec<(String, Result<Duration, String>)> = std::thread::scope(|scope| {
+            let probes: Vec<_> = candidates
+                .iter()
+                .map(|pool| scope.spawn(move || (pool.clone(), probe(pool))))
+                .collect();
+            probes.into_iter().filter_map(|p| p.join().ok()).collect()
+        });
+
+        for (pool, latency) in latencies.iter() {
+            match latency {
+                Ok(latency) => {
+                    info!("pool {} answered in {} ms", pool, latency.as_millis());
+                    self.blocklist.forgive(pool);
+                }
+                Err(e) => {
+                    info!("pool {} unhealthy: {}", pool, e);
+                    self.blocklist.strike(pool, unix_now());
+                }
+            }
+        }
+
+        if self.choose(&latencies) {
+            match self.best() {
+                Some(pool) => info!("new connections go to pool {}", pool),
+                None => warn!("no healthy pool found, asking the balancer instead"),
+            }
+        }
+    }
+
+    /// Probes now, then every `interval_secs` in the background while any
+    /// device of `control` is mining.
+    pub fn start(self: Arc<Self>, control: Arc<FleetControl>) {
+        self.probe_all();
+
+        let interval = Duration::from_secs(self.config.interval_secs.max(1));
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            control.wait_until_active();
+            self.probe_all();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    fn round(latencies: &[(&str, Option<u64>)]) -> Vec<(String, Result<Duration, String>)> {
+        latencies
+            .iter()
+            .map(|(pool, ms)| {
+                (
+                    pool.to_string(),
+                    ms.map(Duration::from_millis)
+                        .ok_or_else(|| "down".to_string()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn switches_only_past_the_margin() {
+        let selector = PoolSelector::new(PoolSelectionConfig::default());
+
+        assert!(selector.choose(&round(&[("a:1", Some(50)), ("b:1", Some(40))])));
+        assert_eq!(selector.best().unwrap(), "b:1");
+
+        assert!(!selector.choose(&round(&[("a:1", Some(35)), ("b:1", Some(40))])));
+        assert_eq!(selector.best().unwrap(), "b:1");
+
+        assert!(selector.choose(&round(&[("a:1", Some(20)), ("b:1", Some(40))])));
+        assert_eq!(selector.best().unwrap(), "a:1");
+    }
+
+    #[test]
+    fn leaves_unhealthy_pools() {
+        let selector = PoolSelector::new(PoolSelectionConfig::default());
+        selector.choose(&round(&[("a:1", Some(20)), ("b:1", Some(40))]));
+
+        assert!(selector.choose(&round(&[("a:1", None), ("b:1", Some(40))])));
+        assert_eq!(selector.best().unwrap(), "b:1");
+
+        assert!(selector.choose(&round(&[("a:1", None), ("b:1", None)])));
+        assert_eq!(selector.best(), None);
+    }
+
+    #[test]
+    fn probes_version_banner() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"2.7\n").unwrap();
+        });
+
+        assert!(probe(&addr).is_ok());
+    }
+
+    #[test]
+    fn rejects_garbage_banner() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"<html>\n").unwrap();
+        });
+
+        assert!(probe(&addr).is_err());
+    }
+
+    #[test]
+    fn bans_pool_after_repeated_failures() {
+        let blocklist = Blocklist::new(3, 3600);
+
+        assert!(!blocklist.strike("a:1", 1000));
+        // A failure right after another counts once.
+        assert!(!blocklist.strike("a:1", 1001));
+        assert!(!blocklist.strike("a:1", 1060));
+        assert!(blocklist.strike("a:1", 1120));
+
+        assert!(blocklist.is_banned("a:1", 1120));
+        assert!(!blocklist.is_banned("b:1", 1120));
+        assert!(!blocklist.is_banned("a:1", 1120 + 3600));
+    }
+
+    #[test]
+    fn forgives_pool_that_behaved() {
+        let blocklist = Blocklist::new(2, 3600);
+
+        blocklist.strike("a:1", 1000);
+        blocklist.forgive("a:1");
+
+        assert!(!blocklist.strike("a:1", 1100));
+        assert!(!blocklist.is_banned("a:1", 1100));
+    }
+
+    #[test]
+    fn keeps_bans_across_restarts() {
+        let path = std::env::temp_dir().join(format!("banlist-{}.json", std::process::id()));
+        let blocklist = Blocklist::new(1, 3600);
+        blocklist.load(path.clone());
+        blocklist.strike("a:1", 1000);
+
+        let restarted = Blocklist::new(1, 3600);
+        restarted.load(path.clone());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(restarted.is_banned("a:1", 2000));
+    }
+}
@@ -0,0 +1,661 @@
+//! Lines exchanged with the pool.
+
+use crate::error::MinerError;
+
+use hex::FromHex;
+
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read};
+
+/// A job sent by the pool: `<last block hash>,<expected hash>,<difficulty>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub last_block_hash: String,
+    pub expected_hash: String,
+    pub difficulty: u64,
+}
+
+/// `line` without what pool implementations differ in around it:
+/// surrounding whitespace and trailing commas.
+pub fn normalize(line: &str) -> &str {
+    line.trim().trim_end_matches(',').trim_end()
+}
+
+/// The fields of `line`, normalized, each without surrounding whitespace.
+pub fn fields(line: &str) -> impl Iterator<Item = &str> {
+    normalize(line).split(',').map(str::trim)
+}
+
+fn is_block_hash(s: &str) -> bool {
+    <[u8; 20]>::from_hex(s).is_ok()
+}
+
+impl Job {
+    /// Parses a job line, normalized by [`fields`]. Hashes are taken in
+    /// either case and kept in lowercase. Fields after the difficulty are
+    /// ignored.
+    pub fn parse(line: &str) -> Result<Self, MinerError> {
+        let malformed = || MinerError::MalformedJob(normalize(line).to_string());
+
+        let mut args = fields(line);
+        let last_block_hash = args
+            .next()
+            .filter(|s| is_block_hash(s))
+            .ok_or_else(malformed)?;
+        let expected_hash = args
+            .next()
+            .filter(|s| is_block_hash(s))
+            .ok_or_else(malformed)?;
+        let difficulty = args
+            .next()
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(malformed)?;
+
+        let job = Self {
+            last_block_hash: last_block_hash.to_ascii_lowercase(),
+            expected_hash: expected_hash.to_ascii_lowercase(),
+            difficulty,
+        };
+        job.max_nonce()?;
+
+        Ok(job)
+    }
+
+    /// Exclusive upper bound of the nonce search for this job.
+    pub fn max_nonce(&self) -> Result<u64, MinerError> {
+        self.difficulty
+            .checked_mul(100)
+            .and_then(|n| n.checked_add(1))
+            .ok_or(MinerError::DifficultyOutOfRange(self.difficulty))
+    }
+}
+
+/// Bytes read from the pool at a time.
+const READ_CHUNK_LEN: usize = 256;
+
+/// Splits what the pool sends into messages. A message ends at a newline,
+/// or when the pool closes the connection or stops sending until the read
+/// times out. Bytes read past the end of a message are kept for the next,
+/// as when a share's response and the next job arrive together.
+pub struct MessageReader {
+    max_len: usize,
+    /// Bytes read but not yet returned.
+    pending: Vec<u8>,
+    message: Vec<u8>,
+}
+
+impl MessageReader {
+    /// A reader failing on messages longer than `max_len`.
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            pending: Vec::new(),
+            message: Vec::new(),
+        }
+    }
+
+    /// The next message, read from `stream` unless one is already queued.
+    pub fn read<R: Read>(&mut self, stream: &mut R) -> Result<&str, MinerError> {
+        self.read_message(stream, false)
+    }
+
+    /// The pool's greeting, which some pools send without a newline, so it
+    /// also ends when a read comes up short.
+    pub fn read_greeting<R: Read>(&mut self, stream: &mut R) -> Result<&str, MinerError> {
+        self.read_message(stream, true)
+    }
+
+    fn read_message<R: Read>(
+        &mut self,
+        stream: &mut R,
+        unterminated: bool,
+    ) -> Result<&str, MinerError> {
+        let mut chunk = [0u8; READ_CHUNK_LEN];
+        let mut drained = false;
+
+        loop {
+            if let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+                self.message = self.pending.drain(..=end).collect();
+                break;
+            }
+            if unterminated && drained && !self.pending.is_empty() {
+                self.message = std::mem::take(&mut self.pending);
+                break;
+            }
+            if self.pending.len() > self.max_len {
+                self.pending.clear();
+                return Err(MinerError::MessageTooLong(self.max_len));
+            }
+
+            match stream.read(&mut chunk) {
+                Ok(0) if self.pending.is_empty() => return Err(MinerError::RecvCommand),
+                Ok(0) => {
+                    self.message = std::mem::take(&mut self.pending);
+                    break;
+                }
+                Ok(n) => {
+                    self.pending.extend_from_slice(&chunk[..n]);
+                    // A segment boundary may fall anywhere in a message, so
+                    // a short read only ends the greeting.
+                    drained = n < chunk.len();
+                }
+                Err(e)
+                    if !self.pending.is_empty()
+                        && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    self.message = std::mem::take(&mut self.pending);
+                    break;
+                }
+                Err(_) => return Err(MinerError::RecvCommand),
+            }
+        }
+
+        if self.message.len() > self.max_len {
+            return Err(MinerError::MessageTooLong(self.max_len));
+        }
+
+        std::str::from_utf8(&self.message).map_err(|_| MinerError::InvalidUTF8)
+    }
+}
+
+/// The pool's answer to a share.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShareResponse<'a> {
+    Good,
+    Block,
+    /// Anything else, usually `BAD` with a reason, normalized.
+    Rejected(&'a str),
+}
+
+impl<'a> ShareResponse<'a> {
+    /// Parses a response by its first field, normalized by [`fields`],
+    /// whatever its case. Some pools follow the verdict with details.
+    pub fn parse(line: &'a str) -> Self {
+        let verdict = fields(line).next().unwrap_or_default();
+        if verdict.eq_ignore_ascii_case("GOOD") {
+            ShareResponse::Good
+        } else if verdict.eq_ignore_ascii_case("BLOCK") {
+            ShareResponse::Block
+        } else {
+            ShareResponse::Rejected(normalize(line))
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        !matches!(self, ShareResponse::Rejected(_))
+    }
+}
+
+/// Fills each `{field}` in `template` with `value(field)`. Placeholders
+/// without a value are kept as they are.
+fn fill_template(template: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut line = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        let after = &rest[open + 1..];
+        let close = match after.find('}') {
+            Some(close) => close,
+            None => break,
+        };
+        line.push_str(&rest[..open]);
+
+        match value(&after[..close]) {
+            Some(v) => line.push_str(&v),
+            None => line.push_str(&rest[open..open + close + 2]),
+        }
+        rest = &after[close + 1..];
+    }
+
+    line.push_str(rest);
+    line.push('\n');
+    line
+}
+
+/// Checks that every `{field}` in `template` is one of `fields` or a key of
+/// `extra`.
+pub fn check_template(
+    template: &str,
+    fields: &[&str],
+    extra: &BTreeMap<String, String>,
+) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let after = &rest[open + 1..];
+        let close = after
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in `{}`", template))?;
+        let field = &after[..close];
+        if !fields.contains(&field) && !extra.contains_key(field) {
+            return Err(format!(
+                "unknown placeholder {{{}}} in `{}`",
+                field, template
+            ));
+        }
+        rest = &after[close + 1..];
+    }
+
+    Ok(())
+}
+
+/// Default job request template, matching the official miners.
+pub const DEFAULT_JOB_REQUEST: &str = "JOB,{username},{device_type}";
+
+pub const JOB_REQUEST_FIELDS: &[&str] = &["username", "device_type", "mining_key"];
+
+/// A request for a new job, sent as `JOB,<username>,<device type>` unless a
+/// job request template says otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobRequest<'a> {
+    pub username: &'a str,
+    pub device_type: &'a str,
+    pub mining_key: Option<&'a str>,
+}
+
+impl JobRequest<'_> {
+    pub fn to_line(&self) -> String {
+        format!("JOB,{},{}\n", self.username, self.device_type)
+    }
+
+    /// Formats the request from `template`, filling each `{field}` from the
+    /// request or, failing that, from `extra`.
+    pub fn to_line_with(&self, template: &str, extra: &BTreeMap<String, String>) -> String {
+        fill_template(template, |field| match field {
+            "username" => Some(self.username.to_string()),
+            "device_type" => Some(self.device_type.to_string()),
+            "mining_key" => Some(self.mining_key.unwrap_or_default().to_string()),
+            _ => extra.get(field).cloned(),
+        })
+    }
+}
+
+/// Default submission template, matching the official AVR miner.
+pub const DEFAULT_SUBMISSION: &str = "{nonce},{rate},{firmware},{device_name},{chip_id}";
+
+pub const SHARE_FIELDS: &[&str] = &[
+    "nonce",
+    "rate",
+    "firmware",
+    "device_name",
+    "chip_id",
+    "username",
+    "wallet_id",
+];
+
+/// A solved share, submitted as
+/// `<nonce>,<rate>,<firmware>,<device name>,<chip id>` unless a submission
+/// template says otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share<'a> {
+    pub nonce: u64,
+    pub rate: f64,
+    pub firmware: &'a str,
+    pub device_name: &'a str,
+    pub chip_id: &'a str,
+    pub username: &'a str,
+    pub wallet_id: Option<&'a str>,
+}
+
+impl Share<'_> {
+    pub fn to_line(&self) -> String {
+        format!(
+            "{},{:.2},{},{},{}\n",
+            self.nonce, self.rate, self.firmware, self.device_name, self.chip_id
+        )
+    }
+
+    /// Formats the share line from `template`, filling each `{field}` from
+    /// the share or, failing that, from `extra`.
+    pub fn to_line_with(&self, template: &str, extra: &BTreeMap<String, String>) -> String {
+        fill_template(template, |field| match field {
+            "nonce" => Some(self.nonce.to_string()),
+            "rate" => Some(format!("{:.2}", self.rate)),
+            "firmware" => Some(self.firmware.to_string()),
+            "device_name" => Some(self.device_name.to_string()),
+            "chip_id" => Some(self.chip_id.to_string()),
+            "username" => Some(self.username.to_string()),
+            "wallet_id" => Some(self.wallet_id.unwrap_or_default().to_string()),
+            _ => extra.get(field).cloned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    const LAST: &str = "e7d3c4a6bd5e5d1c5d2e0b1c9f3f7b7a4c1e2f3a";
+    const EXPECTED: &str = "0a1b2c3d4e5f60718293a4b5c6d7e8f901234567";
+
+    #[test]
+    fn reads_message_longer_than_chunk() {
+        let message = format!("{}\n", "a".repeat(READ_CHUNK_LEN * 3));
+        let mut reader = MessageReader::new(4096);
+
+        let read = reader.read(&mut message.as_bytes()).unwrap();
+
+        assert_eq!(read, message);
+    }
+
+    #[test]
+    fn rejects_oversize_message() {
+        let message = "a".repeat(READ_CHUNK_LEN * 3);
+        let mut reader = MessageReader::new(READ_CHUNK_LEN);
+
+        match reader.read(&mut message.as_bytes()) {
+            Err(MinerError::MessageTooLong(len)) => assert_eq!(len, READ_CHUNK_LEN),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn closed_connection_is_an_error() {
+        let mut reader = MessageReader::new(4096);
+
+        assert!(reader.read(&mut &b""[..]).is_err());
+    }
+
+    #[test]
+    fn queues_messages_sent_together() {
+        let mut stream = &b"GOOD\nlast,expected,6\n"[..];
+        let mut reader = MessageReader::new(4096);
+
+        assert_eq!(reader.read(&mut stream).unwrap(), "GOOD\n");
+        assert_eq!(reader.read(&mut stream).unwrap(), "last,expected,6\n");
+        assert!(reader.read(&mut stream).is_err());
+    }
+
+    #[test]
+    fn ends_unterminated_message_when_pool_stops_sending() {
+        let mut stream = &b"GOOD\n3.0"[..];
+        let mut reader = MessageReader::new(4096);
+
+        assert_eq!(reader.read(&mut stream).unwrap(), "GOOD\n");
+        assert_eq!(reader.read(&mut stream).unwrap(), "3.0");
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_split_job() {
+        let job = format!("{},{},6\n", LAST, EXPECTED);
+        let (first, second) = job.split_at(20);
+        let sent = format!("GOOD\n{}", first);
+        let mut stream = sent.as_bytes().chain(second.as_bytes());
+        let mut reader = MessageReader::new(4096);
+
+        assert_eq!(reader.read(&mut stream).unwrap(), "GOOD\n");
+        assert_eq!(reader.read(&mut stream).unwrap(), job);
+    }
+
+    #[test]
+    fn ends_greeting_without_newline() {
+        let job = format!("{},{},6\n", LAST, EXPECTED);
+        let mut stream = "2.7".as_bytes().chain(job.as_bytes());
+        let mut reader = MessageReader::new(4096);
+
+        assert_eq!(reader.read_greeting(&mut stream).unwrap(), "2.7");
+        assert_eq!(reader.read(&mut stream).unwrap(), job);
+    }
+
+    #[test]
+    fn parses_valid_job() {
+        let job = Job::parse(&format!("{},{},6\n", LAST, EXPECTED)).unwrap();
+
+        assert_eq!(job.last_block_hash, LAST);
+        assert_eq!(job.expected_hash, EXPECTED);
+        assert_eq!(job.difficulty, 6);
+        assert_eq!(job.max_nonce().unwrap(), 601);
+    }
+
+    #[test]
+    fn normalizes_job_fields() {
+        let line = format!(
+            "  {} , {}\t,6,\r\n",
+            LAST.to_uppercase(),
+            EXPECTED.to_uppercase()
+        );
+
+        let job = Job::parse(&line).unwrap();
+
+        assert_eq!(job.last_block_hash, LAST);
+        assert_eq!(job.expected_hash, EXPECTED);
+        assert_eq!(job.difficulty, 6);
+    }
+
+    #[test]
+    fn ignores_extra_fields() {
+        let job = Job::parse(&format!("{},{},6,extra,fields", LAST, EXPECTED)).unwrap();
+
+        assert_eq!(job.difficulty, 6);
+    }
+
+    #[test]
+    fn rejects_truncated_jobs() {
+        assert!(Job::parse("").is_err());
+        assert!(Job::parse(LAST).is_err());
+        assert!(Job::parse(&format!("{},", LAST)).is_err());
+        assert!(Job::parse(&format!("{},{}", LAST, EXPECTED)).is_err());
+        assert!(Job::parse(&format!("{},{},", LAST, EXPECTED)).is_err());
+        assert!(Job::parse(&format!("{},{}", LAST, &EXPECTED[..20])).is_err());
+    }
+
+    #[test]
+    fn rejects_non_hex_hashes() {
+        let non_hex = "zzd3c4a6bd5e5d1c5d2e0b1c9f3f7b7a4c1e2f3a";
+
+        assert!(Job::parse(&format!("{},{},6", non_hex, EXPECTED)).is_err());
+        assert!(Job::parse(&format!("{},{},6", LAST, non_hex)).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_difficulty() {
+        assert!(Job::parse(&format!("{},{},abc", LAST, EXPECTED)).is_err());
+        assert!(Job::parse(&format!("{},{},-1", LAST, EXPECTED)).is_err());
+        assert!(Job::parse(&format!("{},{},99999999999999999999", LAST, EXPECTED)).is_err());
+    }
+
+    #[test]
+    fn error_contains_job() {
+        match Job::parse("BAD\n") {
+            Err(MinerError::MalformedJob(job)) => assert_eq!(job, "BAD"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_difficulty_beyond_u32() {
+        let job = Job::parse(&format!("{},{},50000000", LAST, EXPECTED)).unwrap();
+
+        assert_eq!(job.max_nonce().unwrap(), 5_000_000_001);
+    }
+
+    #[test]
+    fn rejects_out_of_range_difficulty() {
+        let difficulty = u64::MAX / 100 + 1;
+
+        match Job::parse(&format!("{},{},{}", LAST, EXPECTED, difficulty)) {
+            Err(MinerError::DifficultyOutOfRange(d)) => assert_eq!(d, difficulty),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn default_submission_template_matches_share_line() {
+        let share = Share {
+            nonce: 4242,
+            rate: 171.456,
+            firmware: "Official AVR Miner v2.6",
+            device_name: "avr-1",
+            chip_id: "DUCOID01234567",
+            username: "my_username",
+            wallet_id: None,
+        };
+
+        assert_eq!(
+            share.to_line_with(DEFAULT_SUBMISSION, &BTreeMap::new()),
+            share.to_line()
+        );
+    }
+
+    #[test]
+    fn formats_share_line_from_template() {
+        let share = Share {
+            nonce: 4242,
+            rate: 171.456,
+            firmware: "Official PC Miner 2.7",
+            device_name: "pc-1",
+            chip_id: "None",
+            username: "my_username",
+            wallet_id: Some("1234"),
+        };
+
+        assert_eq!(
+            share.to_line_with(
+                "{nonce},{rate},{firmware};{device_name},{wallet_id},{other}",
+                &BTreeMap::new()
+            ),
+            "4242,171.46,Official PC Miner 2.7;pc-1,1234,{other}\n"
+        );
+        assert_eq!(
+            share.to_line_with("{nonce},{rate", &BTreeMap::new()),
+            "4242,{rate\n"
+        );
+    }
+
+    #[test]
+    fn default_job_request_template_matches_job_line() {
+        let request = JobRequest {
+            username: "my_username",
+            device_type: "AVR",
+            mining_key: None,
+        };
+
+        assert_eq!(
+            request.to_line_with(DEFAULT_JOB_REQUEST, &BTreeMap::new()),
+            "JOB,my_username,AVR\n"
+        );
+        assert_eq!(request.to_line(), "JOB,my_username,AVR\n");
+    }
+
+    #[test]
+    fn formats_job_request_with_extra_fields() {
+        let request = JobRequest {
+            username: "my_username",
+            device_type: "LOW",
+            mining_key: Some("secret"),
+        };
+        let mut extra = BTreeMap::new();
+        extra.insert("client".to_string(), "pc-2.7".to_string());
+
+        assert_eq!(
+            request.to_line_with("JOB,{username},{device_type},{mining_key},{client}", &extra),
+            "JOB,my_username,LOW,secret,pc-2.7\n"
+        );
+    }
+
+    #[test]
+    fn checks_template_placeholders() {
+        let mut extra = BTreeMap::new();
+        extra.insert("client".to_string(), "pc-2.7".to_string());
+
+        assert!(check_template(DEFAULT_SUBMISSION, SHARE_FIELDS, &extra).is_ok());
+        assert!(check_template("{nonce}|{username}|{wallet_id}", SHARE_FIELDS, &extra).is_ok());
+        assert!(check_template("{nonce},{client}", SHARE_FIELDS, &extra).is_ok());
+        assert!(check_template("{nonce},{hashrate}", SHARE_FIELDS, &extra).is_err());
+        assert!(check_template("{nonce},{rate", SHARE_FIELDS, &extra).is_err());
+        assert!(check_template(DEFAULT_JOB_REQUEST, JOB_REQUEST_FIELDS, &extra).is_ok());
+    }
+
+    #[test]
+    fn formats_share_line() {
+        let share = Share {
+            nonce: 4242,
+            rate: 171.456,
+            firmware: "Official AVR Miner v2.6",
+            device_name: "avr-1",
+            chip_id: "DUCOID01234567",
+            username: "my_username",
+            wallet_id: None,
+        };
+
+        assert_eq!(
+            share.to_line(),
+            "4242,171.46,Official AVR Miner v2.6,avr-1,DUCOID01234567\n"
+        );
+    }
+
+    #[test]
+    fn parses_share_responses() {
+        assert_eq!(ShareResponse::parse("GOOD\n"), ShareResponse::Good);
+        assert_eq!(ShareResponse::parse("BLOCK"), ShareResponse::Block);
+        assert_eq!(
+            ShareResponse::parse("BAD,Incorrect result\n"),
+            ShareResponse::Rejected("BAD,Incorrect result")
+        );
+        assert!(!ShareResponse::parse("").is_accepted());
+    }
+
+    #[test]
+    fn normalizes_share_responses() {
+        assert_eq!(ShareResponse::parse(" good,\r\n"), ShareResponse::Good);
+        assert_eq!(ShareResponse::parse("Block ,"), ShareResponse::Block);
+        assert_eq!(
+            ShareResponse::parse("BAD,Incorrect result,\n"),
+            ShareResponse::Rejected("BAD,Incorrect result")
+        );
+        assert_eq!(ShareResponse::parse("GOOD,0.12"), ShareResponse::Good);
+    }
+
+    proptest! {
+        #[test]
+        fn job_parse_never_panics(line in "\\PC*") {
+            let _ = Job::parse(&line);
+        }
+
+        #[test]
+        fn job_parse_accepts_well_formed_jobs(
+            last in "[0-9a-f]{40}",
+            expected in "[0-9a-f]{40}",
+            difficulty in 0u64..u64::MAX / 100,
+        ) {
+            let job = Job::parse(&format!("{},{},{}\n", last, expected, difficulty)).unwrap();
+
+            prop_assert_eq!(job.last_block_hash, last);
+            prop_assert_eq!(job.expected_hash, expected);
+            prop_assert_eq!(job.difficulty, difficulty);
+        }
+
+        #[test]
+        fn job_parse_rejects_huge_difficulty(difficulty in u64::MAX / 100 + 1..) {
+            let line = format!("{},{},{}", LAST, EXPECTED, difficulty);
+
+            prop_assert!(Job::parse(&line).is_err());
+        }
+
+        #[test]
+        fn share_response_never_panics(line in "\\PC*") {
+            let _ = ShareResponse::parse(&line);
+        }
+
+        #[test]
+        fn read_message_consumes_input(
+            bytes in proptest::collection::vec(any::<u8>(), 0..2048),
+            max_len in 1usize..1024,
+        ) {
+            let mut stream = &bytes[..];
+            let mut reader = MessageReader::new(max_len);
+
+            // Every read but the last takes at least one byte off the stream
+            // or the queue, whether it succeeds or not, so a stream of n
+            // bytes runs dry within n + 1 reads.
+            for _ in 0..=bytes.len() {
+                if let Err(MinerError::RecvCommand) = reader.read(&mut stream) {
+                    break;
+                }
+            }
+            prop_assert!(stream.is_empty());
+            prop_assert!(reader.read(&mut stream).is_err());
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Python bindings, built as the `duino_miner` extension module.
+
+// pyo3's #[pymethods] expansion trips this lint on every PyResult return.
+#![allow(clippy::useless_conversion)]
+
+use crate::config::Config;
+use crate::events::EventKind;
+use crate::miner::Miner;
+
+use pyo3::exceptions::{PyKeyError, PyRuntimeError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+fn runtime_error(e: Box<dyn std::error::Error>) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// The name an event kind has in JSON, such as `block_found`.
+fn kind_name(kind: EventKind) -> Option<String> {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_owned))
+}
+
+#[pyclass(name = "Config")]
+#[derive(Clone)]
+pub struct PyConfig {
+    inner: Config,
+}
+
+#[pymethods]
+impl PyConfig {
+    #[staticmethod]
+    fn load(file_path: &str) -> PyResult<Self> {
+        let inner = Config::load(file_path).map_err(runtime_error)?;
+        Ok(Self { inner })
+    }
+
+    #[staticmethod]
+    fn from_yaml(c_serial: &str) -> PyResult<Self> {
+        let inner = Config::from_yaml(c_serial).map_err(runtime_error)?;
+        Ok(Self { inner })
+    }
+
+    fn to_yaml(&self) -> PyResult<String> {
+        serde_yaml::to_string(&self.inner).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn device_names(&self) -> Vec<String> {
+        self.inner
+            .devices
+            .iter()
+            .map(|d| d.device_name.clone())
+            .collect()
+    }
+}
+
+#[pyclass(name = "Miner")]
+pub struct PyMiner {
+    inner: Miner,
+}
+
+#[pymethods]
+impl PyMiner {
+    #[new]
+    #[pyo3(signature = (config, pool = None))]
+    fn new(config: &PyConfig, pool: Option<String>) -> Self {
+        let inner = Miner::new(config.inner.clone(), Vec::new()).with_pool(pool);
+        Self { inner }
+    }
+
+    fn start(&mut self) {
+        self.inner.start();
+    }
+
+    /// Stops all devices, blocking until their threads exit.
+    fn stop(&mut self, py: Python) {
+        py.allow_threads(|| self.inner.stop());
+    }
+
+    fn pause(&self, device_name: &str) -> PyResult<()> {
+        self.device(device_name)?.set_paused(true);
+        Ok(())
+    }
+
+    fn resume(&self, device_name: &str) -> PyResult<()> {
+        self.device(device_name)?.set_paused(false);
+        Ok(())
+    }
+
+    fn set_target_rate(&self, device_name: &str, target_rate: u32) -> PyResult<()> {
+        self.device(device_name)?.set_target_rate(target_rate);
+        Ok(())
+    }
+
+    /// Per-device counters as a list of dicts.
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.inner
+            .fleet()
+            .devices()
+            .iter()
+            .zip(self.inner.control().devices().iter())
+            .map(|(stats, control)| {
+                let d = PyDict::new_bound(py);
+                d.set_item("name", stats.name())?;
+                d.set_item("username", control.device().username)?;
+                d.set_item("group", control.device().group)?;
+                d.set_item("connected", stats.is_connected())?;
+                d.set_item("paused", control.is_paused())?;
+                d.set_item("target_rate", control.target_rate())?;
+                d.set_item("reported_rate", stats.reported_rate())?;
+                d.set_item("accepted", stats.accepted())?;
+                d.set_item("rejected", stats.rejected())?;
+                d.set_item("blocks", stats.blocks())?;
+                d.set_item("bytes_sent", stats.bytes_sent())?;
+                d.set_item("bytes_received", stats.bytes_received())?;
+                d.set_item("panics", stats.panics())?;
+                d.set_item("connected_secs", stats.connected_time().as_secs_f64())?;
+                d.set_item("disconnects", stats.disconnects())?;
+                d.set_item(
+                    "mtbf_secs",
+                    stats
+                        .mean_time_between_failures()
+                        .map(|mtbf| mtbf.as_secs_f64()),
+                )?;
+                Ok(d)
+            })
+            .collect()
+    }
+
+    /// Events newer than `since`, oldest first. Pass the last seen `id` to
+    /// poll the event stream.
+    #[pyo3(signature = (since = 0))]
+    fn events<'py>(&self, py: Python<'py>, since: u64) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.inner
+            .fleet()
+            .events()
+            .since(since)
+            .into_iter()
+            .map(|e| {
+                let d = PyDict::new_bound(py);
+                d.set_item("id", e.id)?;
+                d.set_item("timestamp", e.timestamp)?;
+                d.set_item("device", e.device)?;
+                d.set_item("kind", kind_name(e.kind))?;
+                d.set_item("message", e.message)?;
+                Ok(d)
+            })
+            .collect()
+    }
+}
+
+impl PyMiner {
+    fn device(
+        &self,
+        device_name: &str,
+    ) -> PyResult<&std::sync::Arc<crate::control::DeviceControl>> {
+        self.inner
+            .control()
+            .find(device_name)
+            .ok_or_else(|| PyKeyError::new_err(device_name.to_string()))
+    }
+}
+
+#[pymodule]
+fn duino_miner(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyConfig>()?;
+    m.add_class::<PyMiner>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_kinds_are_plain_names() {
+        assert_eq!(
+            kind_name(EventKind::BlockFound).as_deref(),
+            Some("block_found")
+        );
+        assert_eq!(kind_name(EventKind::Rejected).as_deref(), Some("rejected"));
+    }
+}
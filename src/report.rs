@@ -0,0 +1,338 @@
+//! A report of the past day made at a set local time, configured in the
+//! `report` section of the config file. It is logged, and optionally
+//! archived as JSON and sent as a `daily_report` event.
+
+use duino_miner::api::PriceOracle;
+use duino_miner::config::ReportConfig;
+use duino_miner::control::FleetControl;
+use duino_miner::events::EventKind;
+use duino_miner::stats::{DeviceStats, FleetStats};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
+use log::{info, warn};
+use serde::Serialize;
+
+/// How often devices are checked for downtime and the report for being due.
+const TICK: Duration = Duration::from_secs(10);
+
+/// Share counters of a device.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Counters {
+    accepted: u64,
+    rejected: u64,
+    blocks: u64,
+}
+
+impl Counters {
+    fn read(device: &DeviceStats) -> Self {
+        Self {
+            accepted: device.accepted(),
+            rejected: device.rejected(),
+            blocks: device.blocks(),
+        }
+    }
+
+    /// Counts since `last`. Fewer than before means the stats were reset, so
+    /// everything counted since then is new.
+    fn since(self, last: Counters) -> Self {
+        if self.accepted < last.accepted
+            || self.rejected < last.rejected
+            || self.blocks < last.blocks
+        {
+            return self;
+        }
+
+        Self {
+            accepted: self.accepted - last.accepted,
+            rejected: self.rejected - last.rejected,
+            blocks: self.blocks - last.blocks,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct AccountReport {
+    accepted: u64,
+    rejected: u64,
+    /// Accepted shares, in percent of those submitted.
+    acceptance: f64,
+    blocks: u64,
+    /// Minutes devices of the account were down while not paused, summed.
+    downtime_minutes: u64,
+    /// DUCO earned per day, estimated from the account balance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_duco: Option<f64>,
+    /// The estimated DUCO at the price of the shared price oracle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    date: String,
+    accounts: BTreeMap<String, AccountReport>,
+}
+
+impl Report {
+    /// Sums what each of `devices`, given as its account, counters and
+    /// downtime since the last report, did for its account. Earnings are
+    /// valued at `price`, in USD, when known.
+    fn new(
+        date: NaiveDate,
+        devices: &[(String, Counters, Duration)],
+        estimated_duco: impl Fn(&str) -> Option<f64>,
+        price: Option<f64>,
+    ) -> Self {
+        let mut accounts: BTreeMap<String, AccountReport> = BTreeMap::new();
+        let mut downtime: BTreeMap<&str, Duration> = BTreeMap::new();
+        for (username, counters, down) in devices.iter() {
+            let account = accounts.entry(username.clone()).or_default();
+            account.accepted += counters.accepted;
+            account.rejected += counters.rejected;
+            account.blocks += counters.blocks;
+            *downtime.entry(username.as_str()).or_default() += *down;
+        }
+
+        for (username, account) in accounts.iter_mut() {
+            let shares = account.accepted + account.rejected;
+            if shares > 0 {
+                account.acceptance = account.accepted as f64 / shares as f64 * 100f64;
+            }
+            account.downtime_minutes = downtime[username.as_str()].as_secs() / 60;
+            account.estimated_duco = estimated_duco(username);
+            account.estimated_usd = account
+                .estimated_duco
+                .zip(price)
+                .map(|(duco, price)| duco * price);
+        }
+
+        Self {
+            date: date.to_string(),
+            accounts,
+        }
+    }
+
+    fn summary(&self) -> String {
+        self.accounts
+            .iter()
+            .map(|(username, account)| {
+                let earned = match account.estimated_duco {
+                    Some(duco) => format!(", earned: ~{:.2} DUCO", duco),
+                    None => String::new(),
+                };
+                format!(
+                    "{}: accepted: {} ({:.1}%), rejected: {}, blocks: {}, downtime: {} min{}",
+                    username,
+                    account.accepted,
+                    account.acceptance,
+                    account.rejected,
+                    account.blocks,
+                    account.downtime_minutes,
+                    earned
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn write(&self, dir: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let path = Path::new(dir).join(format!("{}.json", self.date));
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// The date the last report counts as made on at `now`, so the first one
+/// comes at the next `at` rather than right away.
+fn last_report(now: NaiveDateTime, at: NaiveTime) -> NaiveDate {
+    if now.time() >= at {
+        now.date()
+    } else {
+        now.date().pred_opt().unwrap_or_else(|| now.date())
+    }
+}
+
+fn is_due(now: NaiveDateTime, at: NaiveTime, last: NaiveDate) -> bool {
+    now.date() > last && now.time() >= at
+}
+
+pub struct Reporter {
+    config: ReportConfig,
+    at: NaiveTime,
+}
+
+impl Reporter {
+    /// Checks the time of day of `config`, so a typo shows at startup.
+    pub fn new(config: ReportConfig) -> Result<Self, String> {
+        let at = NaiveTime::parse_from_str(&config.at, "%H:%M")
+            .map_err(|e| format!("report: at {}: {}", config.at, e))?;
+
+        Ok(Self { config, at })
+    }
+
+    fn publish(&self, fleet: &FleetStats, report: &Report) {
+        let summary = report.summary();
+        info!("daily report {}", summary);
+
+        if let Some(dir) = &self.config.dir {
+            if let Err(e) = report.write(dir) {
+                warn!("could not write the daily report to {}: {}", dir, e);
+            }
+        }
+        if self.config.notify {
+            fleet
+                .events()
+                .push("fleet", EventKind::DailyReport, summary);
+        }
+    }
+
+    /// Makes a report every day at the configured time in the background,
+    /// counting downtime while any device of `control` is mining.
+    pub fn start(self, fleet: Arc<FleetStats>, control: Arc<FleetControl>) {
+        std::thread::spawn(move || {
+            let mut counters: Vec<Counters> =
+                fleet.devices().iter().map(|d| Counters::read(d)).collect();
+            let mut downtime = vec![Duration::ZERO; counters.len()];
+            let mut last = last_report(Local::now().naive_local(), self.at);
+
+            loop {
+                std::thread::sleep(TICK);
+                control.wait_until_active();
+
+                for ((stats, device), down) in fleet
+                    .devices()
+                    .iter()
+                    .zip(control.devices())
+                    .zip(downtime.iter_mut())
+                {
+                    if !stats.is_connected() && !device.is_paused() {
+                        *down += TICK;
+                    }
+                }
+
+                let now = Local::now().naive_local();
+                if !is_due(now, self.at, last) {
+                    continue;
+                }
+                last = now.date();
+
+                let devices: Vec<_> = fleet
+                    .devices()
+                    .iter()
+                    .zip(control.devices())
+                    .zip(counters.iter_mut().zip(downtime.iter_mut()))
+                    .map(|((stats, device), (previous, down))| {
+                        let current = Counters::read(stats);
+                        let since = current.since(*previous);
+                        *previous = current;
+                        (device.device().username, since, std::mem::take(down))
+                    })
+                    .collect();
+                let report = Report::new(
+                    now.date(),
+                    &devices,
+                    |username| fleet.earnings().per_day(username),
+                    PriceOracle::shared().cached(),
+                );
+                self.publish(&fleet, &report);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(accepted: u64, rejected: u64, blocks: u64) -> Counters {
+        Counters {
+            accepted,
+            rejected,
+            blocks,
+        }
+    }
+
+    #[test]
+    fn counts_since_last_report() {
+        assert_eq!(
+            counters(10, 2, 1).since(counters(4, 1, 1)),
+            counters(6, 1, 0)
+        );
+        assert_eq!(
+            counters(3, 0, 0).since(counters(4, 1, 1)),
+            counters(3, 0, 0)
+        );
+    }
+
+    #[test]
+    fn sums_devices_per_account() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let devices = [
+            (
+                "alice".to_string(),
+                counters(90, 5, 1),
+                Duration::from_secs(600),
+            ),
+            (
+                "alice".to_string(),
+                counters(9, 1, 0),
+                Duration::from_secs(300),
+            ),
+            (
+                "bob".to_string(),
+                counters(0, 0, 0),
+                Duration::from_secs(59),
+            ),
+        ];
+
+        let report = Report::new(
+            date,
+            &devices,
+            |username| (username == "alice").then_some(2.5),
+            Some(0.5),
+        );
+
+        assert_eq!(report.date, "2024-05-01");
+        assert_eq!(
+            report.accounts["alice"],
+            AccountReport {
+                accepted: 99,
+                rejected: 6,
+                acceptance: 99f64 / 105f64 * 100f64,
+                blocks: 1,
+                downtime_minutes: 15,
+                estimated_duco: Some(2.5),
+                estimated_usd: Some(1.25),
+            }
+        );
+        assert_eq!(report.accounts["bob"].acceptance, 0f64);
+        assert_eq!(report.accounts["bob"].downtime_minutes, 0);
+    }
+
+    #[test]
+    fn reports_once_a_day_at_the_set_time() {
+        let at = NaiveTime::from_hms_opt(6, 30, 0).unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let time = |hour, min| day.and_hms_opt(hour, min, 0).unwrap();
+
+        let last = last_report(time(5, 0), at);
+        assert!(!is_due(time(6, 0), at, last));
+        assert!(is_due(time(6, 30), at, last));
+        assert!(!is_due(time(7, 0), at, day));
+
+        let last = last_report(time(7, 0), at);
+        assert!(!is_due(time(23, 59), at, last));
+    }
+
+    #[test]
+    fn rejects_bad_time_of_day() {
+        let config: ReportConfig = serde_yaml::from_str("at: \"25:00\"\n").unwrap();
+
+        assert!(Reporter::new(config).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::{GiveUp, RestartPolicy};
+use crate::error::Severity;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// A device's recent restarts, for applying its [`RestartPolicy`].
+#[derive(Default)]
+pub struct Restarts {
+    /// Errors since the device last mined a share.
+    failures: u32,
+    /// Restarts within the last hour.
+    recent: VecDeque<Instant>,
+}
+
+impl Restarts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes that the device mined a share, resetting the backoff.
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Records a restart after an error of `severity` at `now`. Returns how
+    /// long to wait before reconnecting, or `None` when the device should
+    /// give up.
+    pub fn next(
+        &mut self,
+        policy: &RestartPolicy,
+        severity: Severity,
+        now: Instant,
+    ) -> Option<Duration> {
+        self.failures = self.failures.saturating_add(1);
+        self.recent.push_back(now);
+        while let Some(restart) = self.recent.front() {
+            if now.saturating_duration_since(*restart) < HOUR {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        let max_backoff = seconds(policy.max_backoff_secs);
+        if let Some(max_per_hour) = policy.max_per_hour {
+            if self.recent.len() > max_per_hour as usize {
+                return match policy.give_up {
+                    GiveUp::Disable => {
+                        // Start afresh once the device is resumed.
+                        self.recent.clear();
+                        None
+                    }
+                    GiveUp::KeepTrying => Some(max_backoff),
+                };
+            }
+        }
+
+        if severity == Severity::Retry {
+            return Some(Duration::from_secs(0));
+        }
+
+        let backoff = policy.backoff_secs
+            * policy
+                .backoff_factor
+                .powi((self.failures - 1).min(1024) as i32);
+
+        Some(seconds(backoff).min(max_backoff))
+    }
+}
+
+/// `secs` as a duration, saturating on values too large to represent.
+fn seconds(secs: f64) -> Duration {
+    if secs.is_nan() || secs <= 0.0 {
+        return Duration::from_secs(0);
+    }
+
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::error::MinerError;
+
+    fn policy(max_per_hour: Option<u32>, give_up: GiveUp) -> RestartPolicy {
+        RestartPolicy {
+            max_per_hour,
+            give_up,
+            ..RestartPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let policy = policy(None, GiveUp::Disable);
+        let mut restarts = Restarts::new();
+        let now = Instant::now();
+
+        let delays: Vec<_> = (0..3)
+            .map(|_| restarts.next(&policy, Severity::Backoff, now))
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(5)),
+                Some(Duration::from_secs(10)),
+                Some(Duration::from_secs(20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn caps_backoff() {
+        let policy = policy(None, GiveUp::Disable);
+        let mut restarts = Restarts::new();
+        let now = Instant::now();
+
+        for _ in 0..2000 {
+            restarts.next(&policy, Severity::Backoff, now);
+        }
+
+        assert_eq!(
+            restarts.next(&policy, Severity::Backoff, now),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn success_resets_backoff() {
+        let policy = policy(None, GiveUp::Disable);
+        let mut restarts = Restarts::new();
+        let now = Instant::now();
+
+        restarts.next(&policy, Severity::Backoff, now);
+        restarts.succeeded();
+
+        assert_eq!(
+            restarts.next(&policy, Severity::Backoff, now),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn retries_garbled_messages_at_once() {
+        let policy = policy(None, GiveUp::Disable);
+
+        assert_eq!(
+            Restarts::new().next(&policy, Severity::Retry, Instant::now()),
+            Some(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_per_hour() {
+        let policy = policy(Some(2), GiveUp::Disable);
+        let mut restarts = Restarts::new();
+        let now = Instant::now();
+
+        assert!(restarts.next(&policy, Severity::Retry, now).is_some());
+        assert!(restarts.next(&policy, Severity::Retry, now).is_some());
+        assert_eq!(restarts.next(&policy, Severity::Retry, now), None);
+    }
+
+    #[test]
+    fn oversized_messages_count_against_max_per_hour() {
+        let policy = policy(Some(2), GiveUp::Disable);
+        let mut restarts = Restarts::new();
+        let now = Instant::now();
+        let severity = MinerError::MessageTooLong(1024).severity();
+
+        assert_eq!(
+            restarts.next(&policy, severity, now),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            restarts.next(&policy, severity, now),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(restarts.next(&policy, severity, now), None);
+    }
+
+    #[test]
+    fn keeps_trying_slowly_after_max_per_hour() {
+        let policy = policy(Some(0), GiveUp::KeepTrying);
+
+        assert_eq!(
+            Restarts::new().next(&policy, Severity::Retry, Instant::now()),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn forgets_restarts_after_an_hour() {
+        let policy = policy(Some(1), GiveUp::Disable);
+        let mut restarts = Restarts::new();
+        let now = Instant::now();
+
+        restarts.next(&policy, Severity::Retry, now);
+
+        assert!(restarts
+            .next(&policy, Severity::Retry, now + HOUR)
+            .is_some());
+    }
+}
@@ -0,0 +1,67 @@
+//! Checks the open file limit against the connections a run will hold.
+
+/// Descriptors kept for logs, the share log and the HTTP and gRPC servers.
+const RESERVED_FDS: usize = 64;
+
+/// Descriptors needed to hold `connections` sockets at once.
+pub fn fds_needed(connections: usize) -> u64 {
+    (connections + RESERVED_FDS) as u64
+}
+
+/// The soft and hard open file limits.
+#[cfg(unix)]
+pub fn open_file_limit() -> Result<(u64, u64), String> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(format!(
+            "could not read open file limit: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    // rlim_t is not u64 on every platform.
+    #[allow(clippy::unnecessary_cast)]
+    Ok((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+#[cfg(not(unix))]
+pub fn open_file_limit() -> Result<(u64, u64), String> {
+    Err("open file limits are not supported on this platform".to_string())
+}
+
+/// Makes sure `connections` sockets can be open at once, raising the soft
+/// limit up to the hard limit when needed.
+#[cfg(unix)]
+pub fn ensure_fd_limit(connections: usize) -> Result<(), String> {
+    let needed = fds_needed(connections);
+
+    let (current, hard) = open_file_limit()?;
+    if current >= needed {
+        return Ok(());
+    }
+
+    if hard >= needed {
+        let limit = libc::rlimit {
+            rlim_cur: needed as libc::rlim_t,
+            rlim_max: hard as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } == 0 {
+            log::info!("raised open file limit from {} to {}", current, needed);
+            return Ok(());
+        }
+    }
+
+    Err(format!(
+        "open file limit is {} (hard {}) but {} connections need {}; \
+         raise it with `ulimit -n` or run with --workers",
+        current, hard, connections, needed
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn ensure_fd_limit(_connections: usize) -> Result<(), String> {
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest a worker waits before checking whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Devices waiting for their next job fetch, ordered by when it is due.
+#[derive(Default)]
+pub struct Schedule {
+    queue: Mutex<BinaryHeap<Reverse<(Instant, usize)>>>,
+    ready: Condvar,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `device` to be handed to a worker at `due`.
+    pub fn push(&self, due: Instant, device: usize) {
+        self.queue.lock().unwrap().push(Reverse((due, device)));
+        self.ready.notify_one();
+    }
+
+    /// Blocks until a device is due and returns it, or returns `None` once
+    /// `stop` returns true.
+    pub fn next(&self, stop: impl Fn() -> bool) -> Option<usize> {
+        let mut queue = self.queue.lock().unwrap();
+
+        loop {
+            if stop() {
+                return None;
+            }
+
+            let now = Instant::now();
+            let wait = match queue.peek() {
+                Some(Reverse((due, _))) if *due <= now => {
+                    return queue.pop().map(|Reverse((_, device))| device);
+                }
+                Some(Reverse((due, _))) => (*due - now).min(POLL_INTERVAL),
+                None => POLL_INTERVAL,
+            };
+
+            queue = self.ready.wait_timeout(queue, wait).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_out_devices_in_due_order() {
+        let schedule = Schedule::new();
+        let now = Instant::now();
+        schedule.push(now, 2);
+        schedule.push(now - Duration::from_millis(20), 0);
+        schedule.push(now - Duration::from_millis(10), 1);
+
+        let order: Vec<_> = (0..3).map(|_| schedule.next(|| false)).collect();
+
+        assert_eq!(order, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn waits_for_due_time() {
+        let schedule = Schedule::new();
+        let start = Instant::now();
+        schedule.push(start + Duration::from_millis(50), 7);
+
+        assert_eq!(schedule.next(|| false), Some(7));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn stops_without_handing_out_devices() {
+        let schedule = Schedule::new();
+        schedule.push(Instant::now(), 0);
+
+        assert_eq!(schedule.next(|| true), None);
+        assert_eq!(schedule.next(|| false), Some(0));
+    }
+}
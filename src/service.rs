@@ -0,0 +1,201 @@
+use crate::{run_miners, Opts, Service, ServiceAction, SubCommands};
+
+use duino_miner::control::FleetControl;
+
+use std::ffi::OsString;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use log::error;
+
+use clap::Clap;
+
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "duino-miner";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+pub fn install(config_file: String) -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    let config_file = std::fs::canonicalize(&config_file)
+        .map(OsString::from)
+        .unwrap_or_else(|_| OsString::from(config_file));
+
+    let service_info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Duino Miner"),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().map_err(windows_service::Error::Winapi)?,
+        launch_arguments: vec![
+            OsString::from("--config-file"),
+            config_file,
+            OsString::from("--log-target"),
+            OsString::from("eventlog"),
+            OsString::from("service"),
+            OsString::from("run"),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+
+    let service = manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Multiplexed Duinocoin miner")?;
+
+    println!("installed service {}", SERVICE_NAME);
+
+    Ok(())
+}
+
+pub fn uninstall() -> windows_service::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+
+    println!("uninstalled service {}", SERVICE_NAME);
+
+    Ok(())
+}
+
+pub fn run() -> windows_service::Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("service failed: {}", e);
+    }
+}
+
+fn set_state(
+    status_handle: &service_control_handler::ServiceStatusHandle,
+    state: ServiceState,
+    exit_code: ServiceExitCode,
+) -> windows_service::Result<()> {
+    let controls_accepted = if state == ServiceState::Running {
+        ServiceControlAccept::STOP
+    } else {
+        ServiceControlAccept::empty()
+    };
+
+    status_handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    })
+}
+
+/// What the service waits on: the service manager or the miners.
+enum Event {
+    Started(Arc<FleetControl>),
+    Exited(Result<(), String>),
+    Stop,
+}
+
+fn run_service() -> windows_service::Result<()> {
+    let (events_tx, events) = mpsc::channel();
+
+    let stop_tx = events_tx.clone();
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        match control_event {
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            ServiceControl::Stop => {
+                let _ = stop_tx.send(Event::Stop);
+                ServiceControlHandlerResult::NoError
+            }
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)?;
+    set_state(
+        &status_handle,
+        ServiceState::StartPending,
+        ServiceExitCode::Win32(0),
+    )?;
+
+    let opts: Opts = Opts::parse();
+    let run = match opts.sub_command {
+        SubCommands::Service(Service {
+            action: ServiceAction::Run(run),
+        }) => run,
+        _ => unreachable!("the service is only started with `service run`"),
+    };
+    let config_files = match opts.config_file {
+        files if files.is_empty() => vec![crate::paths::config_file(None)],
+        files => files,
+    };
+    let strict_permissions = opts.strict_permissions;
+
+    std::thread::spawn(move || {
+        let started_tx = events_tx.clone();
+        let result = run_miners(config_files, run, strict_permissions, move |control| {
+            let _ = started_tx.send(Event::Started(control));
+        })
+        .map_err(|e| e.to_string());
+        let _ = events_tx.send(Event::Exited(result));
+    });
+
+    let mut control = None;
+    let mut stopping = false;
+    let result = loop {
+        match events.recv() {
+            Ok(Event::Started(started)) if stopping => started.shutdown(),
+            Ok(Event::Started(started)) => {
+                set_state(
+                    &status_handle,
+                    ServiceState::Running,
+                    ServiceExitCode::Win32(0),
+                )?;
+                control = Some(started);
+            }
+            Ok(Event::Exited(result)) => break result,
+            Ok(Event::Stop) => {
+                stopping = true;
+                set_state(
+                    &status_handle,
+                    ServiceState::StopPending,
+                    ServiceExitCode::Win32(0),
+                )?;
+                if let Some(control) = &control {
+                    control.shutdown();
+                }
+            }
+            Err(_) => break Ok(()),
+        }
+    };
+
+    let exit_code = match result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(e) => {
+            error!("miners exited with error: {}", e);
+            ServiceExitCode::ServiceSpecific(1)
+        }
+    };
+    set_state(&status_handle, ServiceState::Stopped, exit_code)
+}
@@ -0,0 +1,84 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "timestamp,device,nonce,duration_us,response,reported_rate\n";
+
+pub struct ShareRecord<'a> {
+    pub device: &'a str,
+    pub nonce: u64,
+    pub duration_us: u128,
+    pub response: &'a str,
+    pub reported_rate: f64,
+}
+
+pub struct ShareLog {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Mutex<(File, u64)>,
+}
+
+fn open_log(path: &PathBuf) -> std::io::Result<(File, u64)> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut size = f.metadata()?.len();
+
+    if size == 0 {
+        f.write_all(HEADER.as_bytes())?;
+        size = HEADER.len() as u64;
+    }
+
+    Ok((f, size))
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl ShareLog {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = open_log(&path)?;
+
+        Ok(Self {
+            path,
+            max_bytes,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, share: &ShareRecord) -> std::io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let line = format!(
+            "{},{},{},{},{},{:.2}\n",
+            timestamp,
+            escape(share.device),
+            share.nonce,
+            share.duration_us,
+            escape(share.response),
+            share.reported_rate
+        );
+
+        let mut file = self.file.lock().unwrap();
+
+        if self.max_bytes > 0 && file.1 + line.len() as u64 > self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, rotated)?;
+            *file = open_log(&self.path)?;
+        }
+
+        file.0.write_all(line.as_bytes())?;
+        file.1 += line.len() as u64;
+
+        Ok(())
+    }
+}
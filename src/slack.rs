@@ -0,0 +1,186 @@
+//! Notifications posted to a Slack incoming webhook, configured in the
+//! `slack` section of the config file.
+
+use duino_miner::config::SlackConfig;
+use duino_miner::control::FleetControl;
+use duino_miner::events::EventKind;
+use duino_miner::protocol::check_template;
+use duino_miner::stats::FleetStats;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde_json::json;
+
+/// How often devices and events are checked.
+const TICK: Duration = Duration::from_secs(10);
+
+const DAY: Duration = Duration::from_secs(86400);
+
+/// Fills each `{field}` of `template` from `fields`.
+fn fill(template: &str, fields: &[(&str, String)]) -> String {
+    fields
+        .iter()
+        .fold(template.to_string(), |text, (field, value)| {
+            text.replace(&format!("{{{}}}", field), value)
+        })
+}
+
+/// Whether a device has been down long enough to notify, tracked per device.
+#[derive(Default)]
+struct Down {
+    since: Option<Instant>,
+    notified: bool,
+}
+
+impl Down {
+    /// Records whether the device is `down` at `now`, returning how long it
+    /// has been down the first time that reaches `after`.
+    fn update(&mut self, down: bool, after: Duration, now: Instant) -> Option<Duration> {
+        if !down {
+            *self = Self::default();
+            return None;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if self.notified || now - since < after {
+            return None;
+        }
+        self.notified = true;
+
+        Some(now - since)
+    }
+}
+
+pub struct SlackNotifier {
+    config: SlackConfig,
+}
+
+impl SlackNotifier {
+    /// Checks the templates of `config`, so a typo shows at startup rather
+    /// than in Slack.
+    pub fn new(config: SlackConfig) -> Result<Self, String> {
+        let templates = [
+            (&config.block_found, &["device", "message"][..]),
+            (&config.device_down, &["device", "minutes"][..]),
+            (&config.daily_summary, &["summary"][..]),
+        ];
+        for (template, fields) in templates.iter() {
+            if let Some(template) = template {
+                check_template(template, fields, &BTreeMap::new())
+                    .map_err(|e| format!("slack: {}", e))?;
+            }
+        }
+
+        Ok(Self { config })
+    }
+
+    fn post(&self, template: &Option<String>, fields: &[(&str, String)]) {
+        let template = match template {
+            Some(template) => template,
+            None => return,
+        };
+
+        let text = fill(template, fields);
+        if let Err(e) = ureq::post(&self.config.webhook_url).send_json(json!({ "text": text })) {
+            warn!("could not post to Slack: {}", e);
+        }
+    }
+
+    /// Posts notifications in the background while any device of `control`
+    /// is mining.
+    pub fn start(self, fleet: Arc<FleetStats>, control: Arc<FleetControl>) {
+        std::thread::spawn(move || {
+            let down_after = Duration::from_secs(self.config.down_after_secs);
+            let mut down: Vec<Down> = fleet.devices().iter().map(|_| Down::default()).collect();
+            let mut last_event = 0;
+            let mut next_summary = Instant::now() + DAY;
+
+            loop {
+                std::thread::sleep(TICK);
+                control.wait_until_active();
+                let now = Instant::now();
+
+                let events = fleet.events().since(last_event);
+                last_event = events.last().map_or(last_event, |e| e.id);
+                for event in events.iter().filter(|e| e.kind == EventKind::BlockFound) {
+                    self.post(
+                        &self.config.block_found,
+                        &[
+                            ("device", event.device.clone()),
+                            ("message", event.message.clone()),
+                        ],
+                    );
+                }
+
+                for ((stats, device), outage) in fleet
+                    .devices()
+                    .iter()
+                    .zip(control.devices())
+                    .zip(down.iter_mut())
+                {
+                    let is_down = !stats.is_connected() && !device.is_paused();
+                    if let Some(duration) = outage.update(is_down, down_after, now) {
+                        self.post(
+                            &self.config.device_down,
+                            &[
+                                ("device", stats.name().to_string()),
+                                ("minutes", (duration.as_secs() / 60).to_string()),
+                            ],
+                        );
+                    }
+                }
+
+                if now >= next_summary {
+                    next_summary = now + DAY;
+                    self.post(&self.config.daily_summary, &[("summary", fleet.summary())]);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_placeholders() {
+        let text = fill(
+            ":tada: {device} found a block: {message}",
+            &[
+                ("device", "avr-1".to_string()),
+                ("message", "block!".to_string()),
+            ],
+        );
+
+        assert_eq!(text, ":tada: avr-1 found a block: block!");
+    }
+
+    #[test]
+    fn notifies_once_per_outage() {
+        let mut down = Down::default();
+        let after = Duration::from_secs(300);
+        let now = Instant::now();
+
+        assert_eq!(down.update(true, after, now), None);
+        assert_eq!(
+            down.update(true, after, now + after),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(down.update(true, after, now + after * 2), None);
+        assert_eq!(down.update(false, after, now + after * 2), None);
+        assert_eq!(down.update(true, after, now + after * 3), None);
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let config: SlackConfig =
+            serde_yaml::from_str("webhook_url: https://example.com\ndaily_summary: \"{rate}\"\n")
+                .unwrap();
+
+        assert!(SlackNotifier::new(config).is_err());
+    }
+}
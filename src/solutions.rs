@@ -0,0 +1,124 @@
+use crate::protocol::Job;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Default number of solved jobs remembered by a fleet.
+pub const DEFAULT_SOLUTION_CACHE_LEN: usize = 1024;
+
+struct Solution {
+    last_block_hash: String,
+    nonce: u64,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    solutions: HashMap<String, Solution>,
+    clock: u64,
+}
+
+/// Least recently used cache of solved jobs, keyed by expected hash and
+/// shared by every device so a job handed to several devices is searched
+/// only once.
+pub struct SolutionCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl SolutionCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Returns the nonce solving `job`, if it has been solved before.
+    pub fn get(&self, job: &Job) -> Option<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+
+        let solution = entries.solutions.get_mut(&job.expected_hash)?;
+        if solution.last_block_hash != job.last_block_hash {
+            return None;
+        }
+        solution.used = clock;
+
+        Some(solution.nonce)
+    }
+
+    /// Remembers `nonce` as the solution of `job`, evicting the least
+    /// recently used solution when full.
+    pub fn insert(&self, job: &Job, nonce: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+
+        if entries.solutions.len() >= self.capacity
+            && !entries.solutions.contains_key(&job.expected_hash)
+        {
+            let oldest = entries
+                .solutions
+                .iter()
+                .min_by_key(|(_, s)| s.used)
+                .map(|(hash, _)| hash.clone());
+            if let Some(oldest) = oldest {
+                entries.solutions.remove(&oldest);
+            }
+        }
+
+        entries.solutions.insert(
+            job.expected_hash.clone(),
+            Solution {
+                last_block_hash: job.last_block_hash.clone(),
+                nonce,
+                used: clock,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(last_block_hash: &str, expected_hash: &str) -> Job {
+        Job {
+            last_block_hash: last_block_hash.to_string(),
+            expected_hash: expected_hash.to_string(),
+            difficulty: 1,
+        }
+    }
+
+    #[test]
+    fn returns_cached_solution() {
+        let cache = SolutionCache::new(4);
+        cache.insert(&job("a", "x"), 42);
+
+        assert_eq!(cache.get(&job("a", "x")), Some(42));
+        assert_eq!(cache.get(&job("a", "y")), None);
+    }
+
+    #[test]
+    fn ignores_solution_for_other_block() {
+        let cache = SolutionCache::new(4);
+        cache.insert(&job("a", "x"), 42);
+
+        assert_eq!(cache.get(&job("b", "x")), None);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = SolutionCache::new(2);
+        cache.insert(&job("a", "x"), 1);
+        cache.insert(&job("a", "y"), 2);
+        cache.get(&job("a", "x"));
+        cache.insert(&job("a", "z"), 3);
+
+        assert_eq!(cache.get(&job("a", "x")), Some(1));
+        assert_eq!(cache.get(&job("a", "y")), None);
+        assert_eq!(cache.get(&job("a", "z")), Some(3));
+    }
+}
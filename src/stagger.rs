@@ -0,0 +1,97 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Share of the fleet's fetch capacity handed out, so staggering spreads job
+/// fetches without slowing devices down.
+const SLACK: f64 = 0.9;
+
+/// Weight of the newest share when averaging share intervals.
+const SMOOTHING: f64 = 0.1;
+
+struct State {
+    /// Earliest time the next job fetch may happen.
+    next: Instant,
+    /// Average time a device spends on a share.
+    interval: Option<Duration>,
+}
+
+/// Spaces job fetches of the whole fleet evenly across the time a device
+/// spends on a share, so devices started together do not fetch, hash and
+/// submit in bursts.
+pub struct Stagger {
+    devices: usize,
+    state: Mutex<State>,
+}
+
+impl Stagger {
+    pub fn new(devices: usize) -> Self {
+        Self {
+            devices: devices.max(1),
+            state: Mutex::new(State {
+                next: Instant::now(),
+                interval: None,
+            }),
+        }
+    }
+
+    /// Records how long a device spent on a share.
+    pub fn record(&self, interval: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.interval = Some(match state.interval {
+            Some(average) => average.mul_f64(1.0 - SMOOTHING) + interval.mul_f64(SMOOTHING),
+            None => interval,
+        });
+    }
+
+    /// Reserves a time at or after `ready` for a device to fetch its next
+    /// job, at least one spacing after the last reservation.
+    pub fn slot(&self, ready: Instant) -> Instant {
+        let mut state = self.state.lock().unwrap();
+        let spacing = state.interval.map_or(Duration::ZERO, |interval| {
+            interval.mul_f64(SLACK) / self.devices as u32
+        });
+
+        let slot = state.next.max(ready);
+        state.next = slot + spacing;
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_wait_before_first_share() {
+        let stagger = Stagger::new(4);
+        let now = Instant::now();
+
+        assert_eq!(stagger.slot(now), now);
+        assert_eq!(stagger.slot(now), now);
+    }
+
+    #[test]
+    fn spreads_fetches_across_interval() {
+        let stagger = Stagger::new(4);
+        stagger.record(Duration::from_secs(4));
+        let now = Instant::now() + Duration::from_secs(1);
+
+        let slots: Vec<Duration> = (0..3).map(|_| stagger.slot(now) - now).collect();
+
+        assert_eq!(slots[0], Duration::ZERO);
+        assert_eq!(slots[1], Duration::from_millis(900));
+        assert_eq!(slots[2], Duration::from_millis(1800));
+    }
+
+    #[test]
+    fn does_not_delay_spread_out_fetches() {
+        let stagger = Stagger::new(4);
+        stagger.record(Duration::from_secs(4));
+        let now = Instant::now() + Duration::from_secs(1);
+
+        stagger.slot(now);
+        let later = now + Duration::from_secs(2);
+
+        assert_eq!(stagger.slot(later), later);
+    }
+}
@@ -0,0 +1,616 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::PriceOracle;
+use crate::control::{DeviceControl, FleetControl};
+use crate::earnings::Earnings;
+use crate::events::{EventKind, Events};
+
+use std::collections::BTreeMap;
+
+use log::info;
+
+use serde::Serialize;
+
+const LATENCY_BUCKETS: usize = 128;
+
+/// Log-scaled latency histogram, four buckets per power of two microseconds.
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    /// Sum of the recorded latencies, in microseconds.
+    total_us: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [(); LATENCY_BUCKETS].map(|_| AtomicU64::new(0)),
+            total_us: AtomicU64::new(0),
+        }
+    }
+}
+
+fn latency_bucket(micros: u64) -> usize {
+    let index = ((micros.max(1) as f64).log2() * 4f64) as usize;
+    index.min(LATENCY_BUCKETS - 1)
+}
+
+fn bucket_upper_bound(index: usize) -> Duration {
+    Duration::from_micros(2f64.powf((index + 1) as f64 / 4f64) as u64)
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.buckets[latency_bucket(micros)].fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.total_us.store(0, Ordering::Relaxed);
+    }
+
+    /// Mean of the recorded latencies, exact rather than bucketed.
+    pub fn mean(&self) -> Option<Duration> {
+        let count: u64 = self.counts().iter().sum();
+        if count == 0 {
+            return None;
+        }
+
+        Some(Duration::from_micros(
+            self.total_us.load(Ordering::Relaxed) / count,
+        ))
+    }
+
+    fn counts(&self) -> [u64; LATENCY_BUCKETS] {
+        let mut counts = [0; LATENCY_BUCKETS];
+        for (count, bucket) in counts.iter_mut().zip(self.buckets.iter()) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+
+        counts
+    }
+}
+
+fn percentile(counts: &[u64; LATENCY_BUCKETS], p: f64) -> Option<Duration> {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+
+    let rank = (total as f64 * p).ceil().max(1f64) as u64;
+    let mut seen = 0;
+    for (index, count) in counts.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return Some(bucket_upper_bound(index));
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+impl Percentiles {
+    fn from_counts(counts: &[u64; LATENCY_BUCKETS]) -> Self {
+        let ms = |p| percentile(counts, p).map(|d: Duration| d.as_secs_f64() * 1000f64);
+
+        Self {
+            p50_ms: ms(0.5),
+            p95_ms: ms(0.95),
+            p99_ms: ms(0.99),
+        }
+    }
+}
+
+/// How far a device's shares fell behind the time its target rate allows.
+#[derive(Debug, Clone, Serialize)]
+pub struct SolveLag {
+    pub mean_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+}
+
+/// Jobs the pool sent a device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct JobStats {
+    pub jobs: u64,
+    pub min_difficulty: Option<u64>,
+    pub max_difficulty: Option<u64>,
+    pub last_difficulty: Option<u64>,
+}
+
+impl JobStats {
+    fn record(&mut self, difficulty: u64) {
+        self.jobs += 1;
+        self.min_difficulty = Some(
+            self.min_difficulty
+                .map_or(difficulty, |d| d.min(difficulty)),
+        );
+        self.max_difficulty = Some(
+            self.max_difficulty
+                .map_or(difficulty, |d| d.max(difficulty)),
+        );
+        self.last_difficulty = Some(difficulty);
+    }
+}
+
+fn format_percentiles(counts: &[u64; LATENCY_BUCKETS]) -> String {
+    let percentiles = Percentiles::from_counts(counts);
+    let fmt = |p: Option<f64>| match p {
+        Some(ms) => format!("{:.1}", ms),
+        None => "-".to_string(),
+    };
+
+    format!(
+        "p50 {} / p95 {} / p99 {} ms",
+        fmt(percentiles.p50_ms),
+        fmt(percentiles.p95_ms),
+        fmt(percentiles.p99_ms)
+    )
+}
+
+/// How long a device stayed connected, and how often it lost the pool.
+#[derive(Default)]
+struct Uptime {
+    connected_since: Option<Instant>,
+    /// Connected time of sessions that ended.
+    past: Duration,
+    disconnects: u64,
+}
+
+impl Uptime {
+    fn connected(&self) -> Duration {
+        self.past
+            + self
+                .connected_since
+                .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+}
+
+#[derive(Default)]
+pub struct DeviceStats {
+    name: String,
+    events: Arc<Events>,
+    connected: AtomicBool,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    blocks: AtomicU64,
+    reported_rate: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    panics: AtomicU64,
+    slow_shares: AtomicU64,
+    /// Slow shares since the last one solved in time or reported.
+    slow_streak: AtomicU64,
+    uptime: Mutex<Uptime>,
+    /// Transport of the current or last connection.
+    transport: Mutex<Option<String>>,
+    jobs: Mutex<JobStats>,
+    pub job_latency: LatencyHistogram,
+    pub submit_latency: LatencyHistogram,
+    /// How much later than due at the target rate each share was solved,
+    /// zero for shares solved in time.
+    pub solve_lag: LatencyHistogram,
+}
+
+impl DeviceStats {
+    pub fn new(name: String, events: Arc<Events>) -> Self {
+        Self {
+            name,
+            events,
+            ..Self::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn event(&self, kind: EventKind, message: String) {
+        self.events.push(&self.name, kind, message);
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        let mut uptime = self.uptime.lock().unwrap();
+        match (uptime.connected_since, connected) {
+            (None, true) => uptime.connected_since = Some(Instant::now()),
+            (Some(since), false) => {
+                uptime.past += since.elapsed();
+                uptime.connected_since = None;
+                uptime.disconnects += 1;
+            }
+            _ => {}
+        }
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn set_reported_rate(&self, rate: f64) {
+        self.reported_rate.store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_block(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a share solved too slowly, returning how many were in a row.
+    pub fn add_slow(&self) -> u64 {
+        self.slow_shares.fetch_add(1, Ordering::Relaxed);
+        self.slow_streak.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Ends a run of slow shares, when a share is solved in time or the run
+    /// was reported.
+    pub fn end_slow_streak(&self) {
+        self.slow_streak.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add_job(&self, difficulty: u64) {
+        self.jobs.lock().unwrap().record(difficulty);
+    }
+
+    pub fn set_transport(&self, transport: String) {
+        *self.transport.lock().unwrap() = Some(transport);
+    }
+
+    /// Transport of the current or last connection, if there was one.
+    pub fn transport(&self) -> Option<String> {
+        self.transport.lock().unwrap().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn reported_rate(&self) -> f64 {
+        f64::from_bits(self.reported_rate.load(Ordering::Relaxed))
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn blocks(&self) -> u64 {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    /// Protocol bytes written to the pool, excluding TCP/IP overhead.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Protocol bytes read from the pool, excluding TCP/IP overhead.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn jobs(&self) -> JobStats {
+        *self.jobs.lock().unwrap()
+    }
+
+    /// Shares solved too far below the target rate.
+    pub fn slow_shares(&self) -> u64 {
+        self.slow_shares.load(Ordering::Relaxed)
+    }
+
+    /// Panics caught while the device was mining.
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Total time connected to the pool.
+    pub fn connected_time(&self) -> Duration {
+        self.uptime.lock().unwrap().connected()
+    }
+
+    /// Times the device lost or dropped its connection.
+    pub fn disconnects(&self) -> u64 {
+        self.uptime.lock().unwrap().disconnects
+    }
+
+    /// Mean connected time between disconnects, `None` before the first.
+    /// A device that reconnects every few minutes spends much of its time
+    /// reconnecting and fetching jobs rather than earning.
+    pub fn mean_time_between_failures(&self) -> Option<Duration> {
+        let uptime = self.uptime.lock().unwrap();
+        match uptime.disconnects {
+            0 => None,
+            n => Some(uptime.connected() / n as u32),
+        }
+    }
+
+    /// Persistent lag means the host cannot keep up with the device's
+    /// target rate.
+    pub fn solve_lag(&self) -> SolveLag {
+        let ms = |d: Duration| d.as_secs_f64() * 1000f64;
+
+        SolveLag {
+            mean_ms: self.solve_lag.mean().map(ms),
+            p95_ms: percentile(&self.solve_lag.counts(), 0.95).map(ms),
+        }
+    }
+
+    /// Zeroes the counters, latencies and uptime. Whether the device is
+    /// connected and the rate it reports are left alone.
+    pub fn reset(&self) {
+        for counter in [
+            &self.accepted,
+            &self.rejected,
+            &self.blocks,
+            &self.bytes_sent,
+            &self.bytes_received,
+            &self.panics,
+            &self.slow_shares,
+            &self.slow_streak,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        *self.jobs.lock().unwrap() = JobStats::default();
+        self.job_latency.reset();
+        self.submit_latency.reset();
+        self.solve_lag.reset();
+
+        let mut uptime = self.uptime.lock().unwrap();
+        *uptime = Uptime {
+            connected_since: uptime.connected_since.map(|_| Instant::now()),
+            ..Uptime::default()
+        };
+    }
+}
+
+/// Stats summed over several devices, such as those of one account.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub devices: usize,
+    pub connected: usize,
+    /// Rate reported by the connected devices.
+    pub reported_rate: f64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub blocks: u64,
+    pub disconnects: u64,
+}
+
+impl Totals {
+    pub fn add(&mut self, device: &DeviceStats) {
+        self.devices += 1;
+        if device.is_connected() {
+            self.connected += 1;
+            self.reported_rate += device.reported_rate();
+        }
+        self.accepted += device.accepted();
+        self.rejected += device.rejected();
+        self.blocks += device.blocks();
+        self.disconnects += device.disconnects();
+    }
+}
+
+pub struct FleetStats {
+    devices: Vec<Arc<DeviceStats>>,
+    events: Arc<Events>,
+    earnings: Earnings,
+    /// When the fleet started or its stats were last reset.
+    started: Mutex<Instant>,
+}
+
+impl FleetStats {
+    pub fn new(device_names: Vec<String>) -> Self {
+        let events = Arc::new(Events::new());
+
+        Self {
+            devices: device_names
+                .into_iter()
+                .map(|name| Arc::new(DeviceStats::new(name, events.clone())))
+                .collect(),
+            events,
+            earnings: Earnings::default(),
+            started: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn events(&self) -> &Arc<Events> {
+        &self.events
+    }
+
+    /// Earnings per account, estimated once balances are polled with
+    /// [`crate::earnings::start_polling`].
+    pub fn earnings(&self) -> &Earnings {
+        &self.earnings
+    }
+
+    pub fn device(&self, index: usize) -> Arc<DeviceStats> {
+        self.devices[index].clone()
+    }
+
+    pub fn devices(&self) -> &[Arc<DeviceStats>] {
+        &self.devices
+    }
+
+    pub fn summary(&self) -> String {
+        let up = self.devices.iter().filter(|d| d.is_connected()).count();
+        let down = self.devices.len() - up;
+
+        let rate: f64 = self
+            .devices
+            .iter()
+            .filter(|d| d.is_connected())
+            .map(|d| d.reported_rate())
+            .sum();
+        let accepted: u64 = self.devices.iter().map(|d| d.accepted()).sum();
+        let rejected: u64 = self.devices.iter().map(|d| d.rejected()).sum();
+        let blocks: u64 = self.devices.iter().map(|d| d.blocks()).sum();
+        let disconnects: u64 = self.devices.iter().map(|d| d.disconnects()).sum();
+        let sent: u64 = self.devices.iter().map(|d| d.bytes_sent()).sum();
+        let received: u64 = self.devices.iter().map(|d| d.bytes_received()).sum();
+
+        let shares = accepted + rejected;
+        let minutes = self.elapsed().as_secs_f64() / 60f64;
+        let shares_per_min = if minutes > 0f64 {
+            shares as f64 / minutes
+        } else {
+            0f64
+        };
+        let acceptance = if shares > 0 {
+            accepted as f64 / shares as f64 * 100f64
+        } else {
+            0f64
+        };
+
+        format!(
+            "up: {}, down: {}, rate: {:.2} H/s, shares/min: {:.2}, accepted: {:.1}%, blocks: {}, \
+             disconnects: {}, sent: {:.1} KiB, received: {:.1} KiB",
+            up,
+            down,
+            rate,
+            shares_per_min,
+            acceptance,
+            blocks,
+            disconnects,
+            sent as f64 / 1024f64,
+            received as f64 / 1024f64
+        )
+    }
+
+    /// Time since the fleet started or its stats were last reset.
+    pub fn elapsed(&self) -> Duration {
+        self.started.lock().unwrap().elapsed()
+    }
+
+    /// Zeroes the counters of all devices, to measure from now on.
+    pub fn reset(&self) {
+        *self.started.lock().unwrap() = Instant::now();
+        for device in self.devices.iter() {
+            device.reset();
+        }
+        self.earnings.reset();
+        info!("stats reset");
+    }
+
+    /// Totals of the devices sharing a key, such as their account or group,
+    /// read from their controls. Devices without a key are left out.
+    pub fn totals_by(
+        &self,
+        control: &FleetControl,
+        key: impl Fn(&DeviceControl) -> Option<String>,
+    ) -> BTreeMap<String, Totals> {
+        let mut totals: BTreeMap<String, Totals> = BTreeMap::new();
+        for (device, device_control) in self.devices.iter().zip(control.devices().iter()) {
+            if let Some(key) = key(device_control) {
+                totals.entry(key).or_default().add(device);
+            }
+        }
+
+        totals
+    }
+
+    /// One line per account, with its estimated earnings when known.
+    pub fn account_summaries(&self, control: &FleetControl) -> Vec<String> {
+        self.totals_by(control, |d| Some(d.device().username))
+            .into_iter()
+            .map(|(username, totals)| {
+                let earnings = match (
+                    self.earnings.per_day(&username),
+                    PriceOracle::shared().cached(),
+                ) {
+                    (Some(per_day), Some(price)) => {
+                        format!(
+                            ", earning: {:.2} DUCO/day (${:.4})",
+                            per_day,
+                            per_day * price
+                        )
+                    }
+                    (Some(per_day), None) => format!(", earning: {:.2} DUCO/day", per_day),
+                    (None, _) => String::new(),
+                };
+                format!(
+                    "{}: up: {}/{}, rate: {:.2} H/s, accepted: {}, rejected: {}, blocks: {}{}",
+                    username,
+                    totals.connected,
+                    totals.devices,
+                    totals.reported_rate,
+                    totals.accepted,
+                    totals.rejected,
+                    totals.blocks,
+                    earnings
+                )
+            })
+            .collect()
+    }
+
+    fn merged(
+        &self,
+        histogram: impl Fn(&DeviceStats) -> &LatencyHistogram,
+    ) -> [u64; LATENCY_BUCKETS] {
+        let mut merged = [0; LATENCY_BUCKETS];
+        for device in self.devices.iter() {
+            for (total, count) in merged.iter_mut().zip(histogram(device).counts().iter()) {
+                *total += count;
+            }
+        }
+
+        merged
+    }
+
+    pub fn job_latency(&self) -> Percentiles {
+        Percentiles::from_counts(&self.merged(|d| &d.job_latency))
+    }
+
+    pub fn submit_latency(&self) -> Percentiles {
+        Percentiles::from_counts(&self.merged(|d| &d.submit_latency))
+    }
+
+    pub fn latency_summary(&self) -> String {
+        format!(
+            "job fetch: {}, share submit: {}",
+            format_percentiles(&self.merged(|d| &d.job_latency)),
+            format_percentiles(&self.merged(|d| &d.submit_latency))
+        )
+    }
+}
+
+pub fn start_reporter(fleet: Arc<FleetStats>, control: Arc<FleetControl>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        control.wait_until_active();
+        info!("fleet {}", fleet.summary());
+        info!("latency {}", fleet.latency_summary());
+        for summary in fleet.account_summaries(&control) {
+            info!("account {}", summary);
+        }
+    });
+}
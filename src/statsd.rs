@@ -0,0 +1,302 @@
+use crate::host::HostStatus;
+
+use duino_miner::control::FleetControl;
+use duino_miner::stats::FleetStats;
+
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+    dogstatsd: bool,
+}
+
+impl StatsdSink {
+    pub fn new(
+        addr: &str,
+        prefix: String,
+        tags: Vec<String>,
+        dogstatsd: bool,
+    ) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+
+        Ok(Self {
+            socket,
+            prefix,
+            tags,
+            dogstatsd,
+        })
+    }
+
+    /// A metric of `device`. With dogstatsd, `labels` such as its group and
+    /// account are added as tags.
+    fn metric_line(
+        &self,
+        device: &str,
+        labels: &[String],
+        name: &str,
+        value: String,
+        kind: &str,
+    ) -> String {
+        if self.dogstatsd {
+            let mut tags = self.tags.clone();
+            tags.push(format!("device:{}", device));
+            tags.extend_from_slice(labels);
+
+            format!(
+                "{}.{}:{}|{}|#{}",
+                self.prefix,
+                name,
+                value,
+                kind,
+                tags.join(",")
+            )
+        } else {
+            format!("{}.{}.{}:{}|{}", self.prefix, device, name, value, kind)
+        }
+    }
+
+    fn send(&self, line: String) {
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            warn!("could not send statsd metric: {}", e);
+        }
+    }
+
+    /// Totals of a group or account, sent as `<prefix>.<kind>.<name>.<metric>`.
+    /// With dogstatsd, the `group` and `account` tags aggregate instead.
+    fn send_totals(&self, kind: &str, name: &str, totals: &Totals) {
+        let metrics = [
+            ("connected", totals.connected.to_string(), "g"),
+            ("reported_rate", format!("{:.2}", totals.reported_rate), "g"),
+            ("accepted", totals.accepted.to_string(), "c"),
+            ("rejected", totals.rejected.to_string(), "c"),
+            ("blocks", totals.blocks.to_string(), "c"),
+            ("disconnects", totals.disconnects.to_string(), "c"),
+        ];
+        for (metric, value, metric_kind) in metrics.iter() {
+            self.send(format!(
+                "{}.{}.{}.{}:{}|{}",
+                self.prefix, kind, name, metric, value, metric_kind
+            ));
+        }
+    }
+
+    fn send_earnings(&self, account: &str, per_day: f64) {
+        if self.dogstatsd {
+            let mut tags = self.tags.clone();
+            tags.push(format!("account:{}", account));
+            self.send(format!(
+                "{}.earnings_per_day:{:.4}|g|#{}",
+                self.prefix,
+                per_day,
+                tags.join(",")
+            ));
+        } else {
+            self.send(format!(
+                "{}.account.{}.earnings_per_day:{:.4}|g",
+                self.prefix, account, per_day
+            ));
+        }
+    }
+
+    /// Readings of the host, sent as `<prefix>.host.<metric>`.
+    fn send_host(&self, host: &HostStatus) {
+        for (metric, value) in [
+            ("cpu_mhz", host.cpu_mhz),
+            ("temperature_c", host.temperature_c),
+        ] {
+            if let Some(value) = value {
+                let mut line = format!("{}.host.{}:{:.1}|g", self.prefix, metric, value);
+                if self.dogstatsd && !self.tags.is_empty() {
+                    line.push_str(&format!("|#{}", self.tags.join(",")));
+                }
+                self.send(line);
+            }
+        }
+    }
+
+    pub fn start(self, fleet: Arc<FleetStats>, control: Arc<FleetControl>, interval: Duration) {
+        std::thread::spawn(move || {
+            let mut last: Vec<(u64, u64, u64, u64, u64, u64, u64)> =
+                vec![(0, 0, 0, 0, 0, 0, 0); fleet.devices().len()];
+
+            loop {
+                std::thread::sleep(interval);
+                control.wait_until_active();
+
+                let mut aggregates: BTreeMap<(&str, String), Totals> = BTreeMap::new();
+                for ((device, last), device_control) in fleet
+                    .devices()
+                    .iter()
+                    .zip(last.iter_mut())
+                    .zip(control.devices().iter())
+                {
+                    let name = device.name();
+                    let settings = device_control.device();
+                    let mut keys = vec![("account", settings.username)];
+                    keys.extend(settings.group.map(|group| ("group", group)));
+                    let labels: Vec<String> = keys
+                        .iter()
+                        .map(|(kind, name)| format!("{}:{}", kind, name))
+                        .collect();
+                    let current = (
+                        device.accepted(),
+                        device.rejected(),
+                        device.blocks(),
+                        device.bytes_sent(),
+                        device.bytes_received(),
+                        device.panics(),
+                        device.disconnects(),
+                    );
+
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "connected",
+                        (device.is_connected() as u8).to_string(),
+                        "g",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "reported_rate",
+                        format!("{:.2}", device.reported_rate()),
+                        "g",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "accepted",
+                        delta(current.0, last.0).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "rejected",
+                        delta(current.1, last.1).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "blocks",
+                        delta(current.2, last.2).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "bytes_sent",
+                        delta(current.3, last.3).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "bytes_received",
+                        delta(current.4, last.4).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "panics",
+                        delta(current.5, last.5).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "disconnects",
+                        delta(current.6, last.6).to_string(),
+                        "c",
+                    ));
+                    self.send(self.metric_line(
+                        name,
+                        &labels,
+                        "connected_secs",
+                        format!("{:.0}", device.connected_time().as_secs_f64()),
+                        "g",
+                    ));
+                    if let Some(mtbf) = device.mean_time_between_failures() {
+                        self.send(self.metric_line(
+                            name,
+                            &labels,
+                            "mtbf_secs",
+                            format!("{:.0}", mtbf.as_secs_f64()),
+                            "g",
+                        ));
+                    }
+                    let lag = device.solve_lag();
+                    for (metric, value) in [
+                        ("solve_lag_mean_ms", lag.mean_ms),
+                        ("solve_lag_p95_ms", lag.p95_ms),
+                    ] {
+                        if let Some(ms) = value {
+                            self.send(self.metric_line(
+                                name,
+                                &labels,
+                                metric,
+                                format!("{:.1}", ms),
+                                "g",
+                            ));
+                        }
+                    }
+
+                    for key in keys {
+                        let totals = aggregates.entry(key).or_default();
+                        totals.connected += device.is_connected() as u64;
+                        if device.is_connected() {
+                            totals.reported_rate += device.reported_rate();
+                        }
+                        totals.accepted += delta(current.0, last.0);
+                        totals.rejected += delta(current.1, last.1);
+                        totals.blocks += delta(current.2, last.2);
+                        totals.disconnects += delta(current.6, last.6);
+                    }
+
+                    *last = current;
+                }
+
+                for (kind, name) in aggregates.keys() {
+                    if *kind == "account" {
+                        if let Some(per_day) = fleet.earnings().per_day(name) {
+                            self.send_earnings(name, per_day);
+                        }
+                    }
+                }
+                self.send_host(&HostStatus::read());
+                if !self.dogstatsd {
+                    for ((kind, name), totals) in aggregates.iter() {
+                        self.send_totals(kind, name, totals);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Increase of a counter since the last send, which is all of it after a
+/// stats reset.
+fn delta(current: u64, last: u64) -> u64 {
+    current.checked_sub(last).unwrap_or(current)
+}
+
+/// Sums over a group or account for one interval, counters as increases.
+#[derive(Default)]
+struct Totals {
+    connected: u64,
+    reported_rate: f64,
+    accepted: u64,
+    rejected: u64,
+    blocks: u64,
+    disconnects: u64,
+}
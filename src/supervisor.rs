@@ -0,0 +1,89 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+static INSTALL_HOOK: Once = Once::new();
+
+thread_local! {
+    /// Whether a panic on this thread will be caught by [`catch`].
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    /// Backtrace of the last panic caught on this thread.
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+/// A panic caught by [`catch`].
+#[derive(Debug)]
+pub struct Panic {
+    pub message: String,
+    /// Captured where the panic was raised.
+    pub backtrace: String,
+}
+
+/// Runs `task`, turning a panic into an error instead of unwinding out of
+/// the thread. Panics outside of `catch` still go to the previous hook.
+pub fn catch<T>(task: impl FnOnce() -> T) -> Result<T, Panic> {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) {
+                BACKTRACE
+                    .with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::force_capture()));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+
+    let catching = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(task));
+    CATCHING.with(|c| c.set(catching));
+
+    result.map_err(|payload| Panic {
+        message: message(&*payload),
+        backtrace: BACKTRACE
+            .with(|backtrace| backtrace.borrow_mut().take())
+            .map(|backtrace| backtrace.to_string())
+            .unwrap_or_default(),
+    })
+}
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_result_without_panic() {
+        assert_eq!(catch(|| 42).unwrap(), 42);
+    }
+
+    #[test]
+    fn catches_panic_with_backtrace() {
+        let panic = catch(|| -> u32 { panic!("clock went backwards by {}s", 3) }).unwrap_err();
+
+        assert_eq!(panic.message, "clock went backwards by 3s");
+        assert!(!panic.backtrace.is_empty());
+    }
+
+    #[test]
+    fn nested_catch_restores_outer() {
+        let outer = catch(|| {
+            let inner = catch(|| panic!("inner"));
+            assert!(inner.is_err());
+            panic!("outer")
+        });
+
+        assert_eq!(outer.map(|_: ()| ()).unwrap_err().message, "outer");
+    }
+}
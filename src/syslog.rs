@@ -0,0 +1,68 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+
+const FACILITY_DAEMON: u8 = 3;
+
+pub struct SyslogLogger {
+    socket: Mutex<UnixDatagram>,
+    level: LevelFilter,
+    pid: u32,
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+impl SyslogLogger {
+    pub fn connect(level: LevelFilter) -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect("/dev/log")
+            .or_else(|_| socket.connect("/var/run/syslog"))?;
+
+        Ok(Self {
+            socket: Mutex::new(socket),
+            level,
+            pid: std::process::id(),
+        })
+    }
+
+    pub fn init(level: LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
+        let logger = Self::connect(level)?;
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(level);
+
+        Ok(())
+    }
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = format!(
+            "<{}>duino-miner[{}]: {}: {}",
+            FACILITY_DAEMON * 8 + severity(record.level()),
+            self.pid,
+            record.target(),
+            record.args()
+        );
+
+        let _ = self.socket.lock().unwrap().send(message.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,88 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limits the bytes per second shared by every device. Up to one second of
+/// traffic may burst; beyond that callers are delayed until it is paid back.
+pub struct Throttle {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+
+        Self {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` from the budget at `now` and returns how long the caller
+    /// has to wait before the budget covers them.
+    fn take(&self, bytes: usize, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let refill =
+            now.saturating_duration_since(bucket.updated).as_secs_f64() * self.bytes_per_sec;
+        bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec) - bytes as f64;
+        bucket.updated = now;
+
+        if bucket.tokens < 0f64 {
+            Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
+    /// Blocks until `bytes` fit in the bandwidth budget.
+    pub fn acquire(&self, bytes: usize) {
+        let wait = self.take(bytes, Instant::now());
+        if wait > Duration::from_secs(0) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_one_second_burst() {
+        let throttle = Throttle::new(1000);
+        let now = Instant::now();
+
+        assert_eq!(throttle.take(600, now), Duration::from_secs(0));
+        assert_eq!(throttle.take(400, now), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn delays_traffic_over_budget() {
+        let throttle = Throttle::new(1000);
+        let now = Instant::now();
+        throttle.take(1000, now);
+
+        assert_eq!(throttle.take(500, now), Duration::from_millis(500));
+        assert_eq!(throttle.take(500, now), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let throttle = Throttle::new(1000);
+        let now = Instant::now();
+        throttle.take(1000, now);
+
+        let later = now + Duration::from_millis(250);
+        assert_eq!(throttle.take(250, later), Duration::from_secs(0));
+        assert_eq!(throttle.take(250, later), Duration::from_millis(250));
+    }
+}
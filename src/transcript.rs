@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long the device may stay silent before the replay ends.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the device to the pool.
+    Sent,
+    /// From the pool to the device.
+    Received,
+}
+
+/// One raw protocol message, as written to a transcript file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub device: String,
+    pub direction: Direction,
+    pub data: String,
+}
+
+/// Appends every message between the devices and the pool to a file, one
+/// JSON object per line.
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, device: &str, direction: Direction, data: &str) -> std::io::Result<()> {
+        let exchange = Exchange {
+            device: device.to_string(),
+            direction,
+            data: data.to_string(),
+        };
+        let mut line = serde_json::to_string(&exchange)?;
+        line.push('\n');
+
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+/// Reads a transcript written by [`Recorder`].
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Exchange>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut exchanges = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            exchanges.push(serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(exchanges)
+}
+
+/// A message the device sent that differs from the transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// Position of the message among the replayed exchanges.
+    pub index: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    /// Exchanges replayed before the transcript ran out or the device
+    /// stopped talking.
+    pub replayed: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// First comma-separated field of a message: the command, or the nonce of
+/// a share. The rest of a share depends on timing.
+fn key(message: &str) -> &str {
+    message.trim().split(',').next().unwrap_or_default()
+}
+
+/// Plays the pool's side of `exchanges` to whoever connects to `listener`,
+/// comparing what the device sends with what was recorded. Sent messages
+/// are compared by their first field only. A device that disconnects midway
+/// is picked up again on its next connection.
+pub fn replay(listener: &TcpListener, exchanges: &[Exchange]) -> std::io::Result<ReplayReport> {
+    let mut report = ReplayReport::default();
+    let mut connection: Option<(TcpStream, BufReader<TcpStream>)> = None;
+
+    while report.replayed < exchanges.len() {
+        let (stream, reader) = match &mut connection {
+            Some(connection) => connection,
+            None => {
+                let (stream, _) = listener.accept()?;
+                stream.set_read_timeout(Some(REPLAY_TIMEOUT))?;
+                let reader = BufReader::new(stream.try_clone()?);
+                connection.insert((stream, reader))
+            }
+        };
+
+        let exchange = &exchanges[report.replayed];
+        match exchange.direction {
+            Direction::Received => {
+                if stream.write_all(exchange.data.as_bytes()).is_err() {
+                    connection = None;
+                    continue;
+                }
+            }
+            Direction::Sent => {
+                let mut actual = String::new();
+                match reader.read_line(&mut actual) {
+                    Ok(0) => {
+                        connection = None;
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        break
+                    }
+                    Err(e) => return Err(e),
+                }
+
+                if key(&actual) != key(&exchange.data) {
+                    report.mismatches.push(Mismatch {
+                        index: report.replayed,
+                        expected: exchange.data.trim().to_string(),
+                        actual: actual.trim().to_string(),
+                    });
+                }
+            }
+        }
+
+        report.replayed += 1;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(direction: Direction, data: &str) -> Exchange {
+        Exchange {
+            device: "avr-1".to_string(),
+            direction,
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_file() {
+        let path = std::env::temp_dir().join(format!("transcript-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let recorder = Recorder::open(&path).unwrap();
+        recorder
+            .record("avr-1", Direction::Received, "2.7\n")
+            .unwrap();
+        recorder
+            .record("avr-1", Direction::Sent, "JOB,alice,AVR\n")
+            .unwrap();
+
+        let exchanges = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            exchanges,
+            vec![
+                exchange(Direction::Received, "2.7\n"),
+                exchange(Direction::Sent, "JOB,alice,AVR\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_differing_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exchanges = vec![
+            exchange(Direction::Received, "2.7\n"),
+            exchange(Direction::Sent, "JOB,alice,AVR\n"),
+            exchange(Direction::Received, "aa,bb,6\n"),
+            exchange(Direction::Sent, "42,190.1,Official AVR Miner v2.6\n"),
+        ];
+
+        let device = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            stream.write_all(b"JOB,alice,AVR\n").unwrap();
+            reader.read_line(&mut line).unwrap();
+            stream
+                .write_all(b"41,190.3,Official AVR Miner v2.6\n")
+                .unwrap();
+        });
+
+        let report = replay(&listener, &exchanges).unwrap();
+        device.join().unwrap();
+
+        assert_eq!(report.replayed, 4);
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch {
+                index: 3,
+                expected: "42,190.1,Official AVR Miner v2.6".to_string(),
+                actual: "41,190.3,Official AVR Miner v2.6".to_string(),
+            }]
+        );
+    }
+}
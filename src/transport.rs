@@ -0,0 +1,256 @@
+//! Ways of reaching a pool. A device tries the transports it lists in order,
+//! so it still connects on networks that filter some ports.
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    /// A direct connection to the pool.
+    Tcp,
+    /// A WebSocket at a URL such as `wss://host:port`, in place of the pool.
+    /// Needs the `websocket` feature.
+    Websocket(String),
+    /// A connection to the pool through the SOCKS5 proxy at `host:port`.
+    Socks5(String),
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Websocket(url) => write!(f, "websocket {}", url),
+            Transport::Socks5(proxy) => write!(f, "socks5 via {}", proxy),
+        }
+    }
+}
+
+/// A connection to the pool, whatever the transport.
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Connects to `pool`, as `host:port`, over `transport`. With `keepalive`,
+/// TCP connections send keepalive probes once idle that long.
+pub fn connect(
+    transport: &Transport,
+    pool: &str,
+    keepalive: Option<Duration>,
+) -> std::io::Result<Box<dyn Stream>> {
+    let stream = match transport {
+        Transport::Tcp => TcpStream::connect(pool)?,
+        Transport::Websocket(url) => return websocket(url),
+        Transport::Socks5(proxy) => socks5(proxy, pool)?,
+    };
+    if let Some(idle) = keepalive {
+        keep_alive(&stream, idle)?;
+    }
+
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn keep_alive(stream: &TcpStream, idle: Duration) -> std::io::Result<()> {
+    socket2::SockRef::from(stream).set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn keep_alive(_stream: &TcpStream, _idle: Duration) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Asks the SOCKS5 proxy at `proxy` to connect to `target`, without
+/// authentication, leaving the name of the target for the proxy to resolve.
+fn socks5(proxy: &str, target: &str) -> std::io::Result<TcpStream> {
+    let (host, port) = target
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .filter(|(host, _)| host.len() <= u8::MAX as usize)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "pool is not host:port"))?;
+
+    let mut stream = TcpStream::connect(proxy)?;
+    stream.write_all(&[5, 1, 0])?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method)?;
+    if method != [5, 0] {
+        return Err(Error::other("proxy requires authentication"));
+    }
+
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(Error::other(format!(
+            "proxy refused the connection with code {}",
+            reply[1]
+        )));
+    }
+    // The address the proxy bound, of no use here.
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        kind => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("proxy replied with address type {}", kind),
+            ))
+        }
+    };
+    stream.read_exact(&mut vec![0u8; address_len + 2])?;
+
+    Ok(stream)
+}
+
+#[cfg(feature = "websocket")]
+fn websocket(url: &str) -> std::io::Result<Box<dyn Stream>> {
+    let (socket, _) = tungstenite::connect(url).map_err(|e| Error::other(e.to_string()))?;
+
+    Ok(Box::new(WebSocketStream {
+        socket,
+        pending: Vec::new(),
+    }))
+}
+
+#[cfg(not(feature = "websocket"))]
+fn websocket(_url: &str) -> std::io::Result<Box<dyn Stream>> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "built without the websocket feature",
+    ))
+}
+
+/// Lines to and from the pool, one per WebSocket message.
+#[cfg(feature = "websocket")]
+struct WebSocketStream {
+    socket: tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>,
+    /// What is left of the last message received.
+    pending: Vec<u8>,
+}
+
+#[cfg(feature = "websocket")]
+impl Read for WebSocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use tungstenite::Message;
+
+        while self.pending.is_empty() {
+            let message = self
+                .socket
+                .read()
+                .map_err(|e| Error::other(e.to_string()))?;
+            self.pending = match message {
+                Message::Text(text) => text.into_bytes(),
+                Message::Binary(data) => data,
+                Message::Close(_) => return Ok(0),
+                _ => continue,
+            };
+            if !self.pending.ends_with(b"\n") {
+                self.pending.push(b'\n');
+            }
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Write for WebSocketStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        self.socket
+            .send(tungstenite::Message::Text(
+                line.trim_end_matches('\n').to_string(),
+            ))
+            .map_err(|e| Error::other(e.to_string()))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.socket.flush().map_err(|e| Error::other(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    #[test]
+    fn reads_transports_from_yaml() {
+        let transports: Vec<Transport> =
+            serde_yaml::from_str("- tcp\n- socks5: 127.0.0.1:9050\n- websocket: wss://pool\n")
+                .unwrap();
+
+        assert_eq!(
+            transports,
+            vec![
+                Transport::Tcp,
+                Transport::Socks5("127.0.0.1:9050".to_string()),
+                Transport::Websocket("wss://pool".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn connects_through_socks5_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+
+            let mut request = [0u8; 5 + 4 + 2];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            stream.write_all(b"2.7\n").unwrap();
+            request
+        });
+
+        let mut stream = connect(&Transport::Socks5(proxy), "pool:2811", None).unwrap();
+        let mut version = [0u8; 4];
+        stream.read_exact(&mut version).unwrap();
+
+        assert_eq!(&version, b"2.7\n");
+        assert_eq!(&server.join().unwrap()[3..], b"\x03\x04pool\x0a\xfb");
+    }
+
+    #[test]
+    fn connects_with_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pool = listener.local_addr().unwrap().to_string();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"2.7\n").unwrap();
+        });
+
+        let mut stream = connect(&Transport::Tcp, &pool, Some(Duration::from_secs(30))).unwrap();
+        let mut version = [0u8; 4];
+        stream.read_exact(&mut version).unwrap();
+
+        assert_eq!(&version, b"2.7\n");
+        server.join().unwrap();
+    }
+}
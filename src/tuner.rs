@@ -0,0 +1,165 @@
+use crate::config::{Device, TunerConfig};
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::info;
+
+/// Difficulty tiers the pool accepts as hints, easiest first.
+pub const TIERS: [&str; 4] = ["LOW", "MEDIUM", "NET", "EXTREME"];
+
+/// A device's tier and the shares solved since it last changed.
+struct Tuning {
+    tier: usize,
+    shares: u32,
+    total_secs: f64,
+}
+
+/// Moves devices to an easier tier when their shares take too long and to a
+/// harder one when they come too quickly. Only devices requesting one of
+/// [`TIERS`] are tuned; other device types have a single fixed difficulty.
+pub struct DifficultyTuner {
+    config: TunerConfig,
+    tuning: Mutex<HashMap<String, Tuning>>,
+}
+
+fn tier_index(tier: &str) -> Option<usize> {
+    TIERS.iter().position(|t| t.eq_ignore_ascii_case(tier))
+}
+
+impl DifficultyTuner {
+    pub fn new(config: TunerConfig) -> Self {
+        Self {
+            config,
+            tuning: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Difficulty tier `device` should request jobs for.
+    pub fn difficulty(&self, device: &Device) -> String {
+        match self.tuning.lock().unwrap().get(&device.name()) {
+            Some(tuning) => TIERS[tuning.tier].to_string(),
+            None => device.difficulty().to_string(),
+        }
+    }
+
+    /// Records that `device` took `elapsed` to solve a share. Returns the new
+    /// tier when this share completes a window that misses the target.
+    pub fn record(&self, device: &Device, elapsed: Duration) -> Option<&'static str> {
+        let mut tuned = self.tuning.lock().unwrap();
+        let tuning = match tuned.entry(device.name()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Tuning {
+                tier: tier_index(device.difficulty())?,
+                shares: 0,
+                total_secs: 0.0,
+            }),
+        };
+
+        tuning.shares += 1;
+        tuning.total_secs += elapsed.as_secs_f64();
+        if tuning.shares < self.config.window.max(1) {
+            return None;
+        }
+
+        let average = tuning.total_secs / tuning.shares as f64;
+        tuning.shares = 0;
+        tuning.total_secs = 0.0;
+
+        let target = self.config.target_secs;
+        let tolerance = self.config.tolerance.max(1.0);
+        let tier = if average > target * tolerance && tuning.tier > 0 {
+            tuning.tier - 1
+        } else if average < target / tolerance && tuning.tier + 1 < TIERS.len() {
+            tuning.tier + 1
+        } else {
+            return None;
+        };
+
+        info!(
+            "{} averaged {:.1}s per share against a target of {:.1}s, moving from {} to {}",
+            device.name(),
+            average,
+            target,
+            TIERS[tuning.tier],
+            TIERS[tier]
+        );
+        tuning.tier = tier;
+
+        Some(TIERS[tier])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuner(window: u32) -> DifficultyTuner {
+        DifficultyTuner::new(TunerConfig {
+            enabled: true,
+            target_secs: 10.0,
+            window,
+            tolerance: 2.0,
+        })
+    }
+
+    fn device(device_type: &str, difficulty: Option<&str>) -> Device {
+        Device {
+            username: "my_username".to_string(),
+            device_name: "pc-1".to_string(),
+            device_type: device_type.to_string(),
+            chip_id: "None".to_string(),
+            firmware: "Official PC Miner 2.7.3".to_string(),
+            target_rate: 100000,
+            profile: None,
+            wallet_id: None,
+            mining_key: None,
+            difficulty: difficulty.map(str::to_string),
+            restart: None,
+            group: None,
+            transports: Vec::new(),
+            namespace: None,
+        }
+    }
+
+    #[test]
+    fn slow_shares_lower_tier() {
+        let tuner = tuner(2);
+        let pc = device("PC", None);
+
+        assert_eq!(tuner.record(&pc, Duration::from_secs(30)), None);
+        assert_eq!(tuner.record(&pc, Duration::from_secs(30)), Some("LOW"));
+        assert_eq!(tuner.difficulty(&pc), "LOW");
+    }
+
+    #[test]
+    fn fast_shares_raise_tier() {
+        let tuner = tuner(1);
+        let pc = device("PC", Some("net"));
+
+        assert_eq!(tuner.record(&pc, Duration::from_secs(1)), Some("EXTREME"));
+        assert_eq!(tuner.record(&pc, Duration::from_secs(1)), None);
+        assert_eq!(tuner.difficulty(&pc), "EXTREME");
+    }
+
+    #[test]
+    fn keeps_tier_within_tolerance() {
+        let tuner = tuner(2);
+        let pc = device("PC", None);
+
+        tuner.record(&pc, Duration::from_secs(4));
+        assert_eq!(tuner.record(&pc, Duration::from_secs(25)), None);
+        assert_eq!(tuner.difficulty(&pc), "MEDIUM");
+    }
+
+    #[test]
+    fn leaves_fixed_tiers_alone() {
+        let tuner = tuner(1);
+        let avr = device("AVR", None);
+
+        assert_eq!(tuner.record(&avr, Duration::from_secs(60)), None);
+        assert_eq!(tuner.difficulty(&avr), "AVR");
+    }
+}
@@ -1,10 +1,6 @@
-use duino_miner::error::MinerError;
-
-use serde::{Deserialize, Serialize};
-
 use rand::Rng;
 
-pub fn generate_8hex() -> String {
+pub fn generate_8hex<R: Rng>(rng: &mut R) -> String {
     const HEX_ARRAY: [char; 16] = [
         '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
     ];
@@ -12,27 +8,9 @@ pub fn generate_8hex() -> String {
     let mut result = String::new();
 
     for _ in 0..8 {
-        let n: usize = rand::thread_rng().gen_range(0..16);
+        let n: usize = rng.gen_range(0..16);
         result.push(HEX_ARRAY[n]);
     }
 
     result
 }
-
-pub fn get_pool_info() -> Result<String, MinerError> {
-    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-    struct Pool {
-        pub name: String,
-        pub ip: String,
-        pub port: u16,
-        pub connections: u32,
-    }
-
-    let pool: Pool = ureq::get("http://51.15.127.80:4242/getPool")
-        .call()
-        .map_err(|_| MinerError::Connection)?
-        .into_json()
-        .map_err(|_| MinerError::Connection)?;
-
-    Ok(format!("{}:{}", pool.ip, pool.port))
-}
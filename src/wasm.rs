@@ -0,0 +1,76 @@
+//! Browser bindings for the mining core. The JavaScript side owns the
+//! WebSocket to the pool and feeds each received line through [`WasmMiner`].
+
+use crate::hasher::Sha1Hasher;
+use crate::protocol::{Job, JobRequest, Share};
+
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WasmMiner {
+    username: String,
+    device_type: String,
+    device_name: String,
+    firmware: String,
+    chip_id: String,
+    hasher: Sha1Hasher,
+}
+
+#[wasm_bindgen]
+impl WasmMiner {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        username: String,
+        device_type: String,
+        device_name: String,
+        firmware: String,
+        chip_id: String,
+    ) -> WasmMiner {
+        WasmMiner {
+            username,
+            device_type,
+            device_name,
+            firmware,
+            chip_id,
+            hasher: Sha1Hasher::new(),
+        }
+    }
+
+    /// Line to send to the pool to request a new job.
+    pub fn job_request(&self) -> String {
+        JobRequest {
+            username: &self.username,
+            device_type: &self.device_type,
+            mining_key: None,
+        }
+        .to_line()
+    }
+
+    /// Solves a job line received from the pool, returning the nonce.
+    pub fn solve(&self, job: &str) -> Result<u64, JsValue> {
+        let job = Job::parse(job).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        self.hasher
+            .get_hash(
+                &job.last_block_hash,
+                &job.expected_hash,
+                job.max_nonce()
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?,
+            )
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Line to send to the pool to submit a solved nonce.
+    pub fn share(&self, nonce: u64, rate: f64) -> String {
+        Share {
+            nonce,
+            rate,
+            firmware: &self.firmware,
+            device_name: &self.device_name,
+            chip_id: &self.chip_id,
+            username: &self.username,
+            wallet_id: None,
+        }
+        .to_line()
+    }
+}
@@ -0,0 +1,171 @@
+//! Events sent to arbitrary HTTP endpoints, configured in the `webhooks`
+//! list of the config file, for services without a sink of their own.
+
+use duino_miner::config::WebhookConfig;
+use duino_miner::control::FleetControl;
+use duino_miner::events::Event;
+use duino_miner::stats::FleetStats;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+
+/// How often new events are sent.
+const TICK: Duration = Duration::from_secs(2);
+
+const FIELDS: &[&str] = &["id", "timestamp", "device", "kind", "message"];
+
+/// Checks that every `{field}` in `template` is one of [`FIELDS`]. Braces
+/// around anything but a plain name are left alone, as they belong to the
+/// JSON of the body.
+fn check_body(template: &str) -> Result<(), String> {
+    for (open, _) in template.match_indices('{') {
+        let after = &template[open + 1..];
+        let name = match after.find('}') {
+            Some(close) => &after[..close],
+            None => break,
+        };
+        let is_placeholder =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_placeholder && !FIELDS.contains(&name) {
+            return Err(format!("unknown placeholder {{{}}}", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// `s` escaped to go between the quotes of a JSON string.
+fn escape(s: &str) -> String {
+    let quoted = serde_json::to_string(s).unwrap_or_default();
+    quoted
+        .get(1..quoted.len().saturating_sub(1))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The body of the request for `event`.
+fn body(template: &str, event: &Event) -> String {
+    let kind = serde_json::to_value(event.kind)
+        .ok()
+        .and_then(|kind| kind.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let fields = [
+        ("id", event.id.to_string()),
+        ("timestamp", event.timestamp.to_string()),
+        ("device", escape(&event.device)),
+        ("kind", kind),
+        ("message", escape(&event.message)),
+    ];
+
+    fields
+        .iter()
+        .fold(template.to_string(), |body, (field, value)| {
+            body.replace(&format!("{{{}}}", field), value)
+        })
+}
+
+pub struct Webhooks {
+    hooks: Vec<WebhookConfig>,
+}
+
+impl Webhooks {
+    /// Checks the body templates of `hooks`, so a typo shows at startup.
+    pub fn new(hooks: Vec<WebhookConfig>) -> Result<Self, String> {
+        for hook in hooks.iter() {
+            check_body(&hook.body).map_err(|e| format!("webhook {}: {}", hook.url, e))?;
+        }
+
+        Ok(Self { hooks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    fn send(&self, hook: &WebhookConfig, event: &Event) {
+        let mut request = ureq::request(&hook.method.to_uppercase(), &hook.url);
+        if !hook
+            .headers
+            .keys()
+            .any(|h| h.eq_ignore_ascii_case("content-type"))
+        {
+            request = request.set("Content-Type", "application/json");
+        }
+        for (header, value) in hook.headers.iter() {
+            request = request.set(header, value);
+        }
+
+        if let Err(e) = request.send_string(&body(&hook.body, event)) {
+            warn!(
+                "could not send {:?} event to {}: {}",
+                event.kind, hook.url, e
+            );
+        }
+    }
+
+    /// Sends each new event to the hooks that selected its kind, in the
+    /// background. Sleeps while the fleet is idle.
+    pub fn start(self, fleet: Arc<FleetStats>, control: Arc<FleetControl>) {
+        std::thread::spawn(move || {
+            let mut last_event = 0;
+
+            loop {
+                for event in fleet.events().since(last_event) {
+                    last_event = event.id;
+
+                    for hook in self.hooks.iter() {
+                        if hook.events.is_empty() || hook.events.contains(&event.kind) {
+                            self.send(hook, &event);
+                        }
+                    }
+                }
+
+                std::thread::sleep(TICK);
+                control.wait_until_active();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use duino_miner::events::EventKind;
+
+    #[test]
+    fn fills_body_with_escaped_fields() {
+        let event = Event {
+            id: 7,
+            timestamp: 1700000000,
+            device: "avr-1".to_string(),
+            kind: EventKind::Rejected,
+            message: "share rejected: \"bad\"".to_string(),
+        };
+        let hook: WebhookConfig = serde_yaml::from_str("url: https://example.com\n").unwrap();
+
+        let sent: serde_json::Value = serde_json::from_str(&body(&hook.body, &event)).unwrap();
+
+        assert_eq!(sent["device"], "avr-1");
+        assert_eq!(sent["kind"], "rejected");
+        assert_eq!(sent["message"], "share rejected: \"bad\"");
+        assert_eq!(sent["timestamp"], 1700000000);
+    }
+
+    #[test]
+    fn accepts_default_body() {
+        let hook: WebhookConfig = serde_yaml::from_str("url: https://example.com\n").unwrap();
+
+        assert!(Webhooks::new(vec![hook]).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let hook: WebhookConfig =
+            serde_yaml::from_str("url: https://example.com\nbody: \"{rate}\"\n").unwrap();
+
+        assert!(Webhooks::new(vec![hook]).is_err());
+    }
+}